@@ -4,9 +4,9 @@ use std::{
     ptr::NonNull,
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
 
-use super::{registers::RegisterGroups, reset::ResetClass, MCD_LIB};
+use super::{registers::RegisterGroups, reset::ResetClass, trace::TraceSource, MCD_LIB};
 
 use crate::{
     breakpoint::TriggerType,
@@ -50,7 +50,7 @@ impl<'a> Core<'a> {
         }
     }
 
-    pub fn get_reset_classes(&self) -> anyhow::Result<impl Iterator<Item = ResetClass>> {
+    pub fn get_reset_classes(&self) -> anyhow::Result<impl Iterator<Item = ResetClass<'_>>> {
         let mut reset_classes = 0;
         let result =
             unsafe { MCD_LIB.mcd_qry_rst_classes_f(self.core.as_ptr(), &mut reset_classes) };
@@ -96,7 +96,9 @@ impl<'a> Core<'a> {
         }
     }
 
-    fn query_payload_size(&self) -> u32 {
+    /// Returns the maximum number of bytes that can be transferred with a single
+    /// read or write.
+    pub fn query_payload_size(&self) -> u32 {
         if let Some(payload) = self.payload_size.get() {
             return payload;
         }
@@ -145,6 +147,67 @@ impl<'a> Core<'a> {
         Ok(buffer)
     }
 
+    /// Reads multiple memory regions, given by address and length, with a single
+    /// transaction list.
+    ///
+    /// Every region must fit into the maximum payload, see [Core::query_payload_size].
+    pub fn read_regions(&self, regions: &[(u64, usize)]) -> anyhow::Result<Vec<Vec<u8>>> {
+        let payload_length = self.query_payload_size();
+
+        if let Some((address, length)) = regions
+            .iter()
+            .find(|(_, length)| *length as u64 > payload_length as u64)
+        {
+            bail!(
+                "Cannot read {length} bytes at {address:#010X} in a single transaction, \
+                at most {payload_length} bytes are supported"
+            );
+        }
+
+        let mut buffers: Vec<Vec<u8>> =
+            regions.iter().map(|(_, length)| vec![0; *length]).collect();
+
+        let mut transactions: Vec<mcd_tx_st> = regions
+            .iter()
+            .zip(buffers.iter_mut())
+            .map(|((address, _), buffer)| create_transaction(*address, Type::Read, buffer))
+            .collect();
+
+        let mut transaction_list = mcd_txlist_st {
+            tx: transactions.as_mut_ptr(),
+            num_tx: transactions.len() as u32,
+            num_tx_ok: 0,
+        };
+        let result =
+            unsafe { MCD_LIB.mcd_execute_txlist_f(self.core.as_ptr(), &mut transaction_list) };
+        if result != 0 {
+            return Err(expect_error(Some(self)))
+                .with_context(|| "Internal MCD library eror while trying to read data");
+        }
+
+        // The library stops at the first transaction it could not complete, the
+        // remaining data is read one region at a time
+        let completed_bytes: Vec<usize> = transactions
+            .iter()
+            .map(|transaction| transaction.num_bytes_ok as usize)
+            .collect();
+        for (index, (address, length)) in regions
+            .iter()
+            .enumerate()
+            .skip(transaction_list.num_tx_ok as usize)
+        {
+            let completed = std::cmp::min(completed_bytes[index], *length);
+            if completed == *length {
+                continue;
+            }
+
+            let rest = self.read_bytes(address + completed as u64, length - completed)?;
+            buffers[index][completed..].copy_from_slice(&rest);
+        }
+
+        Ok(buffers)
+    }
+
     pub fn write(&self, mut address: u64, mut data: Vec<u8>) -> anyhow::Result<()> {
         let payload_length = self.query_payload_size();
         assert!(payload_length >= data.len() as u32);
@@ -188,6 +251,16 @@ impl<'a> Core<'a> {
         }
     }
 
+    /// Stops the core, other cores are not affected.
+    pub fn stop(&self) -> anyhow::Result<()> {
+        let result = unsafe { MCD_LIB.mcd_stop_f(self.core.as_ptr(), 0) };
+        if result != 0 {
+            Err(expect_error(Some(self))).with_context(|| "Internal library reported an error")
+        } else {
+            Ok(())
+        }
+    }
+
     pub fn step(&self) -> anyhow::Result<()> {
         let step_type = MCD_CORE_STEP_TYPE_INSTR as u32;
 
@@ -205,7 +278,7 @@ impl<'a> Core<'a> {
         trigger_type: TriggerType,
         address: u64,
         size: u64,
-    ) -> anyhow::Result<Trigger> {
+    ) -> anyhow::Result<Trigger<'_>> {
         let mut trigger = mcd_trig_simple_core_st::create_trigger(trigger_type, address, size);
         let mut trigger_id = 0;
 
@@ -246,9 +319,14 @@ impl<'a> Core<'a> {
         state.into()
     }
 
-    pub fn register_groups(&self) -> anyhow::Result<RegisterGroups> {
+    pub fn register_groups(&self) -> anyhow::Result<RegisterGroups<'_>> {
         RegisterGroups::from_core(self)
     }
+
+    /// Returns all trace sources available for this core.
+    pub fn traces(&self) -> anyhow::Result<Vec<TraceSource<'_>>> {
+        TraceSource::get_all(self)
+    }
 }
 
 #[derive(Debug)]
@@ -336,6 +414,14 @@ pub struct CoreInfo {
     info: String,
 }
 
+impl CoreInfo {
+    /// Returns the reason the core stopped as described by the debugger, it
+    /// is empty if the core is running or the debugger does not provide one.
+    pub fn stop_reason(&self) -> &str {
+        &self.stop_reason
+    }
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct CoreEvents {
@@ -361,7 +447,7 @@ impl From<mcd_core_event_et> for CoreEvents {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CoreState {
     Unknown,
     Running,
@@ -14,6 +14,7 @@ pub mod memory;
 pub mod registers;
 pub mod reset;
 pub mod system;
+pub mod trace;
 
 mod raw;
 mod transaction;
@@ -49,7 +49,7 @@ impl<'a> MemorySpace<'a> {
             .collect()
     }
 
-    pub fn get_blocks(&self) -> anyhow::Result<Vec<MemoryBlock>> {
+    pub fn get_blocks(&self) -> anyhow::Result<Vec<MemoryBlock<'_>>> {
         log::trace!("Querying for the number of memory spaces");
 
         let mut result_block_count = 0;
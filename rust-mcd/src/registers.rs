@@ -67,7 +67,7 @@ impl<'a> RegisterGroups<'a> {
     /// TODO Add/Reference documentation what the signifcance of index is. For
     /// a trivial setup with the Aurix Lite Kit v2 connected over MicroUSB, this
     /// must be 0.
-    pub fn get_group(&self, index: usize) -> anyhow::Result<RegisterGroup> {
+    pub fn get_group(&self, index: usize) -> anyhow::Result<RegisterGroup<'_>> {
         let register_group = &self.register_groups[index];
 
         let mut number_of_registers = Default::default();
@@ -14,12 +14,19 @@ pub struct ResetInfo {
     inner: mcd_rst_info_st,
 }
 
+impl ResetInfo {
+    /// Returns the description of the reset class as reported from the debug
+    /// controller, e.g. "System Reset".
+    pub fn description(&self) -> &str {
+        unsafe { CStr::from_ptr(&self.inner.info_str[0] as *const i8) }
+            .to_str()
+            .unwrap()
+    }
+}
+
 impl Display for ResetInfo {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let info = unsafe { CStr::from_ptr(&self.inner.info_str[0] as *const i8) }
-            .to_str()
-            .unwrap();
-        f.write_fmt(format_args!("Reset[{:?}]", info))
+        f.write_fmt(format_args!("Reset[{:?}]", self.description()))
     }
 }
 
@@ -60,6 +67,11 @@ impl<'a> ResetClass<'a> {
         }
     }
 
+    /// Returns the index of this class within the reset classes of the core.
+    pub fn class(&self) -> u8 {
+        self.bit_set
+    }
+
     pub fn get_info(&self) -> anyhow::Result<ResetInfo> {
         let mut output = mcd_rst_info_st::default();
         let result = unsafe {
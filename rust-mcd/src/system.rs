@@ -75,7 +75,7 @@ impl System {
     ///
     /// # Panic
     /// This method will panic if the index exceeds the number of cores available.
-    pub fn get_core(&self, core_index: usize) -> Result<Core, anyhow::Error> {
+    pub fn get_core(&self, core_index: usize) -> Result<Core<'_>, anyhow::Error> {
         // I observed that in certain circumstances opening a core can fail (mcd_open_core_f
         // returns 2), so we retry as suggested by the MCD library documentation
         const TRIES: usize = 5;
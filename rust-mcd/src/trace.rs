@@ -0,0 +1,461 @@
+//! Abstracts over trace sources of a [crate::core::Core].
+//!
+//! On AURIX devices the trace sources are provided by the MCDS (or miniMCDS)
+//! on-chip trace unit. Only the core trace data format ([TraceFrame]) is
+//! supported, event and statistic traces are not modelled.
+use std::{
+    borrow::Cow,
+    ffi::{c_void, CStr},
+    fmt::Debug,
+};
+
+use anyhow::Context;
+
+use crate::{
+    core::Core,
+    error::expect_error,
+    mcd_bindings::{
+        mcd_trace_data_core_st, mcd_trace_info_st, mcd_trace_state_st, MCD_TRACE_CYCLE_EXECUTE,
+        MCD_TRACE_CYCLE_FETCH, MCD_TRACE_CYCLE_NONE, MCD_TRACE_CYCLE_NOTEXECUTE,
+        MCD_TRACE_CYCLE_OWNERSHIP, MCD_TRACE_CYCLE_READ, MCD_TRACE_CYCLE_WRITE,
+        MCD_TRACE_FORMAT_CORE_EXECUTE, MCD_TRACE_FORMAT_CORE_FETCH,
+        MCD_TRACE_FORMAT_CORE_FLOW_BCOUNT, MCD_TRACE_FORMAT_CORE_FLOW_ICOUNT,
+        MCD_TRACE_FORMAT_CORE_FLOW_IPREDICATE, MCD_TRACE_MARKER_DEBUG, MCD_TRACE_MARKER_ERROR,
+        MCD_TRACE_MARKER_GAP, MCD_TRACE_MARKER_RUN, MCD_TRACE_MARKER_START, MCD_TRACE_MARKER_STOP,
+        MCD_TRACE_MODE_FIFO, MCD_TRACE_STATE_ARM, MCD_TRACE_STATE_DISABLE, MCD_TRACE_STATE_INIT,
+        MCD_TRACE_STATE_OFF, MCD_TRACE_STATE_STOP, MCD_TRACE_STATE_TRIGGER, MCD_TRACE_TYPE_BUS,
+        MCD_TRACE_TYPE_CORE, MCD_TRACE_TYPE_EVENT, MCD_TRACE_TYPE_STAT, TRUE,
+    },
+    MCD_LIB,
+};
+
+/// A trace source of a core as reported from the debug controller.
+pub struct TraceSource<'a> {
+    core: &'a Core<'a>,
+    inner: mcd_trace_info_st,
+}
+
+impl<'a> TraceSource<'a> {
+    /// Queries all trace sources that are available for the given core.
+    pub fn get_all(core: &'a Core<'a>) -> anyhow::Result<Vec<TraceSource<'a>>> {
+        let mut number_of_traces = 0;
+
+        let result = unsafe {
+            MCD_LIB.mcd_qry_traces_f(
+                core.core.as_ptr(),
+                0,
+                &mut number_of_traces,
+                core::ptr::null_mut(),
+            )
+        };
+
+        if result != 0 {
+            return Err(expect_error(Some(core)))
+                .with_context(|| "Unable to query the number of trace sources");
+        }
+
+        if number_of_traces == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut traces = vec![mcd_trace_info_st::default(); number_of_traces as usize];
+
+        let result = unsafe {
+            MCD_LIB.mcd_qry_traces_f(
+                core.core.as_ptr(),
+                0,
+                &mut number_of_traces,
+                traces.as_mut_ptr(),
+            )
+        };
+
+        if result != 0 {
+            return Err(expect_error(Some(core))).with_context(|| "Unable to query trace sources");
+        }
+
+        traces.truncate(number_of_traces as usize);
+
+        Ok(traces
+            .into_iter()
+            .map(|inner| TraceSource { core, inner })
+            .collect())
+    }
+
+    /// Returns the name of this trace source as reported from the debug
+    /// controller.
+    pub fn name(&self) -> Cow<'_, str> {
+        unsafe { CStr::from_ptr(&self.inner.trace_name[0] as *const i8) }.to_string_lossy()
+    }
+
+    /// Returns the kind of data this source traces.
+    pub fn trace_type(&self) -> TraceType {
+        TraceType::from_library_code(self.inner.trace_type)
+    }
+
+    /// Returns the format in which this source delivers trace frames.
+    pub fn format(&self) -> TraceFormat {
+        TraceFormat::from_library_code(self.inner.trace_format)
+    }
+
+    /// Returns true if this source records the program flow of the core, i.e.
+    /// it delivers [TraceFrame]s that can be decoded into an instruction history.
+    pub fn is_program_trace(&self) -> bool {
+        self.trace_type() == TraceType::Core && self.format().is_program_flow()
+    }
+
+    /// Queries the current state of this trace source.
+    pub fn state(&self) -> anyhow::Result<TraceState> {
+        let mut output = mcd_trace_state_st::default();
+        let result = unsafe {
+            MCD_LIB.mcd_qry_trace_state_f(self.core.core.as_ptr(), self.inner.trace_id, &mut output)
+        };
+
+        if result != 0 {
+            return Err(expect_error(Some(self.core)))
+                .with_context(|| format!("Cannot query state of trace {:?}", self.name()));
+        }
+
+        Ok(output.into())
+    }
+
+    fn set_state(&self, state: u32) -> anyhow::Result<TraceState> {
+        let mut request = mcd_trace_state_st {
+            state,
+            mode: MCD_TRACE_MODE_FIFO as u32,
+            // Let the trace follow the run state of the core, so it records while the
+            // core runs and stops as soon as it halts
+            autoarm: TRUE,
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            MCD_LIB.mcd_set_trace_state_f(
+                self.core.core.as_ptr(),
+                self.inner.trace_id,
+                &mut request,
+            )
+        };
+
+        if result != 0 {
+            return Err(expect_error(Some(self.core)))
+                .with_context(|| format!("Cannot set state of trace {:?}", self.name()));
+        }
+
+        if request.modified == TRUE {
+            log::debug!(
+                "Library modified the requested state of trace {:?}",
+                self.name()
+            );
+        }
+
+        Ok(request.into())
+    }
+
+    /// Clears the trace buffer and arms the trace in circular (FIFO) mode.
+    ///
+    /// The trace follows the run state of the core, so the buffer always contains
+    /// the most recent history once the core halts.
+    pub fn start(&self) -> anyhow::Result<TraceState> {
+        self.set_state(MCD_TRACE_STATE_INIT as u32)?;
+        self.set_state(MCD_TRACE_STATE_ARM as u32)
+    }
+
+    /// Stops recording, the buffer content is retained.
+    pub fn stop(&self) -> anyhow::Result<TraceState> {
+        self.set_state(MCD_TRACE_STATE_STOP as u32)
+    }
+
+    /// Reads up to `count` frames from the trace buffer, starting at frame
+    /// `start_index` where 0 refers to the oldest frame.
+    pub fn read_frames(&self, start_index: u64, count: u32) -> anyhow::Result<Vec<TraceFrame>> {
+        let mut frames = vec![mcd_trace_data_core_st::default(); count as usize];
+        let mut number_of_frames = count;
+
+        let result = unsafe {
+            MCD_LIB.mcd_read_trace_f(
+                self.core.core.as_ptr(),
+                self.inner.trace_id,
+                start_index,
+                &mut number_of_frames,
+                core::mem::size_of::<mcd_trace_data_core_st>() as u32,
+                frames.as_mut_ptr() as *mut c_void,
+            )
+        };
+
+        if result != 0 {
+            return Err(expect_error(Some(self.core)))
+                .with_context(|| format!("Cannot read frames from trace {:?}", self.name()));
+        }
+
+        frames.truncate(number_of_frames as usize);
+
+        Ok(frames.into_iter().map(TraceFrame::from).collect())
+    }
+
+    /// Reads the `count` most recent frames from the trace buffer, ordered from
+    /// oldest to newest.
+    pub fn read_latest_frames(&self, count: u32) -> anyhow::Result<Vec<TraceFrame>> {
+        let available = self.state()?.frames;
+        let count = std::cmp::min(available, count as u64) as u32;
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        self.read_frames(available - count as u64, count)
+    }
+}
+
+impl Debug for TraceSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceSource")
+            .field("trace_id", &self.inner.trace_id)
+            .field("trace_name", &self.name())
+            .field("trace_type", &self.trace_type())
+            .field("format", &self.format())
+            .field("shared", &(self.inner.trace_shared == TRUE))
+            .finish()
+    }
+}
+
+/// Rusty enumeration representing [crate::mcd_bindings::mcd_trace_type_et].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceType {
+    /// Program flow and/or data trace as seen from the core.
+    Core,
+    /// Trace of a bus.
+    Bus,
+    /// Logic and system event trace.
+    Event,
+    /// Statistics trace.
+    Statistic,
+    /// Unknown or user defined trace type.
+    Unknown(u32),
+}
+
+impl TraceType {
+    fn from_library_code(code: u32) -> Self {
+        match code as i32 {
+            MCD_TRACE_TYPE_CORE => Self::Core,
+            MCD_TRACE_TYPE_BUS => Self::Bus,
+            MCD_TRACE_TYPE_EVENT => Self::Event,
+            MCD_TRACE_TYPE_STAT => Self::Statistic,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+/// Rusty enumeration representing [crate::mcd_bindings::mcd_trace_format_et].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceFormat {
+    /// Execution trace extracted from bus fetch cycles.
+    CoreFetch,
+    /// Execution trace, one frame per executed instruction.
+    CoreExecute,
+    /// Program flow trace, the frame data holds an instruction count.
+    CoreFlowInstructionCount,
+    /// Program flow trace, the frame data holds a byte count.
+    CoreFlowByteCount,
+    /// Program flow trace with predicates, the frame data holds an instruction count.
+    CoreFlowInstructionPredicate,
+    /// Any other format, these are not decoded.
+    Other(u32),
+}
+
+impl TraceFormat {
+    fn from_library_code(code: u32) -> Self {
+        match code as i32 {
+            MCD_TRACE_FORMAT_CORE_FETCH => Self::CoreFetch,
+            MCD_TRACE_FORMAT_CORE_EXECUTE => Self::CoreExecute,
+            MCD_TRACE_FORMAT_CORE_FLOW_ICOUNT => Self::CoreFlowInstructionCount,
+            MCD_TRACE_FORMAT_CORE_FLOW_BCOUNT => Self::CoreFlowByteCount,
+            MCD_TRACE_FORMAT_CORE_FLOW_IPREDICATE => Self::CoreFlowInstructionPredicate,
+            _ => Self::Other(code),
+        }
+    }
+
+    /// Returns true if frames of this format describe executed program code.
+    pub fn is_program_flow(&self) -> bool {
+        !matches!(self, Self::Other(_))
+    }
+}
+
+/// Rusty enumeration representing [crate::mcd_bindings::mcd_trace_state_et].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceStateKind {
+    Disabled,
+    Off,
+    Armed,
+    Triggered,
+    Stopped,
+    Unknown(u32),
+}
+
+impl TraceStateKind {
+    fn from_library_code(code: u32) -> Self {
+        match code as i32 {
+            MCD_TRACE_STATE_DISABLE => Self::Disabled,
+            MCD_TRACE_STATE_OFF => Self::Off,
+            MCD_TRACE_STATE_ARM => Self::Armed,
+            MCD_TRACE_STATE_TRIGGER => Self::Triggered,
+            MCD_TRACE_STATE_STOP => Self::Stopped,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+/// The state of a [TraceSource].
+#[derive(Debug)]
+pub struct TraceState {
+    pub state: TraceStateKind,
+    /// Set if the buffer wrapped around, so the oldest frames were overwritten.
+    pub wraparound: bool,
+    /// Number of valid frames in the buffer.
+    pub frames: u64,
+    /// Maximum size of the trace buffer, in frames or bytes.
+    pub size: u64,
+}
+
+impl From<mcd_trace_state_st> for TraceState {
+    fn from(value: mcd_trace_state_st) -> Self {
+        TraceState {
+            state: TraceStateKind::from_library_code(value.state),
+            wraparound: value.wraparound == TRUE,
+            frames: value.frames,
+            size: value.size,
+        }
+    }
+}
+
+/// Rusty enumeration representing [crate::mcd_bindings::mcd_trace_cycle_et].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TraceCycle {
+    /// No trace cycle, only the control information (markers, timestamp) is valid.
+    None,
+    /// Execution of one instruction, or of a block ended by a taken branch for
+    /// program flow traces.
+    Execute,
+    /// Execution of a conditional instruction with a failing condition, or of
+    /// a block ended without a branch for program flow traces.
+    NotExecute,
+    Fetch,
+    Read,
+    Write,
+    /// Change of the executed software thread.
+    Ownership,
+    Unknown(u32),
+}
+
+impl TraceCycle {
+    fn from_library_code(code: u32) -> Self {
+        match code as i32 {
+            MCD_TRACE_CYCLE_NONE => Self::None,
+            MCD_TRACE_CYCLE_EXECUTE => Self::Execute,
+            MCD_TRACE_CYCLE_NOTEXECUTE => Self::NotExecute,
+            MCD_TRACE_CYCLE_FETCH => Self::Fetch,
+            MCD_TRACE_CYCLE_READ => Self::Read,
+            MCD_TRACE_CYCLE_WRITE => Self::Write,
+            MCD_TRACE_CYCLE_OWNERSHIP => Self::Ownership,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+/// Markers attached to a [TraceFrame], see [crate::mcd_bindings::mcd_trace_marker_et].
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct TraceMarkers {
+    /// The core started execution in this frame.
+    pub run: bool,
+    /// The core stopped execution in this frame.
+    pub debug: bool,
+    /// Tracing started in this frame.
+    pub start: bool,
+    /// Tracing stopped in this frame.
+    pub stop: bool,
+    /// The trace unit reported an error.
+    pub error: bool,
+    /// Frames were lost, e.g. due to bandwidth limitations.
+    pub gap: bool,
+}
+
+impl TraceMarkers {
+    fn from_library_code(code: u32) -> Self {
+        let code = code as i32;
+        TraceMarkers {
+            run: (code & MCD_TRACE_MARKER_RUN) != 0,
+            debug: (code & MCD_TRACE_MARKER_DEBUG) != 0,
+            start: (code & MCD_TRACE_MARKER_START) != 0,
+            stop: (code & MCD_TRACE_MARKER_STOP) != 0,
+            error: (code & MCD_TRACE_MARKER_ERROR) != 0,
+            gap: (code & MCD_TRACE_MARKER_GAP) != 0,
+        }
+    }
+}
+
+/// A raw trace frame in the core trace format, see [crate::mcd_bindings::mcd_trace_data_core_st].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TraceFrame {
+    /// Timestamp in picoseconds or clock cycles.
+    pub timestamp: u64,
+    pub markers: TraceMarkers,
+    pub cycle: TraceCycle,
+    pub address: u64,
+    /// Data of this cycle, this is the code length for program flow traces.
+    pub data: u64,
+}
+
+impl From<mcd_trace_data_core_st> for TraceFrame {
+    fn from(value: mcd_trace_data_core_st) -> Self {
+        TraceFrame {
+            timestamp: value.timestamp,
+            markers: TraceMarkers::from_library_code(value.marker),
+            cycle: TraceCycle::from_library_code(value.cycle),
+            address: value.addr.address,
+            data: value.data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mcd_bindings::mcd_addr_st;
+
+    #[test]
+    fn converts_raw_frame() {
+        let raw = mcd_trace_data_core_st {
+            timestamp: 1234,
+            marker: (MCD_TRACE_MARKER_GAP | MCD_TRACE_MARKER_RUN) as u32,
+            cycle: MCD_TRACE_CYCLE_NOTEXECUTE as u32,
+            addr: mcd_addr_st {
+                address: 0x8000_0100,
+                ..Default::default()
+            },
+            data: 4,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            TraceFrame::from(raw),
+            TraceFrame {
+                timestamp: 1234,
+                markers: TraceMarkers {
+                    run: true,
+                    gap: true,
+                    ..Default::default()
+                },
+                cycle: TraceCycle::NotExecute,
+                address: 0x8000_0100,
+                data: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_cycle() {
+        let raw = mcd_trace_data_core_st {
+            cycle: 0x7F00,
+            ..Default::default()
+        };
+
+        assert_eq!(TraceFrame::from(raw).cycle, TraceCycle::Unknown(0x7F00));
+    }
+}
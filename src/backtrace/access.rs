@@ -0,0 +1,104 @@
+//! Abstracts the registers and memory of a halted core, see [CoreAccess].
+use anyhow::Context;
+use rust_mcd::core::Core;
+
+/// Reads the state of a halted core, either from the device or from a core
+/// dump.
+pub trait CoreAccess {
+    /// Reads a register by the name the debugger reports, e.g. `PC` or `FCX`.
+    fn read_register(&self, name: &str) -> anyhow::Result<u32>;
+
+    /// Reads a block of memory as seen by the core.
+    fn read_memory(&self, address: u64, length: usize) -> anyhow::Result<Vec<u8>>;
+
+    /// Reads a register that the debugger may not provide, a failed read is
+    /// logged and returns [None].
+    fn read_register_opt(&self, name: &str) -> Option<u32> {
+        match self.read_register(name) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                log::debug!("Cannot read {name}: {error:#}");
+                None
+            }
+        }
+    }
+}
+
+impl CoreAccess for Core<'_> {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        let groups = self.register_groups()?;
+        let group = groups.get_group(0)?;
+        let register = group
+            .register(name)
+            .with_context(|| format!("Could not find {name} register for core"))?;
+        register
+            .read()
+            .with_context(|| format!("Cannot read {name} register"))
+    }
+
+    fn read_memory(&self, address: u64, length: usize) -> anyhow::Result<Vec<u8>> {
+        // A single read is limited by the payload size
+        let chunk_size = self.query_payload_size().max(1) as usize;
+
+        let mut bytes = Vec::with_capacity(length);
+        while bytes.len() < length {
+            let chunk_length = std::cmp::min(chunk_size, length - bytes.len());
+            let chunk = self
+                .read_bytes(address + bytes.len() as u64, chunk_length)
+                .with_context(|| format!("Cannot read memory at {address:#010X}"))?;
+            bytes.extend(chunk);
+        }
+        Ok(bytes)
+    }
+}
+
+/// A core whose registers and memory are given by a test.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockCore {
+    registers: std::collections::HashMap<String, u32>,
+    /// Blocks of memory by their start address.
+    memory: Vec<(u64, Vec<u8>)>,
+}
+
+#[cfg(test)]
+impl MockCore {
+    pub fn register(mut self, name: &str, value: u32) -> Self {
+        self.registers.insert(name.to_owned(), value);
+        self
+    }
+
+    pub fn memory(mut self, address: u64, data: &[u8]) -> Self {
+        self.memory.push((address, data.to_vec()));
+        self
+    }
+
+    /// Places little endian words at the address.
+    pub fn words(self, address: u64, words: &[u32]) -> Self {
+        let data: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+        self.memory(address, &data)
+    }
+}
+
+#[cfg(test)]
+impl CoreAccess for MockCore {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        self.registers
+            .get(name)
+            .copied()
+            .with_context(|| format!("{name} register is not mocked"))
+    }
+
+    fn read_memory(&self, address: u64, length: usize) -> anyhow::Result<Vec<u8>> {
+        self.memory
+            .iter()
+            .find(|(start, data)| {
+                address >= *start && address + length as u64 <= *start + data.len() as u64
+            })
+            .map(|(start, data)| {
+                let offset = (address - start) as usize;
+                data[offset..offset + length].to_vec()
+            })
+            .with_context(|| format!("Memory at {address:#010X} is not mocked"))
+    }
+}
@@ -2,9 +2,9 @@
 //!
 //! See also https://www.infineon.com/dgdl/tc1_6__architecture_vol1.pdf?fileId=db3a3043372d5cc801373b0f374d5d67#G8.6699641.
 
+use crate::backtrace::access::CoreAccess;
 use crate::backtrace::pcxi::PCXI;
 use anyhow::Context;
-use rust_mcd::core::Core;
 
 /// Represents a link word that points to a CSA.
 ///
@@ -22,7 +22,7 @@ impl ContextLinkWord {
         ((self.segment_address as u32) << 28) + ((self.context_offset as u32) << 6)
     }
 
-    pub(crate) fn load(&self, core: &Core) -> anyhow::Result<SavedContext> {
+    pub(crate) fn load(&self, core: &dyn CoreAccess) -> anyhow::Result<SavedContext> {
         log::trace!(
             "Loading stored context from {:#8X}",
             self.get_context_address()
@@ -30,7 +30,7 @@ impl ContextLinkWord {
         if self.is_upper {
             let mut upper = UpperContext::default();
             let bytes = core
-                .read_bytes(
+                .read_memory(
                     self.get_context_address() as u64,
                     core::mem::size_of::<UpperContext>(),
                 )
@@ -43,7 +43,7 @@ impl ContextLinkWord {
         } else {
             let mut lower = LowerContext::default();
             let bytes = core
-                .read_bytes(
+                .read_memory(
                     self.get_context_address() as u64,
                     core::mem::size_of::<LowerContext>(),
                 )
@@ -108,23 +108,71 @@ pub struct UpperContext {
     pub d15: u32,
 }
 
-#[derive(Debug, Default)]
+impl UpperContext {
+    /// Returns the saved registers by name in the order of the context,
+    /// except for PCXI.
+    pub fn registers(&self) -> [(&'static str, u32); 15] {
+        [
+            ("PSW", self.psw),
+            ("A10", self.a10),
+            ("A11", self.a11),
+            ("D8", self.d8),
+            ("D9", self.d9),
+            ("D10", self.d10),
+            ("D11", self.d11),
+            ("A12", self.a12),
+            ("A13", self.a13),
+            ("A14", self.a14),
+            ("A15", self.a15),
+            ("D12", self.d12),
+            ("D13", self.d13),
+            ("D14", self.d14),
+            ("D15", self.d15),
+        ]
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct LowerContext {
     pub pcxi: PCXI,
-    a11: u32,
-    a2: u32,
-    a3: u32,
-    d0: u32,
-    d1: u32,
-    d2: u32,
-    d3: u32,
-    a4: u32,
-    a5: u32,
-    a6: u32,
-    a7: u32,
-    d4: u32,
-    d5: u32,
-    d6: u32,
-    d7: u32,
+    pub a11: u32,
+    pub a2: u32,
+    pub a3: u32,
+    pub d0: u32,
+    pub d1: u32,
+    pub d2: u32,
+    pub d3: u32,
+    pub a4: u32,
+    pub a5: u32,
+    pub a6: u32,
+    pub a7: u32,
+    pub d4: u32,
+    pub d5: u32,
+    pub d6: u32,
+    pub d7: u32,
+}
+
+impl LowerContext {
+    /// Returns the saved registers by name in the order of the context,
+    /// except for PCXI.
+    pub fn registers(&self) -> [(&'static str, u32); 15] {
+        [
+            ("A11", self.a11),
+            ("A2", self.a2),
+            ("A3", self.a3),
+            ("D0", self.d0),
+            ("D1", self.d1),
+            ("D2", self.d2),
+            ("D3", self.d3),
+            ("A4", self.a4),
+            ("A5", self.a5),
+            ("A6", self.a6),
+            ("A7", self.a7),
+            ("D4", self.d4),
+            ("D5", self.d5),
+            ("D6", self.d6),
+            ("D7", self.d7),
+        ]
+    }
 }
@@ -0,0 +1,217 @@
+//! Reads the list of free CSAs, see [FreeContextList].
+use std::fmt::Display;
+
+use super::access::CoreAccess;
+
+use super::pcxi::PCXI;
+use super::region::{ChainCheck, ChainEnd, CsaRegions};
+
+/// The list of free CSAs at the time the core halted.
+///
+/// A context save takes the CSA at FCX. A free context list depletion trap
+/// (FCD) is raised once FCX reaches LCX, the contexts following LCX are the
+/// reserve for the trap handler. A save with an empty list raises a free
+/// context list underflow trap (FCU).
+#[derive(Debug, Clone)]
+pub struct FreeContextList {
+    /// Address of the first free CSA, [None] if the list is empty.
+    pub fcx: Option<u32>,
+    /// Address of the CSA at which the list is depleted.
+    pub lcx: Option<u32>,
+    /// Addresses of the free CSAs in the order they are used.
+    pub contexts: Vec<u32>,
+    /// The reason the walk over the list ended.
+    pub end: ChainEnd,
+}
+
+/// Returns the address of the CSA a link word points to.
+fn link_address(link: u32) -> Option<u32> {
+    // Free CSAs and the FCX and LCX registers use the link format of PCXI
+    // without the context type and interrupt fields
+    PCXI::from(link & 0xF_FFFF)
+        .get_context()
+        .map(|link| link.get_context_address())
+}
+
+impl FreeContextList {
+    /// Reads the FCX and LCX registers of the core and follows the list.
+    pub fn read(core: &dyn CoreAccess, regions: &CsaRegions) -> anyhow::Result<Self> {
+        let fcx = link_address(core.read_register("FCX")?);
+        let lcx = link_address(core.read_register("LCX")?);
+
+        let mut check = ChainCheck::new(regions);
+        let mut contexts = Vec::new();
+        let mut next = fcx;
+        let end = loop {
+            let Some(address) = next else {
+                break ChainEnd::Complete;
+            };
+            if let Err(end) = check.follow(address) {
+                break end;
+            }
+
+            match core.read_memory(address as u64, 4) {
+                Ok(bytes) => {
+                    contexts.push(address);
+                    next = link_address(u32::from_le_bytes(bytes[..4].try_into()?));
+                }
+                Err(error) => {
+                    break ChainEnd::ReadError {
+                        address,
+                        error: format!("{error:#}"),
+                    }
+                }
+            }
+        };
+
+        Ok(FreeContextList {
+            fcx,
+            lcx,
+            contexts,
+            end,
+        })
+    }
+
+    /// Returns the number of free CSAs before the list is depleted, [None]
+    /// if LCX is not part of the list.
+    pub fn until_depletion(&self) -> Option<usize> {
+        let lcx = self.lcx?;
+        self.contexts.iter().position(|address| *address == lcx)
+    }
+
+    /// Returns true if the next context save raises a trap or the reserve
+    /// after LCX is in use.
+    pub fn is_depleted(&self) -> bool {
+        self.fcx.is_none() || self.until_depletion() == Some(0)
+    }
+}
+
+impl Display for FreeContextList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(fcx) = self.fcx else {
+            return write!(
+                f,
+                "no free CSA left (FCX is null), a context save raises FCU"
+            );
+        };
+
+        write!(f, "{} free CSAs from FCX {fcx:#010X}", self.contexts.len())?;
+        match (self.lcx, self.until_depletion()) {
+            (Some(lcx), Some(0)) => write!(
+                f,
+                ", depleted: FCX reached LCX {lcx:#010X}, only the reserve is left"
+            )?,
+            (Some(lcx), Some(count)) => write!(f, ", {count} until LCX {lcx:#010X}")?,
+            (Some(lcx), None) if self.end.is_complete() => {
+                write!(f, ", LCX {lcx:#010X} is not in the list")?
+            }
+            (Some(lcx), None) => write!(f, ", LCX {lcx:#010X} not reached")?,
+            (None, _) => write!(f, ", LCX is null")?,
+        }
+
+        if !self.end.is_complete() {
+            write!(f, ", list ends early: {}", self.end)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrace::access::MockCore;
+
+    /// Returns the link word to the CSA at the given offset of segment 7.
+    fn link(offset: u32) -> u32 {
+        (7 << 16) | offset
+    }
+
+    fn address(offset: u32) -> u32 {
+        0x7000_0000 + (offset << 6)
+    }
+
+    /// Builds a core whose free list links the CSAs at the given offsets.
+    fn free_list(fcx: u32, lcx: u32, links: &[(u32, u32)]) -> MockCore {
+        links.iter().fold(
+            MockCore::default()
+                .register("FCX", fcx)
+                .register("LCX", lcx),
+            |core, (offset, next)| core.words(address(*offset) as u64, &[*next]),
+        )
+    }
+
+    #[test]
+    fn counts_contexts_until_depletion() {
+        let core = free_list(
+            link(1),
+            link(3),
+            &[(1, link(2)), (2, link(3)), (3, link(4)), (4, 0)],
+        );
+        let list = FreeContextList::read(&core, &CsaRegions::default()).unwrap();
+        assert_eq!(
+            list.contexts,
+            vec![address(1), address(2), address(3), address(4)]
+        );
+        assert_eq!(list.end, ChainEnd::Complete);
+        assert_eq!(list.until_depletion(), Some(2));
+        assert!(!list.is_depleted());
+        assert_eq!(
+            list.to_string(),
+            "4 free CSAs from FCX 0x70000040, 2 until LCX 0x700000C0"
+        );
+    }
+
+    #[test]
+    fn detects_depletion() {
+        // FCX reached LCX, only the reserve is left
+        let core = free_list(link(3), link(3), &[(3, link(4)), (4, 0)]);
+        let list = FreeContextList::read(&core, &CsaRegions::default()).unwrap();
+        assert_eq!(list.until_depletion(), Some(0));
+        assert!(list.is_depleted());
+
+        let core = free_list(0, link(3), &[]);
+        let list = FreeContextList::read(&core, &CsaRegions::default()).unwrap();
+        assert!(list.contexts.is_empty());
+        assert!(list.is_depleted());
+        assert_eq!(
+            list.to_string(),
+            "no free CSA left (FCX is null), a context save raises FCU"
+        );
+    }
+
+    #[test]
+    fn ends_corrupted_lists() {
+        let core = free_list(link(1), link(5), &[(1, link(2)), (2, link(1))]);
+        let list = FreeContextList::read(&core, &CsaRegions::default()).unwrap();
+        assert_eq!(list.contexts, vec![address(1), address(2)]);
+        assert_eq!(
+            list.end,
+            ChainEnd::Cycle {
+                address: address(1)
+            }
+        );
+        assert_eq!(list.until_depletion(), None);
+        assert_eq!(
+            list.to_string(),
+            "2 free CSAs from FCX 0x70000040, LCX 0x70000140 not reached, \
+             list ends early: link to 0x70000040 forms a cycle"
+        );
+
+        let regions = CsaRegions::from_bounds(&[(address(0), address(4))]);
+        let core = free_list(link(1), link(2), &[(1, link(8))]);
+        let list = FreeContextList::read(&core, &regions).unwrap();
+        assert_eq!(list.contexts, vec![address(1)]);
+        assert_eq!(
+            list.end,
+            ChainEnd::OutOfRegion {
+                address: address(8)
+            }
+        );
+
+        // The second CSA cannot be read
+        let core = free_list(link(1), link(2), &[(1, link(2))]);
+        let list = FreeContextList::read(&core, &CsaRegions::default()).unwrap();
+        assert_eq!(list.contexts, vec![address(1)]);
+        assert!(matches!(list.end, ChainEnd::ReadError { address: a, .. } if a == address(2)));
+    }
+}
@@ -0,0 +1,1684 @@
+//! Recovers the arguments and local variables of frames from the DWARF debug
+//! information, see [DebugInfo].
+use std::{fmt::Display, path::Path, rc::Rc};
+
+use super::access::CoreAccess;
+use super::panic::PanicMessage;
+use anyhow::{bail, Context};
+use elf::{endian::AnyEndian, ElfBytes};
+use gimli::{
+    AttributeValue, BaseAddresses, CfaRule, DebugFrame, DebuggingInformationEntry, Dwarf,
+    EndianRcSlice, EntriesTreeNode, EvaluationResult, Expression, Location, Piece, Reader,
+    RunTimeEndian, Unit, UnitOffset, UnwindContext, UnwindSection, Value,
+};
+
+type R = EndianRcSlice<RunTimeEndian>;
+
+/// Number of bytes of a variable read at most, larger values are truncated.
+const MAX_VALUE_SIZE: u64 = 64;
+
+/// Number of bytes of a string of the panic message read at most.
+const MAX_STRING_SIZE: u64 = 256;
+
+/// Nesting depth up to which members of structures and arrays are printed.
+const MAX_DEPTH: usize = 2;
+
+/// Number of members or elements printed per structure or array.
+const MAX_ELEMENTS: usize = 8;
+
+/// An argument or local variable of a frame.
+#[derive(Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    pub type_name: String,
+    /// Set for the arguments of the function of the frame.
+    pub argument: bool,
+    /// The formatted value, or the reason it is not available in angle
+    /// brackets, e.g. `<optimized out>`.
+    pub value: String,
+}
+
+impl Display for Variable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} = {}", self.name, self.type_name, self.value)
+    }
+}
+
+/// The registers of a frame indexed by their DWARF number, i.e. D0 to D15
+/// followed by A0 to A15.
+#[derive(Debug, Clone, Default)]
+pub struct FrameRegisters {
+    values: [Option<u32>; 32],
+}
+
+impl FrameRegisters {
+    /// Sets the registers by name, names other than data and address
+    /// registers are ignored.
+    pub fn extend<'a, I: IntoIterator<Item = (&'a str, u32)>>(&mut self, registers: I) {
+        for (name, value) in registers {
+            let (offset, index) = match name.split_at(1) {
+                ("D", index) => (0, index),
+                ("A", index) => (16, index),
+                _ => continue,
+            };
+            if let Ok(index @ 0..=15) = index.parse::<usize>() {
+                self.values[offset + index] = Some(value);
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, register: u16) -> anyhow::Result<u32> {
+        let value = self.values.get(register as usize).copied().flatten();
+        value.with_context(|| {
+            let name = match register {
+                0..=15 => format!("D{register}"),
+                16..=31 => format!("A{}", register - 16),
+                _ => format!("register {register}"),
+            };
+            format!("{name} not saved")
+        })
+    }
+}
+
+/// The state needed to evaluate the location of a variable in a frame.
+struct Scope<'a> {
+    unit: &'a Unit<R>,
+    core: &'a dyn CoreAccess,
+    pc: u64,
+    registers: &'a FrameRegisters,
+    frame_base: Option<u64>,
+    call_frame_cfa: Option<u64>,
+}
+
+/// The DWARF debug information of the elf file.
+pub struct DebugInfo {
+    dwarf: Dwarf<R>,
+    units: Vec<Unit<R>>,
+    debug_frame: DebugFrame<R>,
+}
+
+impl DebugInfo {
+    pub fn load(elf_file: &Path) -> anyhow::Result<Self> {
+        let elf_data = std::fs::read(elf_file).context("Cannot read elf file")?;
+        let elf =
+            ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).context("Cannot parse elf file")?;
+        let endian = match elf.ehdr.endianness {
+            AnyEndian::Little => RunTimeEndian::Little,
+            AnyEndian::Big => RunTimeEndian::Big,
+        };
+
+        Self::parse(|name| {
+            let data = match elf.section_header_by_name(name)? {
+                Some(header) => elf.section_data(&header)?.0,
+                None => &[],
+            };
+            Ok(EndianRcSlice::new(Rc::from(data), endian))
+        })
+    }
+
+    /// Parses the debug information from the sections given by name.
+    fn parse(section: impl Fn(&str) -> anyhow::Result<R>) -> anyhow::Result<Self> {
+        let dwarf = Dwarf::load(|id| section(id.name()))?;
+        let mut debug_frame = DebugFrame::from(section(".debug_frame")?);
+        debug_frame.set_address_size(4);
+
+        let mut units = Vec::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next()? {
+            units.push(dwarf.unit(header)?);
+        }
+        if units.is_empty() {
+            bail!("Elf file does not contain debug information");
+        }
+
+        Ok(DebugInfo {
+            dwarf,
+            units,
+            debug_frame,
+        })
+    }
+
+    /// Returns the arguments and local variables in scope at the address,
+    /// using the registers of the frame and the memory of the core.
+    pub fn frame_variables(
+        &self,
+        core: &dyn CoreAccess,
+        address: u32,
+        registers: &FrameRegisters,
+    ) -> anyhow::Result<Vec<Variable>> {
+        let pc = address as u64;
+        let (unit, function) = self.function_at(pc)?;
+        let mut tree = unit.entries_tree(Some(function))?;
+        let function = tree.root()?;
+        let scope = self.scope(unit, core, pc, registers, function.entry());
+
+        let mut variables = Vec::new();
+        self.scope_variables(&scope, function, true, &mut variables)?;
+        Ok(variables)
+    }
+
+    /// Reads the panic message if the function of the frame is a panic
+    /// handler, i.e. takes a `&PanicInfo` argument.
+    pub fn panic_message(
+        &self,
+        core: &dyn CoreAccess,
+        address: u32,
+        registers: &FrameRegisters,
+    ) -> anyhow::Result<Option<PanicMessage>> {
+        let pc = address as u64;
+        let (unit, function) = self.function_at(pc)?;
+        let mut tree = unit.entries_tree(Some(function))?;
+        let function = tree.root()?;
+        let scope = self.scope(unit, core, pc, registers, function.entry());
+
+        let mut children = function.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() != gimli::DW_TAG_formal_parameter {
+                continue;
+            }
+            let Some(info_type) = self.pointee(unit, self.type_of(unit, entry)?)? else {
+                continue;
+            };
+            if !self
+                .type_name(unit, Some(info_type))?
+                .ends_with("PanicInfo")
+            {
+                continue;
+            }
+
+            // Handlers such as panic-halt do not use the argument, it is
+            // still a panic even though the message is lost
+            let info = match self.variable_bytes(&scope, entry, 4) {
+                Ok(Some(bytes)) => to_u64(&bytes),
+                Ok(None) => return Ok(Some(PanicMessage::default())),
+                Err(error) => {
+                    log::debug!("Cannot read PanicInfo: {error:#}");
+                    return Ok(Some(PanicMessage::default()));
+                }
+            };
+            return Ok(Some(self.read_panic_info(&scope, info, info_type)?));
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the unit and subprogram containing the address.
+    fn function_at(&self, pc: u64) -> anyhow::Result<(&Unit<R>, UnitOffset)> {
+        for unit in self.units.iter() {
+            if !self.unit_contains(unit, pc)? {
+                continue;
+            }
+
+            let mut tree = unit.entries_tree(None)?;
+            if let Some(function) = self.find_function(unit, tree.root()?, pc)? {
+                return Ok((unit, function));
+            }
+        }
+
+        bail!("No debug information for {pc:#010X}")
+    }
+
+    fn scope<'a>(
+        &self,
+        unit: &'a Unit<R>,
+        core: &'a dyn CoreAccess,
+        pc: u64,
+        registers: &'a FrameRegisters,
+        function: &DebuggingInformationEntry<R>,
+    ) -> Scope<'a> {
+        let mut scope = Scope {
+            unit,
+            core,
+            pc,
+            registers,
+            frame_base: None,
+            call_frame_cfa: self.call_frame_cfa(pc, registers),
+        };
+        scope.frame_base = self.frame_base(&scope, function);
+        scope
+    }
+
+    fn read_panic_info(
+        &self,
+        scope: &Scope,
+        address: u64,
+        info_type: UnitOffset,
+    ) -> anyhow::Result<PanicMessage> {
+        let unit = scope.unit;
+        let mut panic = PanicMessage::default();
+
+        if let Some((offset, location_type)) = self.member(unit, info_type, "location")? {
+            let location = read_u32(scope.core, address + offset)? as u64;
+            if let Some(location_type) = self.pointee(unit, location_type)? {
+                for name in ["file", "filename"] {
+                    if let Some((offset, _)) = self.member(unit, location_type, name)? {
+                        panic.file = Some(read_str(scope.core, location + offset)?);
+                    }
+                }
+                if let Some((offset, _)) = self.member(unit, location_type, "line")? {
+                    panic.line = Some(read_u32(scope.core, location + offset)?);
+                }
+                if let Some((offset, _)) = self.member(unit, location_type, "col")? {
+                    panic.column = Some(read_u32(scope.core, location + offset)?);
+                }
+            }
+        }
+
+        // The message is either a reference or an optional reference to the
+        // arguments, both are represented by a pointer
+        if let Some((offset, message_type)) = self.member(unit, info_type, "message")? {
+            let arguments = read_u32(scope.core, address + offset)? as u64;
+            let arguments_type = match self.pointee(unit, message_type)? {
+                Some(arguments_type) => Some(arguments_type),
+                None => self.find_struct(unit, "Arguments", "pieces")?,
+            };
+            if let (true, Some(arguments_type)) = (arguments != 0, arguments_type) {
+                // The layout of the arguments changes between Rust versions,
+                // the location is still shown if it is not understood
+                match self.read_arguments(scope, arguments, arguments_type) {
+                    Ok(message) => panic.message = Some(message),
+                    Err(error) => log::debug!("Cannot read the panic message: {error:#}"),
+                }
+            }
+        }
+
+        Ok(panic)
+    }
+
+    /// Reads the static pieces of `fmt::Arguments`, the arguments in between
+    /// are replaced by `{..}`.
+    fn read_arguments(
+        &self,
+        scope: &Scope,
+        address: u64,
+        arguments_type: UnitOffset,
+    ) -> anyhow::Result<String> {
+        let unit = scope.unit;
+        let Some((offset, _)) = self.member(unit, arguments_type, "pieces")? else {
+            bail!("unsupported layout of fmt::Arguments");
+        };
+        let (pieces, count) = read_fat_pointer(scope.core, address + offset)?;
+
+        let arguments = match self.member(unit, arguments_type, "args")? {
+            Some((offset, _)) => read_fat_pointer(scope.core, address + offset)?.1,
+            None => 0,
+        };
+
+        let mut message = String::new();
+        for index in 0..count.min(MAX_ELEMENTS as u64) {
+            message.push_str(&read_str(scope.core, pieces + index * 8)?);
+            if index < arguments {
+                message.push_str("{..}");
+            }
+        }
+        for _ in count..arguments {
+            message.push_str("{..}");
+        }
+        Ok(message)
+    }
+
+    /// Returns the offset and type of a member of a structure.
+    fn member(
+        &self,
+        unit: &Unit<R>,
+        structure: UnitOffset,
+        name: &str,
+    ) -> anyhow::Result<Option<(u64, Option<UnitOffset>)>> {
+        let mut tree = unit.entries_tree(Some(structure))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            let member = child.entry();
+            if member.tag() == gimli::DW_TAG_member
+                && self.name(unit, member)?.as_deref() == Some(name)
+            {
+                let offset = self.member_offset(unit, member)?.unwrap_or(0);
+                return Ok(Some((offset, self.type_of(unit, member)?)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Finds a structure by name that has the given member.
+    fn find_struct(
+        &self,
+        unit: &Unit<R>,
+        name: &str,
+        member: &str,
+    ) -> anyhow::Result<Option<UnitOffset>> {
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs()? {
+            if entry.tag() == gimli::DW_TAG_structure_type
+                && self.name(unit, entry)?.as_deref() == Some(name)
+                && self.member(unit, entry.offset(), member)?.is_some()
+            {
+                return Ok(Some(entry.offset()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns the type a pointer or reference type points to.
+    fn pointee(
+        &self,
+        unit: &Unit<R>,
+        offset: Option<UnitOffset>,
+    ) -> anyhow::Result<Option<UnitOffset>> {
+        let Some(offset) = offset else {
+            return Ok(None);
+        };
+        let entry = unit.entry(offset)?;
+        match entry.tag() {
+            gimli::DW_TAG_typedef | gimli::DW_TAG_const_type | gimli::DW_TAG_volatile_type => {
+                self.pointee(unit, self.type_of(unit, &entry)?)
+            }
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => self.type_of(unit, &entry),
+            _ => Ok(None),
+        }
+    }
+
+    fn unit_contains(&self, unit: &Unit<R>, pc: u64) -> anyhow::Result<bool> {
+        let mut ranges = self.dwarf.unit_ranges(unit)?;
+        while let Some(range) = ranges.next()? {
+            if (range.begin..range.end).contains(&pc) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn contains(
+        &self,
+        unit: &Unit<R>,
+        entry: &DebuggingInformationEntry<R>,
+        pc: u64,
+    ) -> anyhow::Result<bool> {
+        let mut ranges = self.dwarf.die_ranges(unit, entry)?;
+        while let Some(range) = ranges.next()? {
+            if (range.begin..range.end).contains(&pc) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns the subprogram containing the address, searching through
+    /// namespaces and types that hold the functions of Rust programs.
+    fn find_function(
+        &self,
+        unit: &Unit<R>,
+        node: EntriesTreeNode<R>,
+        pc: u64,
+    ) -> anyhow::Result<Option<UnitOffset>> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() == gimli::DW_TAG_subprogram {
+                if self.contains(unit, entry, pc)? {
+                    return Ok(Some(entry.offset()));
+                }
+            } else if let Some(function) = self.find_function(unit, child, pc)? {
+                return Ok(Some(function));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Collects the variables of a function or a nested scope containing the
+    /// address.
+    fn scope_variables(
+        &self,
+        scope: &Scope,
+        node: EntriesTreeNode<R>,
+        function: bool,
+        variables: &mut Vec<Variable>,
+    ) -> anyhow::Result<()> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_formal_parameter | gimli::DW_TAG_variable => {
+                    let argument = function && entry.tag() == gimli::DW_TAG_formal_parameter;
+                    if let Some(variable) = self.variable(scope, entry, argument)? {
+                        variables.push(variable);
+                    }
+                }
+                gimli::DW_TAG_lexical_block | gimli::DW_TAG_inlined_subroutine
+                    if self.contains(scope.unit, entry, scope.pc)? =>
+                {
+                    self.scope_variables(scope, child, false, variables)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the attribute of the entry or of the entry it was derived
+    /// from, e.g. the name of an inlined argument.
+    fn attr(
+        &self,
+        unit: &Unit<R>,
+        entry: &DebuggingInformationEntry<R>,
+        name: gimli::DwAt,
+    ) -> anyhow::Result<Option<AttributeValue<R>>> {
+        if let Some(value) = entry.attr_value(name)? {
+            return Ok(Some(value));
+        }
+        for origin in [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification] {
+            if let Some(AttributeValue::UnitRef(offset)) = entry.attr_value(origin)? {
+                return self.attr(unit, &unit.entry(offset)?, name);
+            }
+        }
+        Ok(None)
+    }
+
+    fn name(
+        &self,
+        unit: &Unit<R>,
+        entry: &DebuggingInformationEntry<R>,
+    ) -> anyhow::Result<Option<String>> {
+        match self.attr(unit, entry, gimli::DW_AT_name)? {
+            Some(name) => Ok(Some(
+                self.dwarf
+                    .attr_string(unit, name)?
+                    .to_string_lossy()?
+                    .into_owned(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn type_of(
+        &self,
+        unit: &Unit<R>,
+        entry: &DebuggingInformationEntry<R>,
+    ) -> anyhow::Result<Option<UnitOffset>> {
+        match self.attr(unit, entry, gimli::DW_AT_type)? {
+            Some(AttributeValue::UnitRef(offset)) => Ok(Some(offset)),
+            _ => Ok(None),
+        }
+    }
+
+    fn variable(
+        &self,
+        scope: &Scope,
+        entry: &DebuggingInformationEntry<R>,
+        argument: bool,
+    ) -> anyhow::Result<Option<Variable>> {
+        let unit = scope.unit;
+        let Some(name) = self.name(unit, entry)? else {
+            return Ok(None);
+        };
+        let type_offset = self.type_of(unit, entry)?;
+
+        let value = match self.read_variable(scope, entry, type_offset) {
+            Ok(value) => value,
+            Err(error) => format!("<{error:#}>"),
+        };
+
+        Ok(Some(Variable {
+            name,
+            type_name: self.type_name(unit, type_offset)?,
+            argument,
+            value,
+        }))
+    }
+
+    fn read_variable(
+        &self,
+        scope: &Scope,
+        entry: &DebuggingInformationEntry<R>,
+        type_offset: Option<UnitOffset>,
+    ) -> anyhow::Result<String> {
+        let unit = scope.unit;
+        let size = self
+            .type_size(unit, type_offset)?
+            .unwrap_or(4)
+            .min(MAX_VALUE_SIZE);
+
+        match self.variable_bytes(scope, entry, size)? {
+            Some(bytes) => self.format_value(unit, type_offset, &bytes, 0),
+            None => Ok("<optimized out>".to_owned()),
+        }
+    }
+
+    /// Reads the value of a variable, [None] if it is optimized out.
+    fn variable_bytes(
+        &self,
+        scope: &Scope,
+        entry: &DebuggingInformationEntry<R>,
+        size: u64,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let unit = scope.unit;
+        if let Some(constant) = entry.attr_value(gimli::DW_AT_const_value)? {
+            let bytes = match constant {
+                AttributeValue::Block(block) => block.to_slice()?.to_vec(),
+                AttributeValue::Sdata(value) => value.to_le_bytes().to_vec(),
+                value => match value.udata_value() {
+                    Some(value) => value.to_le_bytes().to_vec(),
+                    None => bail!("unsupported constant"),
+                },
+            };
+            return Ok(Some(bytes));
+        }
+
+        let expression = match entry.attr_value(gimli::DW_AT_location)? {
+            Some(AttributeValue::Exprloc(expression)) => expression,
+            Some(value) => {
+                let Some(mut locations) = self.dwarf.attr_locations(unit, value)? else {
+                    return Ok(None);
+                };
+                let mut found = None;
+                while let Some(location) = locations.next()? {
+                    if (location.range.begin..location.range.end).contains(&scope.pc) {
+                        found = Some(location.data);
+                        break;
+                    }
+                }
+                match found {
+                    Some(expression) => expression,
+                    None => return Ok(None),
+                }
+            }
+            None => return Ok(None),
+        };
+
+        let pieces = self.evaluate(scope, expression)?;
+        Ok(Some(read_pieces(
+            scope.core,
+            scope.registers,
+            &pieces,
+            size,
+        )?))
+    }
+
+    /// Evaluates a location expression with the registers and memory of the
+    /// frame.
+    fn evaluate(&self, scope: &Scope, expression: Expression<R>) -> anyhow::Result<Vec<Piece<R>>> {
+        let mut evaluation = expression.evaluation(scope.unit.encoding());
+        let mut result = evaluation.evaluate()?;
+        loop {
+            result = match result {
+                EvaluationResult::Complete => break,
+                EvaluationResult::RequiresMemory { address, size, .. } => {
+                    let bytes = read_memory(scope.core, address, size as u64)?;
+                    let mut word = [0u8; 8];
+                    word[..bytes.len()].copy_from_slice(&bytes);
+                    evaluation.resume_with_memory(Value::Generic(u64::from_le_bytes(word)))?
+                }
+                EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = scope.registers.get(register.0)?;
+                    evaluation.resume_with_register(Value::Generic(value as u64))?
+                }
+                EvaluationResult::RequiresFrameBase => {
+                    let frame_base = scope.frame_base.context("frame base unknown")?;
+                    evaluation.resume_with_frame_base(frame_base)?
+                }
+                EvaluationResult::RequiresCallFrameCfa => {
+                    let cfa = scope.call_frame_cfa.context("call frame unknown")?;
+                    evaluation.resume_with_call_frame_cfa(cfa)?
+                }
+                EvaluationResult::RequiresRelocatedAddress(address) => {
+                    evaluation.resume_with_relocated_address(address)?
+                }
+                EvaluationResult::RequiresIndexedAddress { index, .. } => {
+                    let address = self.dwarf.address(scope.unit, index)?;
+                    evaluation.resume_with_indexed_address(address)?
+                }
+                _ => bail!("unsupported location"),
+            };
+        }
+        Ok(evaluation.result())
+    }
+
+    /// Evaluates the frame base of a function, [None] if it cannot be
+    /// determined, in which case variables relative to it are unavailable.
+    fn frame_base(&self, scope: &Scope, function: &DebuggingInformationEntry<R>) -> Option<u64> {
+        let Ok(Some(AttributeValue::Exprloc(expression))) =
+            function.attr_value(gimli::DW_AT_frame_base)
+        else {
+            return None;
+        };
+
+        match self.evaluate(scope, expression) {
+            Ok(pieces) => match pieces.first().map(|piece| &piece.location) {
+                Some(Location::Register { register }) => {
+                    scope.registers.get(register.0).ok().map(u64::from)
+                }
+                Some(Location::Address { address }) => Some(*address),
+                _ => None,
+            },
+            Err(error) => {
+                log::debug!("Cannot evaluate frame base: {error:#}");
+                None
+            }
+        }
+    }
+
+    /// Computes the canonical frame address from the call frame information.
+    fn call_frame_cfa(&self, pc: u64, registers: &FrameRegisters) -> Option<u64> {
+        let mut context = UnwindContext::new();
+        let row = self
+            .debug_frame
+            .unwind_info_for_address(
+                &BaseAddresses::default(),
+                &mut context,
+                pc,
+                |section, bases, offset| section.cie_from_offset(bases, offset),
+            )
+            .ok()?;
+
+        match row.cfa() {
+            CfaRule::RegisterAndOffset { register, offset } => {
+                let value = registers.get(register.0).ok()?;
+                Some((value as i64 + offset) as u64)
+            }
+            _ => None,
+        }
+    }
+
+    fn type_size(&self, unit: &Unit<R>, offset: Option<UnitOffset>) -> anyhow::Result<Option<u64>> {
+        let Some(offset) = offset else {
+            return Ok(None);
+        };
+        let entry = unit.entry(offset)?;
+        if let Some(size) = entry
+            .attr_value(gimli::DW_AT_byte_size)?
+            .and_then(|size| size.udata_value())
+        {
+            return Ok(Some(size));
+        }
+
+        match entry.tag() {
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type => self.type_size(unit, self.type_of(unit, &entry)?),
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => Ok(Some(unit.encoding().address_size as u64)),
+            gimli::DW_TAG_array_type => {
+                let element = self.type_size(unit, self.type_of(unit, &entry)?)?;
+                let count = self.array_length(unit, offset)?;
+                Ok(element.zip(count).map(|(size, count)| size * count))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn array_length(&self, unit: &Unit<R>, offset: UnitOffset) -> anyhow::Result<Option<u64>> {
+        let mut tree = unit.entries_tree(Some(offset))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            if entry.tag() != gimli::DW_TAG_subrange_type {
+                continue;
+            }
+            if let Some(count) = entry
+                .attr_value(gimli::DW_AT_count)?
+                .and_then(|count| count.udata_value())
+            {
+                return Ok(Some(count));
+            }
+            if let Some(bound) = entry
+                .attr_value(gimli::DW_AT_upper_bound)?
+                .and_then(|bound| bound.udata_value())
+            {
+                return Ok(Some(bound + 1));
+            }
+        }
+        Ok(None)
+    }
+
+    fn type_name(&self, unit: &Unit<R>, offset: Option<UnitOffset>) -> anyhow::Result<String> {
+        let Some(offset) = offset else {
+            return Ok("void".to_owned());
+        };
+        let entry = unit.entry(offset)?;
+        if let Some(name) = self.name(unit, &entry)? {
+            return Ok(name);
+        }
+
+        let inner = self.type_of(unit, &entry)?;
+        Ok(match entry.tag() {
+            gimli::DW_TAG_pointer_type => format!("*{}", self.type_name(unit, inner)?),
+            gimli::DW_TAG_reference_type | gimli::DW_TAG_rvalue_reference_type => {
+                format!("&{}", self.type_name(unit, inner)?)
+            }
+            gimli::DW_TAG_const_type => format!("const {}", self.type_name(unit, inner)?),
+            gimli::DW_TAG_volatile_type => format!("volatile {}", self.type_name(unit, inner)?),
+            gimli::DW_TAG_array_type => match self.array_length(unit, offset)? {
+                Some(count) => format!("[{}; {count}]", self.type_name(unit, inner)?),
+                None => format!("[{}]", self.type_name(unit, inner)?),
+            },
+            _ => "?".to_owned(),
+        })
+    }
+
+    fn format_value(
+        &self,
+        unit: &Unit<R>,
+        offset: Option<UnitOffset>,
+        bytes: &[u8],
+        depth: usize,
+    ) -> anyhow::Result<String> {
+        let Some(offset) = offset else {
+            return Ok(format_hex(bytes));
+        };
+        let entry = unit.entry(offset)?;
+
+        match entry.tag() {
+            gimli::DW_TAG_typedef
+            | gimli::DW_TAG_const_type
+            | gimli::DW_TAG_volatile_type
+            | gimli::DW_TAG_restrict_type
+            | gimli::DW_TAG_atomic_type => {
+                self.format_value(unit, self.type_of(unit, &entry)?, bytes, depth)
+            }
+            gimli::DW_TAG_base_type => match entry.attr_value(gimli::DW_AT_encoding)? {
+                Some(AttributeValue::Encoding(encoding)) => Ok(format_base(encoding, bytes)),
+                _ => Ok(format_hex(bytes)),
+            },
+            gimli::DW_TAG_pointer_type
+            | gimli::DW_TAG_reference_type
+            | gimli::DW_TAG_rvalue_reference_type => Ok(format!("{:#010X}", to_u64(bytes))),
+            gimli::DW_TAG_enumeration_type => {
+                let value = to_u64(bytes);
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let enumerator = child.entry();
+                    let matches = match enumerator.attr_value(gimli::DW_AT_const_value)? {
+                        Some(AttributeValue::Sdata(constant)) => {
+                            constant == sign_extend(value, bytes.len())
+                        }
+                        Some(constant) => constant.udata_value() == Some(value),
+                        None => false,
+                    };
+                    if matches {
+                        if let Some(name) = self.name(unit, enumerator)? {
+                            return Ok(name);
+                        }
+                    }
+                }
+                Ok(value.to_string())
+            }
+            gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+                if depth >= MAX_DEPTH {
+                    return Ok("{ .. }".to_owned());
+                }
+
+                let mut members = Vec::new();
+                let mut tree = unit.entries_tree(Some(offset))?;
+                let mut children = tree.root()?.children();
+                while let Some(child) = children.next()? {
+                    let member = child.entry();
+                    if member.tag() == gimli::DW_TAG_variant_part {
+                        // Enums of Rust, the active variant is not decoded
+                        return Ok("{ .. }".to_owned());
+                    }
+                    if member.tag() != gimli::DW_TAG_member {
+                        continue;
+                    }
+                    if members.len() == MAX_ELEMENTS {
+                        members.push("..".to_owned());
+                        break;
+                    }
+
+                    let name = self.name(unit, member)?.unwrap_or_default();
+                    let member_type = self.type_of(unit, member)?;
+                    let start = self.member_offset(unit, member)?.unwrap_or(0) as usize;
+                    let end = start + self.type_size(unit, member_type)?.unwrap_or(0) as usize;
+                    let value = match bytes.get(start..end) {
+                        Some(member_bytes) => {
+                            self.format_value(unit, member_type, member_bytes, depth + 1)?
+                        }
+                        None => "..".to_owned(),
+                    };
+                    members.push(format!("{name}: {value}"));
+                }
+                Ok(format!("{{ {} }}", members.join(", ")))
+            }
+            gimli::DW_TAG_array_type => {
+                if depth >= MAX_DEPTH {
+                    return Ok("[..]".to_owned());
+                }
+
+                let element_type = self.type_of(unit, &entry)?;
+                let Some(element_size) = self.type_size(unit, element_type)?.filter(|s| *s > 0)
+                else {
+                    return Ok("[..]".to_owned());
+                };
+                let count = self.array_length(unit, offset)?.unwrap_or(0) as usize;
+
+                let mut elements = Vec::new();
+                for element in bytes.chunks_exact(element_size as usize).take(count) {
+                    if elements.len() == MAX_ELEMENTS {
+                        break;
+                    }
+                    elements.push(self.format_value(unit, element_type, element, depth + 1)?);
+                }
+                if elements.len() < count {
+                    elements.push("..".to_owned());
+                }
+                Ok(format!("[{}]", elements.join(", ")))
+            }
+            _ => Ok(format_hex(bytes)),
+        }
+    }
+
+    /// Returns the offset of a member within its structure.
+    fn member_offset(
+        &self,
+        unit: &Unit<R>,
+        member: &DebuggingInformationEntry<R>,
+    ) -> anyhow::Result<Option<u64>> {
+        match member.attr_value(gimli::DW_AT_data_member_location)? {
+            // Older DWARF versions describe the offset as an expression
+            Some(AttributeValue::Exprloc(expression)) => {
+                let mut evaluation = expression.evaluation(unit.encoding());
+                evaluation.set_initial_value(0);
+                if evaluation.evaluate()? != EvaluationResult::Complete {
+                    return Ok(None);
+                }
+                match evaluation.result().first().map(|piece| &piece.location) {
+                    Some(Location::Address { address }) => Ok(Some(*address)),
+                    _ => Ok(None),
+                }
+            }
+            Some(value) => Ok(value.udata_value()),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Assembles the bytes of a value from the pieces of its location.
+fn read_pieces(
+    core: &dyn CoreAccess,
+    registers: &FrameRegisters,
+    pieces: &[Piece<R>],
+    size: u64,
+) -> anyhow::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for piece in pieces {
+        let remaining = size.saturating_sub(bytes.len() as u64);
+        let piece_size = piece
+            .size_in_bits
+            .map(|bits| bits.div_ceil(8))
+            .unwrap_or(remaining);
+
+        match &piece.location {
+            Location::Empty => bail!("optimized out"),
+            Location::Register { register } => {
+                // Values wider than a register continue in the following one
+                let mut value = Vec::new();
+                for index in 0..piece_size.div_ceil(4) {
+                    let register = registers.get(register.0 + index as u16)?;
+                    value.extend(register.to_le_bytes());
+                }
+                value.truncate(piece_size as usize);
+                bytes.extend(value);
+            }
+            Location::Address { address } => {
+                bytes.extend(read_memory(core, *address, piece_size)?);
+            }
+            Location::Value { value } => {
+                let value = value.to_u64(u64::MAX)?.to_le_bytes();
+                bytes.extend(value.iter().take(piece_size as usize));
+            }
+            Location::Bytes { value } => bytes.extend(value.to_slice()?.iter()),
+            Location::ImplicitPointer { .. } => bail!("implicit pointer"),
+        }
+    }
+
+    bytes.resize(size as usize, 0);
+    Ok(bytes)
+}
+
+fn read_memory(core: &dyn CoreAccess, address: u64, size: u64) -> anyhow::Result<Vec<u8>> {
+    core.read_memory(address, size as usize)
+        .with_context(|| format!("cannot read memory at {address:#010X}"))
+}
+
+fn read_u32(core: &dyn CoreAccess, address: u64) -> anyhow::Result<u32> {
+    Ok(to_u64(&read_memory(core, address, 4)?) as u32)
+}
+
+/// Reads the address and length of a slice or `&str`.
+fn read_fat_pointer(core: &dyn CoreAccess, address: u64) -> anyhow::Result<(u64, u64)> {
+    let bytes = read_memory(core, address, 8)?;
+    Ok((to_u64(&bytes[..4]), to_u64(&bytes[4..])))
+}
+
+/// Reads a `&str`, long strings are truncated.
+fn read_str(core: &dyn CoreAccess, address: u64) -> anyhow::Result<String> {
+    let (data, length) = read_fat_pointer(core, address)?;
+    let bytes = read_memory(core, data, length.min(MAX_STRING_SIZE))?;
+    let mut string = String::from_utf8_lossy(&bytes).into_owned();
+    if length > MAX_STRING_SIZE {
+        string.push_str("...");
+    }
+    Ok(string)
+}
+
+fn to_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    let length = bytes.len().min(8);
+    word[..length].copy_from_slice(&bytes[..length]);
+    u64::from_le_bytes(word)
+}
+
+fn sign_extend(value: u64, size: usize) -> i64 {
+    if size == 0 || size >= 8 {
+        return value as i64;
+    }
+    let shift = 64 - 8 * size as u32;
+    ((value << shift) as i64) >> shift
+}
+
+fn format_base(encoding: gimli::DwAte, bytes: &[u8]) -> String {
+    let value = to_u64(bytes);
+    match (encoding, bytes.len()) {
+        (gimli::DW_ATE_boolean, _) => (value != 0).to_string(),
+        (gimli::DW_ATE_signed | gimli::DW_ATE_signed_char, size) => {
+            sign_extend(value, size).to_string()
+        }
+        (gimli::DW_ATE_unsigned | gimli::DW_ATE_unsigned_char, _) => value.to_string(),
+        (gimli::DW_ATE_float, 4) => f32::from_bits(value as u32).to_string(),
+        (gimli::DW_ATE_float, 8) => f64::from_bits(value).to_string(),
+        (gimli::DW_ATE_UTF, 4) => match char::from_u32(value as u32) {
+            Some(character) => format!("{character:?}"),
+            None => value.to_string(),
+        },
+        _ => format_hex(bytes),
+    }
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    if bytes.len() <= 8 {
+        return format!("{:#X}", to_u64(bytes));
+    }
+    let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    format!("[{}]", hex.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gimli::{constants::*, Register};
+
+    use super::*;
+    use crate::backtrace::access::MockCore;
+
+    /// An attribute value of [UnitBuilder], each is encoded with a fixed form.
+    enum Attr {
+        Str(&'static str),
+        Data1(u8),
+        Data4(u32),
+        Udata(u64),
+        Sdata(i64),
+        Addr(u32),
+        Ref(&'static str),
+        Expr(Vec<u8>),
+    }
+
+    fn uleb(out: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    fn sleb(out: &mut Vec<u8>, mut value: i64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+                out.push(byte);
+                return;
+            }
+            out.push(byte | 0x80);
+        }
+    }
+
+    /// Assembles a single DWARF 4 compile unit, every entry gets its own
+    /// abbreviation.
+    #[derive(Default)]
+    struct UnitBuilder {
+        abbrev: Vec<u8>,
+        info: Vec<u8>,
+        /// Number of entries, which is the code of the last abbreviation.
+        entries: u64,
+        labels: HashMap<&'static str, u32>,
+        references: Vec<(usize, &'static str)>,
+    }
+
+    impl UnitBuilder {
+        fn new() -> Self {
+            let mut unit = UnitBuilder::default();
+            // Unit length, version, abbreviation offset and address size
+            unit.info.extend([0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 4]);
+            unit
+        }
+
+        fn entry(
+            &mut self,
+            label: Option<&'static str>,
+            tag: DwTag,
+            children: bool,
+            attributes: &[(DwAt, Attr)],
+        ) {
+            self.entries += 1;
+            let code = self.entries;
+            if let Some(label) = label {
+                self.labels.insert(label, self.info.len() as u32);
+            }
+
+            uleb(&mut self.abbrev, code);
+            uleb(&mut self.abbrev, tag.0 as u64);
+            self.abbrev.push(children as u8);
+            uleb(&mut self.info, code);
+
+            for (name, value) in attributes {
+                let form = match value {
+                    Attr::Str(string) => {
+                        self.info.extend(string.as_bytes());
+                        self.info.push(0);
+                        DW_FORM_string
+                    }
+                    Attr::Data1(value) => {
+                        self.info.push(*value);
+                        DW_FORM_data1
+                    }
+                    Attr::Data4(value) => {
+                        self.info.extend(value.to_le_bytes());
+                        DW_FORM_data4
+                    }
+                    Attr::Udata(value) => {
+                        uleb(&mut self.info, *value);
+                        DW_FORM_udata
+                    }
+                    Attr::Sdata(value) => {
+                        sleb(&mut self.info, *value);
+                        DW_FORM_sdata
+                    }
+                    Attr::Addr(address) => {
+                        self.info.extend(address.to_le_bytes());
+                        DW_FORM_addr
+                    }
+                    Attr::Ref(label) => {
+                        self.references.push((self.info.len(), label));
+                        self.info.extend([0; 4]);
+                        DW_FORM_ref4
+                    }
+                    Attr::Expr(expression) => {
+                        uleb(&mut self.info, expression.len() as u64);
+                        self.info.extend(expression);
+                        DW_FORM_exprloc
+                    }
+                };
+                uleb(&mut self.abbrev, name.0 as u64);
+                uleb(&mut self.abbrev, form.0 as u64);
+            }
+            self.abbrev.extend([0, 0]);
+        }
+
+        fn end_children(&mut self) {
+            self.info.push(0);
+        }
+
+        fn finish(mut self) -> DebugInfo {
+            for (position, label) in self.references.iter() {
+                let offset = self.labels[label].to_le_bytes();
+                self.info[*position..*position + 4].copy_from_slice(&offset);
+            }
+            let length = (self.info.len() - 4) as u32;
+            self.info[..4].copy_from_slice(&length.to_le_bytes());
+            self.abbrev.push(0);
+
+            let (info, abbrev) = (self.info, self.abbrev);
+            DebugInfo::parse(|name| {
+                let data: &[u8] = match name {
+                    ".debug_info" => &info,
+                    ".debug_abbrev" => &abbrev,
+                    _ => &[],
+                };
+                Ok(EndianRcSlice::new(Rc::from(data), RunTimeEndian::Little))
+            })
+            .unwrap()
+        }
+    }
+
+    fn reg(register: u8) -> u8 {
+        DW_OP_reg0.0 + register
+    }
+
+    fn base_type(dwarf: &mut UnitBuilder, name: &'static str, encoding: DwAte, size: u8) {
+        dwarf.entry(
+            Some(name),
+            DW_TAG_base_type,
+            false,
+            &[
+                (DW_AT_name, Attr::Str(name)),
+                (DW_AT_encoding, Attr::Data1(encoding.0)),
+                (DW_AT_byte_size, Attr::Data1(size)),
+            ],
+        );
+    }
+
+    fn member(dwarf: &mut UnitBuilder, name: &'static str, member_type: &'static str, offset: u64) {
+        dwarf.entry(
+            None,
+            DW_TAG_member,
+            false,
+            &[
+                (DW_AT_name, Attr::Str(name)),
+                (DW_AT_type, Attr::Ref(member_type)),
+                (DW_AT_data_member_location, Attr::Udata(offset)),
+            ],
+        );
+    }
+
+    fn variable(
+        dwarf: &mut UnitBuilder,
+        tag: DwTag,
+        name: &'static str,
+        variable_type: &'static str,
+        location: Option<Vec<u8>>,
+    ) {
+        let mut attributes = vec![
+            (DW_AT_name, Attr::Str(name)),
+            (DW_AT_type, Attr::Ref(variable_type)),
+        ];
+        if let Some(location) = location {
+            attributes.push((DW_AT_location, Attr::Expr(location)));
+        }
+        dwarf.entry(None, tag, false, &attributes);
+    }
+
+    /// Builds the types and a function `work` at 0x80000000 to 0x80000100
+    /// whose frame base is A10.
+    fn debug_info() -> DebugInfo {
+        let mut dwarf = UnitBuilder::new();
+        dwarf.entry(
+            None,
+            DW_TAG_compile_unit,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("app")),
+                (DW_AT_low_pc, Attr::Addr(0x8000_0000)),
+                (DW_AT_high_pc, Attr::Data4(0x100)),
+            ],
+        );
+
+        base_type(&mut dwarf, "u8", DW_ATE_unsigned, 1);
+        base_type(&mut dwarf, "u16", DW_ATE_unsigned, 2);
+        base_type(&mut dwarf, "i16", DW_ATE_signed, 2);
+        base_type(&mut dwarf, "u32", DW_ATE_unsigned, 4);
+        base_type(&mut dwarf, "u64", DW_ATE_unsigned, 8);
+
+        dwarf.entry(
+            Some("*u32"),
+            DW_TAG_pointer_type,
+            false,
+            &[(DW_AT_type, Attr::Ref("u32"))],
+        );
+
+        // Older compilers give the member offset as an expression
+        dwarf.entry(
+            Some("Point"),
+            DW_TAG_structure_type,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("Point")),
+                (DW_AT_byte_size, Attr::Data1(4)),
+            ],
+        );
+        member(&mut dwarf, "x", "u16", 0);
+        dwarf.entry(
+            None,
+            DW_TAG_member,
+            false,
+            &[
+                (DW_AT_name, Attr::Str("y")),
+                (DW_AT_type, Attr::Ref("i16")),
+                (
+                    DW_AT_data_member_location,
+                    Attr::Expr(vec![DW_OP_plus_uconst.0, 2]),
+                ),
+            ],
+        );
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("Line"),
+            DW_TAG_structure_type,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("Line")),
+                (DW_AT_byte_size, Attr::Data1(8)),
+            ],
+        );
+        member(&mut dwarf, "start", "Point", 0);
+        member(&mut dwarf, "end", "Point", 4);
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("Path"),
+            DW_TAG_structure_type,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("Path")),
+                (DW_AT_byte_size, Attr::Data1(8)),
+            ],
+        );
+        member(&mut dwarf, "line", "Line", 0);
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("Flags"),
+            DW_TAG_structure_type,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("Flags")),
+                (DW_AT_byte_size, Attr::Data1(10)),
+            ],
+        );
+        for (offset, name) in ["f0", "f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9"]
+            .into_iter()
+            .enumerate()
+        {
+            member(&mut dwarf, name, "u8", offset as u64);
+        }
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("Level"),
+            DW_TAG_enumeration_type,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("Level")),
+                (DW_AT_byte_size, Attr::Data1(1)),
+            ],
+        );
+        for (name, value) in [("Low", -1), ("Mid", 0), ("High", 1)] {
+            dwarf.entry(
+                None,
+                DW_TAG_enumerator,
+                false,
+                &[
+                    (DW_AT_name, Attr::Str(name)),
+                    (DW_AT_const_value, Attr::Sdata(value)),
+                ],
+            );
+        }
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("[u8; 10]"),
+            DW_TAG_array_type,
+            true,
+            &[(DW_AT_type, Attr::Ref("u8"))],
+        );
+        dwarf.entry(
+            None,
+            DW_TAG_subrange_type,
+            false,
+            &[(DW_AT_count, Attr::Data1(10))],
+        );
+        dwarf.end_children();
+
+        dwarf.entry(
+            Some("[u32; 20]"),
+            DW_TAG_array_type,
+            true,
+            &[(DW_AT_type, Attr::Ref("u32"))],
+        );
+        dwarf.entry(
+            None,
+            DW_TAG_subrange_type,
+            false,
+            &[(DW_AT_upper_bound, Attr::Data1(19))],
+        );
+        dwarf.end_children();
+
+        dwarf.entry(
+            None,
+            DW_TAG_subprogram,
+            true,
+            &[
+                (DW_AT_name, Attr::Str("work")),
+                (DW_AT_low_pc, Attr::Addr(0x8000_0000)),
+                (DW_AT_high_pc, Attr::Data4(0x100)),
+                (DW_AT_frame_base, Attr::Expr(vec![reg(26)])),
+            ],
+        );
+        let parameter = DW_TAG_formal_parameter;
+        variable(&mut dwarf, parameter, "count", "u32", Some(vec![reg(2)]));
+        // A 64 bit value continues in the following register
+        variable(&mut dwarf, parameter, "wide", "u64", Some(vec![reg(4)]));
+        // The halves of the value are in two registers
+        variable(
+            &mut dwarf,
+            parameter,
+            "split",
+            "u32",
+            Some(vec![reg(6), DW_OP_piece.0, 2, reg(7), DW_OP_piece.0, 2]),
+        );
+        variable(&mut dwarf, parameter, "lost", "u32", Some(vec![reg(9)]));
+        variable(&mut dwarf, parameter, "unused", "u32", None);
+        variable(
+            &mut dwarf,
+            DW_TAG_variable,
+            "offset",
+            "i16",
+            Some(vec![DW_OP_fbreg.0, 8]),
+        );
+        variable(
+            &mut dwarf,
+            DW_TAG_variable,
+            "level",
+            "Level",
+            Some(vec![DW_OP_lit1.0, DW_OP_stack_value.0]),
+        );
+        let mut address = vec![DW_OP_addr.0];
+        address.extend(0x7000_0200u32.to_le_bytes());
+        variable(
+            &mut dwarf,
+            DW_TAG_variable,
+            "point",
+            "Point",
+            Some(address.clone()),
+        );
+        address[1] = 0x10;
+        variable(
+            &mut dwarf,
+            DW_TAG_variable,
+            "bytes",
+            "[u8; 10]",
+            Some(address),
+        );
+        variable(
+            &mut dwarf,
+            DW_TAG_variable,
+            "pointer",
+            "*u32",
+            Some(vec![DW_OP_lit0.0 + 16, DW_OP_stack_value.0]),
+        );
+
+        // Only the variables of blocks that contain the address are in scope
+        for (low_pc, name) in [(0x8000_0000, "inner"), (0x8000_0080, "elsewhere")] {
+            dwarf.entry(
+                None,
+                DW_TAG_lexical_block,
+                true,
+                &[
+                    (DW_AT_low_pc, Attr::Addr(low_pc)),
+                    (DW_AT_high_pc, Attr::Data4(0x80)),
+                ],
+            );
+            variable(
+                &mut dwarf,
+                DW_TAG_variable,
+                name,
+                "u32",
+                Some(vec![DW_OP_lit7.0, DW_OP_stack_value.0]),
+            );
+            dwarf.end_children();
+        }
+        dwarf.end_children();
+
+        dwarf.end_children();
+        dwarf.finish()
+    }
+
+    fn type_offset(info: &DebugInfo, name: &str) -> UnitOffset {
+        let unit = &info.units[0];
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            if entry.tag() == gimli::DW_TAG_subprogram {
+                break;
+            }
+            let entry_name = info.name(unit, entry).unwrap();
+            let entry_type = info.type_name(unit, Some(entry.offset())).unwrap();
+            if entry_name.as_deref() == Some(name) || (entry_name.is_none() && entry_type == name) {
+                return entry.offset();
+            }
+        }
+        panic!("No type {name}");
+    }
+
+    fn format(info: &DebugInfo, type_name: &str, bytes: &[u8]) -> String {
+        let offset = type_offset(info, type_name);
+        info.format_value(&info.units[0], Some(offset), bytes, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn maps_registers_to_dwarf_numbers() {
+        let mut registers = FrameRegisters::default();
+        registers.extend([
+            ("D0", 1),
+            ("D15", 2),
+            ("A0", 3),
+            ("A15", 4),
+            ("A16", 5),
+            ("PSW", 6),
+            ("Dx", 7),
+        ]);
+
+        assert_eq!(registers.get(0).unwrap(), 1);
+        assert_eq!(registers.get(15).unwrap(), 2);
+        assert_eq!(registers.get(16).unwrap(), 3);
+        assert_eq!(registers.get(31).unwrap(), 4);
+        assert_eq!(registers.get(1).unwrap_err().to_string(), "D1 not saved");
+        assert_eq!(registers.get(17).unwrap_err().to_string(), "A1 not saved");
+        assert_eq!(
+            registers.get(40).unwrap_err().to_string(),
+            "register 40 not saved"
+        );
+    }
+
+    fn piece(size_in_bits: Option<u64>, location: Location<R>) -> Piece<R> {
+        Piece {
+            size_in_bits,
+            bit_offset: None,
+            location,
+        }
+    }
+
+    #[test]
+    fn assembles_pieces() {
+        let mut registers = FrameRegisters::default();
+        registers.extend([("D4", 0x1111_2222), ("D5", 0x3333_4444), ("D6", 0xBEEF)]);
+        let core = MockCore::default().memory(0x7000_0000, &[1, 2, 3, 4]);
+        let register = |number| Location::Register {
+            register: Register(number),
+        };
+
+        // A value wider than a register continues in the next one
+        let bytes = read_pieces(&core, &registers, &[piece(None, register(4))], 8).unwrap();
+        assert_eq!(to_u64(&bytes), 0x3333_4444_1111_2222);
+
+        // Partial pieces from a register and memory
+        let pieces = [
+            piece(Some(16), register(6)),
+            piece(
+                Some(16),
+                Location::Address {
+                    address: 0x7000_0002,
+                },
+            ),
+        ];
+        let bytes = read_pieces(&core, &registers, &pieces, 4).unwrap();
+        assert_eq!(bytes, [0xEF, 0xBE, 3, 4]);
+
+        // Implicit values are truncated to the piece and padded to the size
+        let pieces = [piece(
+            Some(8),
+            Location::Value {
+                value: Value::Generic(0x1234),
+            },
+        )];
+        let bytes = read_pieces(&core, &registers, &pieces, 2).unwrap();
+        assert_eq!(bytes, [0x34, 0]);
+
+        let error = read_pieces(&core, &registers, &[piece(None, register(6))], 8).unwrap_err();
+        assert_eq!(error.to_string(), "D7 not saved");
+        let error = read_pieces(&core, &registers, &[piece(None, Location::Empty)], 4).unwrap_err();
+        assert_eq!(error.to_string(), "optimized out");
+    }
+
+    #[test]
+    fn sign_extends_values() {
+        assert_eq!(sign_extend(0xFF, 1), -1);
+        assert_eq!(sign_extend(0x7F, 1), 127);
+        assert_eq!(sign_extend(0xFFFE, 2), -2);
+        assert_eq!(sign_extend(0xFFFF_FFFF, 4), -1);
+        assert_eq!(sign_extend(u64::MAX, 8), -1);
+        assert_eq!(sign_extend(0xFF, 0), 255);
+    }
+
+    #[test]
+    fn formats_base_types() {
+        assert_eq!(format_base(gimli::DW_ATE_boolean, &[2]), "true");
+        assert_eq!(format_base(gimli::DW_ATE_signed, &[0xFE, 0xFF]), "-2");
+        assert_eq!(format_base(gimli::DW_ATE_signed_char, &[0x80]), "-128");
+        assert_eq!(format_base(gimli::DW_ATE_unsigned, &[0xFE, 0xFF]), "65534");
+        assert_eq!(
+            format_base(gimli::DW_ATE_float, &1.5f32.to_bits().to_le_bytes()),
+            "1.5"
+        );
+        assert_eq!(
+            format_base(gimli::DW_ATE_float, &(-0.25f64).to_bits().to_le_bytes()),
+            "-0.25"
+        );
+        assert_eq!(format_base(gimli::DW_ATE_UTF, &[0x41, 0, 0, 0]), "'A'");
+        // A surrogate is not a character
+        assert_eq!(format_base(gimli::DW_ATE_UTF, &[0, 0xD8, 0, 0]), "55296");
+        assert_eq!(format_base(gimli::DW_ATE_float, &[0, 0]), "0x0");
+        assert_eq!(
+            format_base(gimli::DW_ATE_complex_float, &[0x12; 9]),
+            "[12 12 12 12 12 12 12 12 12]"
+        );
+    }
+
+    #[test]
+    fn finds_member_offsets() {
+        let info = debug_info();
+        let unit = &info.units[0];
+        let point = type_offset(&info, "Point");
+
+        let (offset, member_type) = info.member(unit, point, "x").unwrap().unwrap();
+        assert_eq!(offset, 0);
+        assert_eq!(info.type_name(unit, member_type).unwrap(), "u16");
+        // Given by an expression
+        let (offset, member_type) = info.member(unit, point, "y").unwrap().unwrap();
+        assert_eq!(offset, 2);
+        assert_eq!(info.type_name(unit, member_type).unwrap(), "i16");
+        assert!(info.member(unit, point, "z").unwrap().is_none());
+    }
+
+    #[test]
+    fn formats_values_by_type() {
+        let info = debug_info();
+        assert_eq!(format(&info, "i16", &[0xFD, 0xFF]), "-3");
+        assert_eq!(format(&info, "*u32", &[0, 2, 0, 0x70]), "0x70000200");
+        assert_eq!(format(&info, "Level", &[0xFF]), "Low");
+        assert_eq!(format(&info, "Level", &[1]), "High");
+        assert_eq!(format(&info, "Level", &[5]), "5");
+        assert_eq!(
+            format(&info, "Point", &[7, 0, 0xFD, 0xFF]),
+            "{ x: 7, y: -3 }"
+        );
+        // Members beyond the read bytes
+        assert_eq!(format(&info, "Point", &[7, 0]), "{ x: 7, y: .. }");
+    }
+
+    #[test]
+    fn truncates_large_values() {
+        let info = debug_info();
+        assert_eq!(
+            format(&info, "Flags", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            "{ f0: 0, f1: 1, f2: 2, f3: 3, f4: 4, f5: 5, f6: 6, f7: 7, .. }"
+        );
+        assert_eq!(
+            format(&info, "[u8; 10]", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            "[0, 1, 2, 3, 4, 5, 6, 7, ..]"
+        );
+        // Members nested deeper than the limit are elided
+        assert_eq!(
+            format(&info, "Path", &[1, 0, 2, 0, 3, 0, 4, 0]),
+            "{ line: { start: { .. }, end: { .. } } }"
+        );
+
+        // The read is limited, the remaining elements are elided
+        let unit = &info.units[0];
+        let array = type_offset(&info, "[u32; 20]");
+        assert_eq!(info.type_size(unit, Some(array)).unwrap(), Some(80));
+        assert_eq!(info.type_name(unit, Some(array)).unwrap(), "[u32; 20]");
+        assert_eq!(
+            format(&info, "[u32; 20]", &[0; MAX_VALUE_SIZE as usize]),
+            "[0, 0, 0, 0, 0, 0, 0, 0, ..]"
+        );
+    }
+
+    #[test]
+    fn reads_frame_variables() {
+        let info = debug_info();
+        let mut registers = FrameRegisters::default();
+        registers.extend([
+            ("D2", 42),
+            ("D4", 0x1111_2222),
+            ("D5", 0x3333_4444),
+            ("D6", 0xBEEF),
+            ("D7", 0xDEAD),
+            ("A10", 0x7000_0100),
+        ]);
+        let core = MockCore::default()
+            .memory(0x7000_0108, &[0xFE, 0xFF])
+            .memory(0x7000_0200, &[7, 0, 0xFD, 0xFF])
+            .memory(0x7000_0210, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        let variables = info
+            .frame_variables(&core, 0x8000_0010, &registers)
+            .unwrap();
+        let lines: Vec<String> = variables
+            .iter()
+            .map(|variable| variable.to_string())
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                "count: u32 = 42",
+                "wide: u64 = 3689367578881368610",
+                "split: u32 = 3735928559",
+                "lost: u32 = <D9 not saved>",
+                "unused: u32 = <optimized out>",
+                "offset: i16 = -2",
+                "level: Level = High",
+                "point: Point = { x: 7, y: -3 }",
+                "bytes: [u8; 10] = [0, 1, 2, 3, 4, 5, 6, 7, ..]",
+                "pointer: *u32 = 0x00000010",
+                "inner: u32 = 7",
+            ]
+        );
+        assert!(variables[..5].iter().all(|variable| variable.argument));
+        assert!(!variables[5..].iter().any(|variable| variable.argument));
+
+        let error = info
+            .frame_variables(&core, 0x8000_0200, &registers)
+            .unwrap_err();
+        assert_eq!(error.to_string(), "No debug information for 0x80000200");
+    }
+}
@@ -1,9 +1,30 @@
 //! This module defines a stacktrace for the tricore architecture that is obtained
 //! by traversing the CSA link chain.
+use self::access::CoreAccess;
 use self::csa::{SavedContext, UpperContext};
-
+use self::free_list::FreeContextList;
+use self::locals::{DebugInfo, FrameRegisters, Variable};
+use self::panic::PanicMessage;
+use self::pcxi::PCXI;
+use self::psw::PSW;
+use self::region::{ChainEnd, CsaRegions, RegionSymbols};
+use self::source::SourceCache;
+use self::stack::{StackPosition, StackRegions, StackUsage};
+use self::trap::{Trap, TrapRegister, TrapStatus};
+use self::vector::{VectorRegisters, VectorTableSymbols, VectorTables};
+
+pub mod access;
 pub mod csa;
+pub mod free_list;
+pub mod locals;
+pub mod panic;
 pub mod pcxi;
+pub mod psw;
+pub mod region;
+pub mod source;
+pub mod stack;
+pub mod trap;
+pub mod vector;
 
 use std::{
     collections::HashMap,
@@ -13,60 +34,227 @@ use std::{
 
 use anyhow::Context;
 use colored::{Color, Colorize};
-use elf::{endian::AnyEndian, ElfBytes};
-use rust_mcd::core::Core;
+
+use crate::defmt::location::{remap_path, PathRemap};
+use crate::output::{
+    ContextEvent, FrameEvent, FreeListEvent, PswEvent, StackEvent, TrapEvent, VariableEvent,
+};
+
+/// Controls what is printed along the frames of a backtrace.
+#[derive(Debug, Clone, Default)]
+pub struct BacktraceOptions {
+    /// Set to print the saved context of each frame.
+    pub verbose: bool,
+    /// Number of source lines printed before and after the line of each
+    /// frame, no source is printed if zero.
+    pub source_lines: usize,
+    /// Mappings applied to the source paths in the elf file.
+    pub remap_path_prefixes: Vec<PathRemap>,
+}
 
 pub struct BackTraceInfo {
     stack_frames: Vec<StackFrameInfo>,
+    chain_end: ChainEnd,
+    free_list: Option<FreeContextList>,
+    stack_usage: Vec<StackUsage>,
 }
 
 impl BackTraceInfo {
-    pub fn log_stdout(&self) {
-        for f in self.stack_frames.iter() {
-            f.log_stdout();
+    /// Prints the frames along the information selected by the options.
+    pub fn log_stdout(&self, options: &BacktraceOptions) {
+        let mut sources = SourceCache::new(&options.remap_path_prefixes);
+        for (index, f) in self.stack_frames.iter().enumerate() {
+            f.log_stdout(options, &mut sources, index == 0);
+        }
+
+        if !self.chain_end.is_complete() {
+            println!(
+                "{}",
+                format!("CSA link chain is corrupted, {}", self.chain_end)
+                    .bold()
+                    .red()
+            );
+        }
+
+        for usage in self.stack_usage.iter() {
+            let line = format!("Stack: {usage}");
+            if usage.is_exceeded() {
+                println!("{}", line.bold().red());
+            } else {
+                println!(
+                    "{}",
+                    line.color(Color::TrueColor {
+                        r: 100,
+                        g: 100,
+                        b: 100
+                    })
+                );
+            }
+        }
+
+        if let Some(free_list) = &self.free_list {
+            let line = format!("Free context list: {free_list}");
+            if free_list.is_depleted() || !free_list.end.is_complete() {
+                println!("{}", line.bold().red());
+            } else {
+                println!(
+                    "{}",
+                    line.color(Color::TrueColor {
+                        r: 100,
+                        g: 100,
+                        b: 100
+                    })
+                );
+            }
         }
     }
+
+    /// Returns the reason the CSA link chain ended, [None] if it is complete.
+    pub fn chain_error(&self) -> Option<String> {
+        (!self.chain_end.is_complete()).then(|| self.chain_end.to_string())
+    }
+
+    /// Returns the usage of the stacks as part of a backtrace event.
+    pub fn stack_events(&self) -> Vec<StackEvent> {
+        self.stack_usage
+            .iter()
+            .map(|usage| StackEvent {
+                kind: usage.kind.name(),
+                pointer: usage.pointer,
+                start: usage.region.as_ref().map(|region| region.start),
+                end: usage.region.as_ref().map(|region| region.end),
+                used: match usage.position {
+                    Some(StackPosition::Within { used }) => Some(used),
+                    _ => None,
+                },
+                overflow: match usage.position {
+                    Some(StackPosition::Overflow { by }) => Some(by),
+                    _ => None,
+                },
+                underflow: match usage.position {
+                    Some(StackPosition::Underflow { by }) => Some(by),
+                    _ => None,
+                },
+                high_water: usage.high_water,
+            })
+            .collect()
+    }
+
+    /// Returns the state of the free context list as part of a backtrace event.
+    pub fn free_list_event(&self) -> Option<FreeListEvent> {
+        self.free_list.as_ref().map(|free_list| FreeListEvent {
+            fcx: free_list.fcx,
+            lcx: free_list.lcx,
+            free: free_list.contexts.len(),
+            until_depletion: free_list.until_depletion(),
+            depleted: free_list.is_depleted(),
+            error: (!free_list.end.is_complete()).then(|| free_list.end.to_string()),
+        })
+    }
+
+    /// Returns the frames as part of a backtrace event, with `verbose` set the
+    /// saved context of each frame is included.
+    pub fn frame_events(&self, verbose: bool) -> Vec<FrameEvent<'_>> {
+        self.stack_frames
+            .iter()
+            .map(|frame| FrameEvent {
+                address: frame.address,
+                function: &frame.info.function,
+                location: &frame.info.module,
+                interrupt_priority: frame.interrupt_priority,
+                trap: frame.is_trap.as_ref().map(|trap| TrapEvent {
+                    class: trap.class.index(),
+                    class_name: trap.class.name(),
+                    tin: trap.tin,
+                    tin_name: trap.tin_name().map(|(abbreviation, _)| abbreviation),
+                    registers: frame
+                        .trap_registers
+                        .iter()
+                        .map(|(register, value)| (register.name(), *value))
+                        .collect(),
+                }),
+                context: frame
+                    .context
+                    .as_ref()
+                    .filter(|_| verbose)
+                    .map(FrameContext::event),
+                variables: frame
+                    .variables
+                    .iter()
+                    .map(|variable| VariableEvent {
+                        name: &variable.name,
+                        type_name: &variable.type_name,
+                        argument: variable.argument,
+                        value: &variable.value,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
 }
 
 impl Stacktrace {
-    pub(crate) fn addr2line(&self, elf_file: &Path) -> anyhow::Result<BackTraceInfo> {
+    /// Returns the address of each frame in the order of the backtrace, the
+    /// halted address followed by the return addresses of the current and
+    /// each saved context.
+    fn frame_addresses(&self) -> impl Iterator<Item = u32> + '_ {
+        [self.current_pc, self.current_upper.a11]
+            .into_iter()
+            .chain(self.stack_frames.iter().map(|ctx| ctx.return_address()))
+    }
+
+    /// Resolves the functions of the frames and labels frames in trap and
+    /// interrupt vectors, the symbols are used to find the vector tables if
+    /// the registers of the core were not available.
+    pub(crate) fn addr2line(
+        &self,
+        elf_file: &Path,
+        vector_symbols: &VectorTableSymbols,
+    ) -> anyhow::Result<BackTraceInfo> {
         let mut registry = Addr2LineRegistry::new(elf_file);
-        let trap_metadata = TrapMetadata::from_elf(elf_file).unwrap_or(TrapMetadata::empty());
+        let vector_tables = VectorTables::resolve(&self.vector_registers, elf_file, vector_symbols);
 
-        registry.load(
-            self.stack_frames
-                .iter()
-                .map(|ctx| ctx.return_address())
-                .chain([self.current_pc].into_iter())
-                .chain([self.current_upper.a11].into_iter()),
-        )?;
+        registry.load(self.frame_addresses())?;
 
         let mut stack_frames = Vec::new();
 
-        let current_trapinfo = trap_metadata
-            .trap_class(self.current_pc)
-            .map(|class| TrapInfo {
-                class,
-                trap_id: self.current_upper.d15 as u8,
-            });
+        // The hardware passes the TIN in D15, it is only reliable as long as
+        // the handler did not modify D15 yet
+        let current_trap = vector_tables.trap_class(self.current_pc).map(|class| Trap {
+            class,
+            tin: self.current_upper.d15 as u8,
+        });
 
         stack_frames.push(StackFrameInfo {
             address: self.current_pc,
-            is_trap: current_trapinfo,
+            is_trap: current_trap,
+            interrupt_priority: vector_tables.interrupt_priority(self.current_pc),
+            trap_registers: Vec::new(),
+            context: Some(FrameContext::current(&self.current_upper)),
+            variables: Vec::new(),
             info: registry.get_address_info(self.current_pc)?,
         });
 
         stack_frames.push(StackFrameInfo {
             address: self.current_upper.a11,
             is_trap: None,
+            interrupt_priority: vector_tables.interrupt_priority(self.current_upper.a11),
+            trap_registers: Vec::new(),
+            context: None,
+            variables: Vec::new(),
             info: registry.get_address_info(self.current_upper.a11)?,
         });
 
-        for ctx in self.stack_frames.iter() {
+        // Each saved context is linked by the PCXI of the context before it
+        let links = [self.current_upper.pcxi]
+            .into_iter()
+            .chain(self.stack_frames.iter().map(|ctx| ctx.pcxi()));
+
+        for (ctx, link) in self.stack_frames.iter().zip(links) {
             let is_trap = if let SavedContext::Upper(ctx) = ctx {
-                trap_metadata.trap_class(ctx.a11).map(|class| TrapInfo {
+                vector_tables.trap_class(ctx.a11).map(|class| Trap {
                     class,
-                    trap_id: ctx.d15.try_into().unwrap(),
+                    tin: ctx.d15 as u8,
                 })
             } else {
                 None
@@ -75,37 +263,174 @@ impl Stacktrace {
             stack_frames.push(StackFrameInfo {
                 address: ctx.return_address(),
                 is_trap,
+                interrupt_priority: vector_tables.interrupt_priority(ctx.return_address()),
+                trap_registers: Vec::new(),
+                context: Some(FrameContext::saved(ctx, link)),
+                variables: Vec::new(),
                 info: registry.get_address_info(ctx.return_address())?,
             })
         }
 
-        Ok(BackTraceInfo { stack_frames })
+        for (frame, variables) in stack_frames.iter_mut().zip(self.locals.iter()) {
+            frame.variables = variables.clone();
+        }
+
+        // The status registers describe the most recent trap only
+        for frame in stack_frames.iter_mut() {
+            if let Some(trap) = frame.is_trap {
+                frame.trap_registers = self.trap_status.describe(&trap);
+                break;
+            }
+        }
+
+        Ok(BackTraceInfo {
+            stack_frames,
+            chain_end: self.chain_end.clone(),
+            free_list: self.free_list.clone(),
+            stack_usage: self.stack_usage.clone(),
+        })
     }
 }
 
 pub struct StackFrameInfo {
     address: u32,
-    is_trap: Option<TrapInfo>,
+    is_trap: Option<Trap>,
+    /// Set if the frame is in the interrupt vector of the given priority.
+    interrupt_priority: Option<u8>,
+    /// Status registers describing the trap, only set for the most recent one.
+    trap_registers: Vec<(TrapRegister, u32)>,
+    /// The context the return address of the frame was read from.
+    context: Option<FrameContext>,
+    /// Arguments and local variables, only set if requested.
+    variables: Vec<Variable>,
     info: Addr2LineInfo,
 }
 
-#[derive(Debug)]
-#[allow(dead_code)]
-struct TrapInfo {
-    class: u8,
-    trap_id: u8,
+/// Registers of a frame, either saved in a CSA or read from the core.
+struct FrameContext {
+    /// Address of the CSA, not set for the registers of the core.
+    csa_address: Option<u32>,
+    /// The PCXI stored in the context, linking to the previous context.
+    link: PCXI,
+    is_upper: bool,
+    registers: Vec<(&'static str, u32)>,
 }
 
-impl StackFrameInfo {
+impl FrameContext {
+    fn current(upper: &UpperContext) -> Self {
+        FrameContext {
+            csa_address: None,
+            link: upper.pcxi,
+            is_upper: true,
+            registers: upper.registers().to_vec(),
+        }
+    }
+
+    /// Describes the saved context `ctx` which was found by following `link`.
+    fn saved(ctx: &SavedContext, link: PCXI) -> Self {
+        let (is_upper, registers) = match ctx {
+            SavedContext::Upper(upper) => (true, upper.registers().to_vec()),
+            SavedContext::Lower(lower) => (false, lower.registers().to_vec()),
+        };
+
+        FrameContext {
+            csa_address: link.get_context().map(|link| link.get_context_address()),
+            link: ctx.pcxi(),
+            is_upper,
+            registers,
+        }
+    }
+
+    fn psw(&self) -> Option<PSW> {
+        self.registers
+            .iter()
+            .find(|(name, _)| *name == "PSW")
+            .map(|(_, value)| PSW::from(*value))
+    }
+
     fn log_stdout(&self) {
+        let grey = Color::TrueColor {
+            r: 100,
+            g: 100,
+            b: 100,
+        };
+        let location = match self.csa_address {
+            Some(address) => format!("{} context @ CSA {address:#010X}", self.kind()),
+            None => "core registers".to_owned(),
+        };
+        let link = match self.link.get_context() {
+            Some(link) => format!(
+                "{} context @ {:#010X}",
+                if link.is_upper { "upper" } else { "lower" },
+                link.get_context_address()
+            ),
+            None => "end of chain".to_owned(),
+        };
+        println!(
+            "{}",
+            format!(
+                "           {location}, PCXI = {:#010X} -> {link}",
+                u32::from(self.link)
+            )
+            .color(grey)
+        );
+
+        if let Some(psw) = self.psw() {
+            println!("{}", format!("           PSW: {psw}").color(grey));
+        }
+
+        for row in self.registers.chunks(4) {
+            let row: Vec<String> = row
+                .iter()
+                .map(|(name, value)| format!("{name:<3} = {value:#010X}"))
+                .collect();
+            println!("{}", format!("           {}", row.join("  ")).color(grey));
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        if self.is_upper {
+            "upper"
+        } else {
+            "lower"
+        }
+    }
+
+    fn event(&self) -> ContextEvent {
+        ContextEvent {
+            kind: self.kind(),
+            csa_address: self.csa_address,
+            pcxi: self.link.into(),
+            previous_csa_address: self
+                .link
+                .get_context()
+                .map(|link| link.get_context_address()),
+            registers: self.registers.iter().copied().collect(),
+            psw: self.psw().map(|psw| PswEvent {
+                io_privilege: psw.io_privilege_name(),
+                protection_register_set: psw.protection_register_set(),
+                call_depth: psw.call_depth().map(|(depth, _)| depth),
+                call_depth_limit: psw.call_depth().map(|(_, limit)| limit),
+                interrupt_stack: psw.interrupt_stack(),
+            }),
+        }
+    }
+}
+
+impl StackFrameInfo {
+    /// Prints the frame, the source line is highlighted if the frame is the
+    /// one the core halted in.
+    fn log_stdout(&self, options: &BacktraceOptions, sources: &mut SourceCache, halted: bool) {
         let address = self.address;
         let function = &self.info.function;
-        let module = &self.info.module;
-        let trap_info = self
-            .is_trap
-            .as_ref()
-            .map(|info| format!("-> detected as trap handler {info:?}"))
-            .unwrap_or_else(|| "".into());
+        let module = remap_path(&options.remap_path_prefixes, &self.info.module);
+        let trap_info = match (&self.is_trap, self.interrupt_priority) {
+            (Some(trap), _) => format!("-> detected as trap handler, {trap}"),
+            (None, Some(priority)) => {
+                format!("-> detected as interrupt service of priority {priority}")
+            }
+            (None, None) => "".into(),
+        };
 
         println!(
             "{} -> {} {}\n{}",
@@ -118,29 +443,55 @@ impl StackFrameInfo {
                 b: 100
             })
         );
+
+        if options.source_lines > 0 {
+            sources.print_snippet(&self.info.module, options.source_lines, halted);
+        }
+
+        for variable in self.variables.iter() {
+            let kind = if variable.argument { "arg" } else { "let" };
+            println!(
+                "           {} {}: {} = {}",
+                kind.dimmed(),
+                variable.name.cyan(),
+                variable.type_name.dimmed(),
+                variable.value
+            );
+        }
+
+        for (register, value) in self.trap_registers.iter() {
+            println!(
+                "{}",
+                format!("           {:<5} = {value:#010X}", register.name()).red()
+            );
+        }
+
+        if let Some(context) = self.context.as_ref().filter(|_| options.verbose) {
+            context.log_stdout();
+        }
     }
 }
 
 #[derive(Clone)]
-struct Addr2LineInfo {
-    function: String,
-    module: String,
+pub(crate) struct Addr2LineInfo {
+    pub function: String,
+    pub module: String,
 }
 
-struct Addr2LineRegistry<'a> {
+pub(crate) struct Addr2LineRegistry<'a> {
     elf_file: &'a Path,
     registry: HashMap<u32, Addr2LineInfo>,
 }
 
 impl<'a> Addr2LineRegistry<'a> {
-    fn new(elf_file: &'a Path) -> Self {
+    pub fn new(elf_file: &'a Path) -> Self {
         Addr2LineRegistry {
             elf_file,
             registry: HashMap::new(),
         }
     }
 
-    fn get_address_info(&mut self, address: u32) -> anyhow::Result<Addr2LineInfo> {
+    pub fn get_address_info(&mut self, address: u32) -> anyhow::Result<Addr2LineInfo> {
         let Some(info) = self.registry.get(&address) else {
             self.load([address].into_iter())?;
             return Ok(self.registry.get(&address).unwrap().clone());
@@ -149,7 +500,7 @@ impl<'a> Addr2LineRegistry<'a> {
         Ok(info.clone())
     }
 
-    fn load<I: Iterator<Item = u32>>(&mut self, addresses: I) -> anyhow::Result<()> {
+    pub fn load<I: Iterator<Item = u32>>(&mut self, addresses: I) -> anyhow::Result<()> {
         let mut defmt_print_process = Command::new("addr2line");
         let spawned_decoder = defmt_print_process
             .stdin(Stdio::piped())
@@ -191,111 +542,177 @@ impl<'a> Addr2LineRegistry<'a> {
     }
 }
 
-/// Captures information about the currently installed trap tables.
-struct TrapMetadata {
-    /// Contains the base address of the trap table if one was found.
-    trap_symbol: Option<u32>,
+/// Models a stacktrace, e.g. a snapshot of call frames at runtime.
+#[derive(Debug)]
+pub struct Stacktrace {
+    pub current_pc: u32,
+    pub current_upper: UpperContext,
+    pub stack_frames: Vec<SavedContext>,
+    /// The registers capturing the cause of the most recent bus error.
+    pub trap_status: TrapStatus,
+    /// The vector table registers of the core.
+    pub vector_registers: VectorRegisters,
+    /// The reason the walk over the CSA link chain ended.
+    pub chain_end: ChainEnd,
+    /// The free CSAs, [None] if the registers cannot be read.
+    pub free_list: Option<FreeContextList>,
+    /// The usage of the user and interrupt stack, empty if the stacks are not
+    /// known.
+    pub stack_usage: Vec<StackUsage>,
+    /// The variables of each frame in the order of the backtrace, only read
+    /// on request, see [Stacktrace::analyze_frames].
+    pub locals: Vec<Vec<Variable>>,
+    /// The panic the core halted in, if any.
+    pub panic: Option<PanicMessage>,
 }
 
-impl TrapMetadata {
-    /// Extracts information about available trap tables from the given elf file.
-    ///
-    /// If this function call fails, [TrapMetadata::empty] may be used to create
-    /// a stub variant of this structure.
-    fn from_elf(elf_file: &Path) -> anyhow::Result<Self> {
-        let elf_data = std::fs::read(elf_file).context("Cannot read elf file")?;
-        let elf =
-            ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).context("Cannot parse elf file")?;
-
-        let (symbols, strings) = elf
-            .symbol_table()
-            .with_context(|| "Could not parse symbol table from elf file")?
-            .with_context(|| "Elf file does not have symbol table")?;
-
-        const VALID_SYMBOLS: &[&str] = &["first_trap_table", "BSP_TRAP_VECTOR_TABLE"];
-
-        let trap_symbol = symbols
-            .iter()
-            .find_map(|symbol| {
-                let Ok(symbol_name) = strings.get(symbol.st_name as usize) else {
-                    return None;
-                };
-
-                if !VALID_SYMBOLS.contains(&symbol_name) {
-                    return None;
+impl Stacktrace {
+    /// Looks for a panic in the frames and recovers the arguments and local
+    /// variables of each frame if requested.
+    pub fn analyze_frames(&mut self, core: &dyn CoreAccess, analysis: &FrameAnalysis) {
+        let frames = self.frame_registers(core);
+
+        for (address, registers) in frames.iter() {
+            match analysis.debug_info.panic_message(core, *address, registers) {
+                Ok(Some(panic)) => {
+                    self.panic = Some(panic);
+                    break;
                 }
+                Ok(None) => {}
+                Err(error) => {
+                    log::debug!("Cannot check for a panic at {address:#010X}: {error:#}")
+                }
+            }
+        }
 
-                Some(symbol.st_value)
-            })
-            .with_context(|| {
-                format!(
-                    "Could not find trap table; searched for symbols {:?}",
-                    VALID_SYMBOLS
-                )
-            })?;
-
-        Ok(TrapMetadata {
-            trap_symbol: Some(trap_symbol.try_into().unwrap()),
-        })
-    }
-
-    /// Creates an instance with no metadata associated to it.
-    ///
-    /// When [`trap_class`][Self::trap_class] is called on such an instance,
-    /// it will always return [None].
-    pub fn empty() -> Self {
-        TrapMetadata { trap_symbol: None }
+        if analysis.locals {
+            self.locals = frames
+                .iter()
+                .map(|(address, registers)| {
+                    match analysis
+                        .debug_info
+                        .frame_variables(core, *address, registers)
+                    {
+                        Ok(variables) => variables,
+                        Err(error) => {
+                            log::debug!("Cannot read variables at {address:#010X}: {error:#}");
+                            Vec::new()
+                        }
+                    }
+                })
+                .collect();
+        }
     }
 
-    /// Based on the metadata in this structure, attempts to identify the trap
-    /// class based on the given address.
+    /// Returns the address of each frame in the order of the backtrace along
+    /// the registers known to its function.
     ///
-    /// This address is usually the program counter of the program when it hit the trap.
-    fn trap_class(&self, address: u32) -> Option<u8> {
-        let offset_from_start = address.checked_sub(self.trap_symbol?)?;
+    /// The function of the halted frame sees all registers of the core, the
+    /// calling functions only the upper context saved by their call, values
+    /// in other registers are reported as not available.
+    fn frame_registers(&self, core: &dyn CoreAccess) -> Vec<(u32, FrameRegisters)> {
+        let register = |name: &'static str| Some((name, core.read_register_opt(name)?));
+
+        // The global address registers are not part of any context
+        let global: Vec<_> = ["A0", "A1", "A8", "A9"]
+            .into_iter()
+            .filter_map(register)
+            .collect();
+
+        let mut current = FrameRegisters::default();
+        current.extend(global.iter().copied());
+        current.extend(self.current_upper.registers());
+        current.extend(
+            [
+                "D0", "D1", "D2", "D3", "D4", "D5", "D6", "D7", "A2", "A3", "A4", "A5", "A6", "A7",
+            ]
+            .into_iter()
+            .filter_map(register),
+        );
 
-        if offset_from_start > 8 * 32 {
-            return None;
+        let mut frames = vec![(self.current_pc, current)];
+
+        // The registers of a caller were saved by the call of the function
+        // below it, return addresses are looked up at the call instruction
+        for (index, return_address) in self.frame_addresses().skip(1).enumerate() {
+            let mut registers = FrameRegisters::default();
+            registers.extend(global.iter().copied());
+            match self.stack_frames.get(index) {
+                Some(SavedContext::Upper(upper)) => registers.extend(upper.registers()),
+                Some(SavedContext::Lower(lower)) => registers.extend(lower.registers()),
+                None => {}
+            }
+            frames.push((return_address.saturating_sub(1), registers));
         }
 
-        let class = offset_from_start / 32;
+        frames
+    }
+}
 
-        Some(class as u8)
+/// The debug information used to analyze the frames of a stacktrace, see
+/// [Stacktrace::analyze_frames].
+pub struct FrameAnalysis {
+    pub debug_info: DebugInfo,
+    /// Set to read the variables of every frame.
+    pub locals: bool,
+}
+
+impl FrameAnalysis {
+    /// Loads the debug information of the elf file, [None] if it is missing.
+    ///
+    /// The debug information is needed for the panic message even if the
+    /// variables are not shown.
+    pub fn load(elf_file: &Path, locals: bool) -> Option<Self> {
+        match DebugInfo::load(elf_file) {
+            Ok(debug_info) => Some(FrameAnalysis { debug_info, locals }),
+            Err(error) if locals => {
+                log::warn!("Cannot show local variables: {error:#}");
+                None
+            }
+            Err(error) => {
+                log::debug!("Cannot load debug information: {error:#}");
+                None
+            }
+        }
     }
 }
 
-/// Models a stacktrace, e.g. a snapshot of call frames at runtime.
-#[derive(Debug)]
-pub struct Stacktrace {
-    pub current_pc: u32,
-    pub current_upper: UpperContext,
-    pub stack_frames: Vec<SavedContext>,
+/// The memory regions declared by the linker, used to check the stacktrace.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLayout {
+    pub csa: CsaRegions,
+    pub stacks: StackRegions,
+}
+
+impl MemoryLayout {
+    /// Looks up the regions in the elf file.
+    pub fn resolve(
+        elf_file: &Path,
+        csa: &[RegionSymbols],
+        user_stacks: &[RegionSymbols],
+        interrupt_stacks: &[RegionSymbols],
+        fill_pattern: Option<u32>,
+    ) -> Self {
+        MemoryLayout {
+            csa: CsaRegions::resolve(elf_file, csa),
+            stacks: StackRegions::resolve(elf_file, user_stacks, interrupt_stacks, fill_pattern),
+        }
+    }
 }
 
-/// This is an extension trait for [Core].
+/// This is an extension trait for [CoreAccess].
 pub trait StacktraceExt: Sized {
-    /// Reads the stacktrace from the given core.
+    /// Reads the stacktrace from the given core, links to contexts outside
+    /// the CSA regions end the stacktrace.
     ///
-    /// This function is available on the [Core] type.
-    fn read_current(&self) -> anyhow::Result<Stacktrace>;
+    /// This function is available on all types implementing [CoreAccess], i.e.
+    /// the device and core dumps.
+    fn read_current(&self, layout: &MemoryLayout) -> anyhow::Result<Stacktrace>;
 }
 
-impl StacktraceExt for Core<'_> {
-    fn read_current(&self) -> anyhow::Result<Stacktrace> {
-        let groups = self.register_groups()?;
-        let group = groups.get_group(0)?;
-
-        let register = |name: &str| {
-            anyhow::Ok(
-                group
-                    .register(name)
-                    .ok_or_else(|| {
-                        anyhow::Error::msg(format!("Could not find {name} register for core"))
-                    })?
-                    .read()
-                    .with_context(|| format!("Cannot read {name} register"))?,
-            )
-        };
+impl<T: CoreAccess> StacktraceExt for T {
+    fn read_current(&self, layout: &MemoryLayout) -> anyhow::Result<Stacktrace> {
+        let register = |name: &str| self.read_register(name);
 
         let current_upper = UpperContext {
             pcxi: register("PCXI")?.into(),
@@ -318,12 +735,126 @@ impl StacktraceExt for Core<'_> {
 
         let current_pc = register("PC")?;
 
-        let stack_frames = current_upper.pcxi.walk_context(self).collect();
+        let mut walker = current_upper.pcxi.walk_context(self, &layout.csa);
+        let stack_frames: Vec<SavedContext> = walker.by_ref().collect();
+        let chain_end = walker.end().cloned().unwrap_or(ChainEnd::Complete);
+
+        let free_list = match FreeContextList::read(self, &layout.csa) {
+            Ok(free_list) => Some(free_list),
+            Err(error) => {
+                log::debug!("Cannot read free context list: {error:#}");
+                None
+            }
+        };
+
+        let trap_status = TrapStatus::read(self);
+        let vector_registers = VectorRegisters::read(self);
+
+        let stack_usage = StackUsage::analyze(
+            self,
+            &layout.stacks,
+            [(current_upper.psw, current_upper.a10)].into_iter().chain(
+                stack_frames.iter().filter_map(|ctx| match ctx {
+                    SavedContext::Upper(upper) => Some((upper.psw, upper.a10)),
+                    SavedContext::Lower(_) => None,
+                }),
+            ),
+        );
 
         anyhow::Ok(Stacktrace {
             stack_frames,
             current_pc,
             current_upper,
+            trap_status,
+            vector_registers,
+            chain_end,
+            free_list,
+            stack_usage,
+            locals: Vec::new(),
+            panic: None,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrace::access::MockCore;
+    use crate::backtrace::csa::LowerContext;
+
+    fn stacktrace(stack_frames: Vec<SavedContext>) -> Stacktrace {
+        Stacktrace {
+            current_pc: 0x8000_0104,
+            current_upper: UpperContext {
+                a11: 0x8000_0200,
+                d8: 8,
+                ..Default::default()
+            },
+            stack_frames,
+            trap_status: TrapStatus::default(),
+            vector_registers: VectorRegisters::default(),
+            chain_end: ChainEnd::Complete,
+            free_list: None,
+            stack_usage: Vec::new(),
+            locals: Vec::new(),
+            panic: None,
+        }
+    }
+
+    #[test]
+    fn pairs_frames_with_saved_registers() {
+        let stacktrace = stacktrace(vec![
+            SavedContext::Upper(UpperContext {
+                a11: 0x8000_0300,
+                d8: 0x18,
+                ..Default::default()
+            }),
+            SavedContext::Lower(LowerContext {
+                a11: 0x8000_0400,
+                d2: 0x22,
+                ..Default::default()
+            }),
+        ]);
+        let core = MockCore::default()
+            .register("A0", 0xA0)
+            .register("D0", 0xD0);
+
+        let frames = stacktrace.frame_registers(&core);
+        let addresses: Vec<u32> = frames.iter().map(|(address, _)| *address).collect();
+        assert_eq!(
+            addresses,
+            [0x8000_0104, 0x8000_01FF, 0x8000_02FF, 0x8000_03FF]
+        );
+
+        // The frames are in the order of the backtrace, callers are looked up
+        // at their call instruction
+        let expected: Vec<u32> = stacktrace
+            .frame_addresses()
+            .enumerate()
+            .map(|(index, address)| if index == 0 { address } else { address - 1 })
+            .collect();
+        assert_eq!(addresses, expected);
+
+        // The halted function sees all registers of the core
+        let (_, current) = &frames[0];
+        assert_eq!(current.get(0).unwrap(), 0xD0);
+        assert_eq!(current.get(8).unwrap(), 8);
+        assert_eq!(current.get(16).unwrap(), 0xA0);
+
+        // The caller at A11 of the current context gets the first saved one
+        let (_, caller) = &frames[1];
+        assert_eq!(caller.get(8).unwrap(), 0x18);
+        assert_eq!(caller.get(16).unwrap(), 0xA0);
+        assert!(caller.get(0).is_err());
+
+        let (_, caller) = &frames[2];
+        assert_eq!(caller.get(2).unwrap(), 0x22);
+        assert!(caller.get(8).is_err());
+
+        // Nothing was saved for the outermost frame but the global registers
+        let (_, outermost) = &frames[3];
+        assert_eq!(outermost.get(16).unwrap(), 0xA0);
+        assert!(outermost.get(2).is_err());
+        assert!(outermost.get(8).is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Describes a Rust panic the core halted in, see [PanicMessage].
+use std::fmt::Display;
+
+use colored::Colorize;
+
+use crate::output::PanicEvent;
+
+/// The location and message read from the `PanicInfo` of the panic handler.
+#[derive(Debug, Clone, Default)]
+pub struct PanicMessage {
+    /// The static parts of the message, arguments are shown as `{..}` since
+    /// formatting them requires running code on the device.
+    pub message: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    /// Set if the application already logged this panic, e.g. with
+    /// `panic-probe`, see [PanicMessage::was_logged].
+    pub logged: bool,
+}
+
+impl PanicMessage {
+    /// Returns the location in the form `file:line:column`.
+    pub fn location(&self) -> Option<String> {
+        let file = self.file.as_ref()?;
+        Some(match (self.line, self.column) {
+            (Some(line), Some(column)) => format!("{file}:{line}:{column}"),
+            (Some(line), None) => format!("{file}:{line}"),
+            _ => file.clone(),
+        })
+    }
+
+    /// Returns true if the given lines logged by the core contain this panic.
+    ///
+    /// The location has to appear in a line starting the panic message as
+    /// printed by `panic-probe` or the standard library, and every static part
+    /// of the message has to appear in any of the lines.
+    pub fn was_logged(&self, lines: &[String]) -> bool {
+        let location_logged = self.location().map(|location| {
+            lines
+                .iter()
+                .any(|line| line.contains("panicked at") && line.contains(&location))
+        });
+        let message_logged = self.message.as_ref().map(|message| {
+            message
+                .split("{..}")
+                .flat_map(str::lines)
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+                .all(|part| lines.iter().any(|line| line.contains(part)))
+        });
+
+        match (location_logged, message_logged) {
+            (None, None) => false,
+            (location, message) => location.unwrap_or(true) && message.unwrap_or(true),
+        }
+    }
+
+    /// Prints the panic prominently, the message is left out if the
+    /// application already logged it.
+    pub fn log_stdout(&self, core: usize) {
+        let location = self
+            .location()
+            .map(|location| format!(" at {location}"))
+            .unwrap_or_default();
+        println!("{}", format!("Core {core} panicked{location}").bold().red());
+
+        match &self.message {
+            _ if self.logged => println!("{}", "The panic message was logged above".dimmed()),
+            Some(message) => println!("{}", message.bold()),
+            None => println!("{}", "The panic message is not available".dimmed()),
+        }
+    }
+
+    pub fn event(&self) -> PanicEvent {
+        PanicEvent {
+            message: self.message.clone(),
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            logged: self.logged,
+        }
+    }
+}
+
+impl Display for PanicMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "panicked at {location}")?,
+            None => write!(f, "panicked")?,
+        }
+        if let Some(message) = &self.message {
+            write!(f, ":\n{message}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn panic() -> PanicMessage {
+        PanicMessage {
+            message: Some("index out of bounds: the len is {..} but the index is {..}".to_owned()),
+            file: Some("src/main.rs".to_owned()),
+            line: Some(10),
+            column: Some(5),
+            logged: false,
+        }
+    }
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_logged_panic() {
+        let logged = lines(&[
+            "Starting",
+            "panicked at src/main.rs:10:5:",
+            "index out of bounds: the len is 3 but the index is 7",
+        ]);
+        assert!(panic().was_logged(&logged));
+    }
+
+    #[test]
+    fn ignores_other_panics() {
+        // Another location
+        let logged = lines(&[
+            "panicked at src/main.rs:20:5:",
+            "index out of bounds: the len is 3 but the index is 7",
+        ]);
+        assert!(!panic().was_logged(&logged));
+
+        // Another message at the same location
+        let logged = lines(&["panicked at src/main.rs:10:5:", "explicit panic"]);
+        assert!(!panic().was_logged(&logged));
+
+        assert!(!panic().was_logged(&[]));
+    }
+
+    #[test]
+    fn compares_available_parts() {
+        let without_message = PanicMessage {
+            message: None,
+            ..panic()
+        };
+        assert!(without_message.was_logged(&lines(&["panicked at src/main.rs:10:5"])));
+
+        assert!(!PanicMessage::default().was_logged(&lines(&["panicked at src/main.rs:10:5"])));
+    }
+}
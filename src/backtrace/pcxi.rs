@@ -1,7 +1,8 @@
 use bitfield_struct::bitfield;
-use rust_mcd::core::Core;
 
+use super::access::CoreAccess;
 use super::csa::{ContextLinkWord, SavedContext};
+use super::region::{ChainCheck, ChainEnd, CsaRegions};
 
 /// Models the PCXI register of the tricore architecture.
 #[bitfield(u32)]
@@ -10,10 +11,12 @@ pub struct PCXI {
     #[bits(4)]
     pub previous_segment_address: u8,
     pub is_upper: bool,
+    /// Interrupt enable of the previous context.
+    pub previous_interrupt_enable: bool,
+    /// CPU priority number of the previous context.
+    pub previous_cpu_priority: u8,
     #[bits(2)]
     _reserved: u8,
-    #[bits(9)]
-    _not_implemented: u16,
 }
 
 impl PCXI {
@@ -32,22 +35,57 @@ impl PCXI {
     }
     /// Allows to iterate over all contexts in the CSA link chain using the
     /// specified core access.
-    pub fn walk_context<'a>(&self, core: &'a Core) -> ContextWalker<'a> {
-        ContextWalker { pcxi: *self, core }
+    ///
+    /// The walk ends early if a link leaves the CSA regions or forms a cycle,
+    /// see [ContextWalker::end].
+    pub fn walk_context<'a>(
+        &self,
+        core: &'a dyn CoreAccess,
+        regions: &CsaRegions,
+    ) -> ContextWalker<'a> {
+        ContextWalker {
+            pcxi: *self,
+            core,
+            check: ChainCheck::new(regions),
+            end: None,
+        }
     }
 }
 
 /// Iterator over all contexts in the link chain.
 pub struct ContextWalker<'a> {
     pcxi: PCXI,
-    core: &'a Core<'a>,
+    core: &'a dyn CoreAccess,
+    check: ChainCheck,
+    end: Option<ChainEnd>,
+}
+
+impl ContextWalker<'_> {
+    /// Returns the reason the walk ended, [None] while it did not end yet.
+    pub fn end(&self) -> Option<&ChainEnd> {
+        self.end.as_ref()
+    }
 }
 
 impl Iterator for ContextWalker<'_> {
     type Item = SavedContext;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let link_word = self.pcxi.get_context()?;
+        if self.end.is_some() {
+            return None;
+        }
+
+        let Some(link_word) = self.pcxi.get_context() else {
+            self.end = Some(ChainEnd::Complete);
+            return None;
+        };
+
+        let address = link_word.get_context_address();
+        if let Err(end) = self.check.follow(address) {
+            log::debug!("Stopping at corrupted CSA link chain: {end}");
+            self.end = Some(end);
+            return None;
+        }
 
         match link_word.load(self.core) {
             Ok(ctx) => {
@@ -56,13 +94,118 @@ impl Iterator for ContextWalker<'_> {
             }
             Err(err) => {
                 // We are bound to the Iterator trait so we cannot return an
-                // error here
-                log::error!(
-                    "Failed to obtain full list of context from device: {:?}",
-                    err
-                );
+                // error here, the caller obtains it from the end of the walk
+                log::debug!("Failed to obtain full list of context from device: {err:?}");
+                self.end = Some(ChainEnd::ReadError {
+                    address,
+                    error: format!("{err:#}"),
+                });
                 None
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrace::access::MockCore;
+
+    /// Returns the link word to the upper context at the given offset of
+    /// segment 7.
+    fn upper_link(offset: u32) -> u32 {
+        (1 << 20) | (7 << 16) | offset
+    }
+
+    /// Adds an upper context at the offset of segment 7 that links to the
+    /// given word and returns from the address.
+    fn upper_context(core: MockCore, offset: u32, link: u32, a11: u32) -> MockCore {
+        let mut words = [0u32; 16];
+        words[0] = link;
+        words[3] = a11;
+        core.words(0x7000_0000 + (offset << 6) as u64, &words)
+    }
+
+    fn walk(core: &MockCore, pcxi: u32, regions: &CsaRegions) -> (Vec<u32>, ChainEnd) {
+        let mut walker = PCXI::from(pcxi).walk_context(core, regions);
+        let return_addresses = walker.by_ref().map(|ctx| ctx.return_address()).collect();
+        (return_addresses, walker.end().cloned().unwrap())
+    }
+
+    #[test]
+    fn walks_complete_chain() {
+        let core = upper_context(MockCore::default(), 1, upper_link(2), 0x8000_0010);
+        let core = upper_context(core, 2, 0, 0x8000_0020);
+        assert_eq!(
+            walk(&core, upper_link(1), &CsaRegions::default()),
+            (vec![0x8000_0010, 0x8000_0020], ChainEnd::Complete)
+        );
+    }
+
+    #[test]
+    fn stops_at_cycle() {
+        // The second context links back to the first one
+        let core = upper_context(MockCore::default(), 1, upper_link(2), 0x8000_0010);
+        let core = upper_context(core, 2, upper_link(1), 0x8000_0020);
+        assert_eq!(
+            walk(&core, upper_link(1), &CsaRegions::default()),
+            (
+                vec![0x8000_0010, 0x8000_0020],
+                ChainEnd::Cycle {
+                    address: 0x7000_0040
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn stops_outside_regions() {
+        let core = upper_context(MockCore::default(), 1, upper_link(0x10), 0x8000_0010);
+        let core = upper_context(core, 0x10, 0, 0x8000_0020);
+        let regions = CsaRegions::from_bounds(&[(0x7000_0000, 0x7000_0100)]);
+        assert_eq!(
+            walk(&core, upper_link(1), &regions),
+            (
+                vec![0x8000_0010],
+                ChainEnd::OutOfRegion {
+                    address: 0x7000_0400
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn stops_at_read_error() {
+        let core = upper_context(MockCore::default(), 1, upper_link(3), 0x8000_0010);
+        let (return_addresses, end) = walk(&core, upper_link(1), &CsaRegions::default());
+        assert_eq!(return_addresses, vec![0x8000_0010]);
+        match end {
+            ChainEnd::ReadError { address, error } => {
+                assert_eq!(address, 0x7000_00C0);
+                assert!(error.contains("Cannot read saved context"));
+            }
+            end => panic!("Unexpected end {end:?}"),
+        }
+    }
+
+    #[test]
+    fn decodes_layout() {
+        let pcxi = PCXI::from(0x0AB7_0123);
+        assert_eq!(pcxi.previous_context_pointer(), 0x0123);
+        assert_eq!(pcxi.previous_segment_address(), 0x7);
+        assert!(pcxi.is_upper());
+        assert!(pcxi.previous_interrupt_enable());
+        assert_eq!(pcxi.previous_cpu_priority(), 0x2A);
+
+        let link = pcxi.get_context().unwrap();
+        assert_eq!(link.get_context_address(), 0x7000_48C0);
+        assert!(link.is_upper);
+
+        let pcxi = PCXI::from(0x0010_0000);
+        assert!(pcxi.is_upper());
+        assert!(!pcxi.previous_interrupt_enable());
+        assert_eq!(pcxi.previous_cpu_priority(), 0);
+        // Without a pointer the chain ends, whatever the other fields hold
+        assert!(pcxi.get_context().is_none());
+    }
+}
@@ -0,0 +1,138 @@
+//! Decodes the program status word, see [PSW].
+use std::fmt::Display;
+
+use bitfield_struct::bitfield;
+
+/// Models the PSW register of the tricore architecture.
+#[bitfield(u32)]
+pub struct PSW {
+    /// Call depth counter, the width of the counter is encoded in the leading
+    /// ones, see [PSW::call_depth].
+    #[bits(7)]
+    pub call_depth_counter: u8,
+    pub call_depth_count_enable: bool,
+    /// Write permission for the global address registers A0, A1, A8 and A9.
+    pub global_register_write: bool,
+    /// Set if the interrupt stack is in use.
+    pub interrupt_stack: bool,
+    #[bits(2)]
+    pub io_privilege: u8,
+    #[bits(2)]
+    pub protection_register_set: u8,
+    /// Safety task identifier.
+    pub safe_task: bool,
+    #[bits(9)]
+    _reserved: u16,
+    #[bits(3)]
+    _user_status: u8,
+    pub sticky_advance_overflow: bool,
+    pub advance_overflow: bool,
+    pub sticky_overflow: bool,
+    pub overflow: bool,
+    pub carry: bool,
+}
+
+impl PSW {
+    /// Returns the call depth and the depth at which a call depth overflow
+    /// trap is raised, [None] if call depth counting is disabled.
+    pub fn call_depth(&self) -> Option<(u8, u8)> {
+        let counter = self.call_depth_counter();
+        if !self.call_depth_count_enable() || counter == 0x7F {
+            return None;
+        }
+
+        // The counter occupies the bits below the most significant zero
+        let width = (0..7).rev().find(|bit| counter & (1 << bit) == 0)?;
+        let mask = (1u8 << width) - 1;
+        Some((counter & mask, mask))
+    }
+
+    pub fn io_privilege_name(&self) -> &'static str {
+        match self.io_privilege() {
+            0 => "User-0",
+            1 => "User-1",
+            2 => "Supervisor",
+            _ => "reserved",
+        }
+    }
+}
+
+impl Display for PSW {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "IO {}, PRS {}",
+            self.io_privilege_name(),
+            self.protection_register_set()
+        )?;
+
+        match self.call_depth() {
+            Some((depth, limit)) => write!(f, ", call depth {depth}/{limit}")?,
+            None => write!(f, ", call depth counting disabled")?,
+        }
+
+        let flags = [
+            (self.interrupt_stack(), "IS"),
+            (self.global_register_write(), "GW"),
+            (self.safe_task(), "S"),
+            (self.carry(), "C"),
+            (self.overflow(), "V"),
+            (self.sticky_overflow(), "SV"),
+            (self.advance_overflow(), "AV"),
+            (self.sticky_advance_overflow(), "SAV"),
+        ];
+        for (_, name) in flags.iter().filter(|(set, _)| *set) {
+            write!(f, ", {name}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a PSW with call depth counting enabled and the given counter.
+    fn counting(counter: u32) -> PSW {
+        PSW::from(0x80 | counter)
+    }
+
+    #[test]
+    fn decodes_call_depth() {
+        // 0cccccc: 6 bit counter
+        assert_eq!(counting(0b000_0101).call_depth(), Some((5, 63)));
+        // 10ccccc: 5 bit counter
+        assert_eq!(counting(0b100_0011).call_depth(), Some((3, 31)));
+        // 11110cc: 2 bit counter
+        assert_eq!(counting(0b111_1010).call_depth(), Some((2, 3)));
+        // 111110c: 1 bit counter
+        assert_eq!(counting(0b111_1101).call_depth(), Some((1, 1)));
+        // 1111110: the counter has no bits, every call overflows
+        assert_eq!(counting(0x7E).call_depth(), Some((0, 0)));
+        // 1111111: counting is disabled
+        assert_eq!(counting(0x7F).call_depth(), None);
+        // Counting is disabled by the enable bit whatever the counter holds
+        assert_eq!(PSW::from(0b000_0101).call_depth(), None);
+    }
+
+    #[test]
+    fn displays_flags() {
+        // Supervisor mode on the interrupt stack with global register writes
+        let psw = PSW::from(0x0000_0B85);
+        assert_eq!(psw.io_privilege_name(), "Supervisor");
+        assert!(psw.interrupt_stack());
+        assert_eq!(
+            psw.to_string(),
+            "IO Supervisor, PRS 0, call depth 5/63, IS, GW"
+        );
+
+        let psw = PSW::from(0x8000_107F);
+        assert_eq!(psw.protection_register_set(), 1);
+        assert!(psw.carry());
+        assert_eq!(
+            psw.to_string(),
+            "IO User-0, PRS 1, call depth counting disabled, C"
+        );
+    }
+}
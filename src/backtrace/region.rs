@@ -0,0 +1,232 @@
+//! Locates memory regions declared by the linker and validates links between
+//! contexts, see [ChainCheck].
+use std::{collections::HashSet, fmt::Display, ops::Range, path::Path, str::FromStr};
+
+use super::vector::symbol_values;
+
+/// Symbols marking the CSA regions searched for by default, the region of
+/// the whole application and those of the individual cores.
+pub const DEFAULT_CSA_REGION_SYMBOLS: &[&str] = &[
+    "__CSA_BEGIN:__CSA_END",
+    "__CSA0:__CSA0_END",
+    "__CSA1:__CSA1_END",
+    "__CSA2:__CSA2_END",
+    "__CSA3:__CSA3_END",
+    "__CSA4:__CSA4_END",
+    "__CSA5:__CSA5_END",
+];
+
+/// The symbols of the lowest and past the highest address of a memory
+/// region, given as `BEGIN:END`.
+#[derive(Debug, Clone)]
+pub struct RegionSymbols {
+    pub begin: String,
+    pub end: String,
+}
+
+impl FromStr for RegionSymbols {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((begin, end)) = s.split_once(':') else {
+            anyhow::bail!("Expected the symbols of a region as BEGIN:END, got '{s}'");
+        };
+        if begin.is_empty() || end.is_empty() {
+            anyhow::bail!("Symbols of the region '{s}' must not be empty");
+        }
+
+        Ok(RegionSymbols {
+            begin: begin.to_owned(),
+            end: end.to_owned(),
+        })
+    }
+}
+
+impl Display for RegionSymbols {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.begin, self.end)
+    }
+}
+
+/// The memory regions reserved for CSAs by the linker.
+#[derive(Debug, Clone, Default)]
+pub struct CsaRegions {
+    regions: Vec<Range<u32>>,
+}
+
+impl CsaRegions {
+    /// Looks up the regions in the elf file.
+    pub fn resolve(elf_file: &Path, symbols: &[RegionSymbols]) -> Self {
+        let regions = resolve_regions(elf_file, symbols);
+        if !regions.is_empty() {
+            log::debug!("CSA regions at {regions:X?}");
+        }
+        CsaRegions { regions }
+    }
+
+    /// Creates the regions from their start and end addresses.
+    #[cfg(test)]
+    pub fn from_bounds(bounds: &[(u32, u32)]) -> Self {
+        CsaRegions {
+            regions: bounds.iter().map(|(start, end)| *start..*end).collect(),
+        }
+    }
+
+    /// Returns true if the address is within a region, always true if no
+    /// region is known.
+    pub fn contains(&self, address: u32) -> bool {
+        self.regions.is_empty() || self.regions.iter().any(|region| region.contains(&address))
+    }
+}
+
+/// Looks up the regions in the elf file, pairs of which one symbol is not
+/// defined are ignored.
+pub fn resolve_regions(elf_file: &Path, symbols: &[RegionSymbols]) -> Vec<Range<u32>> {
+    let names: Vec<String> = symbols
+        .iter()
+        .flat_map(|region| [region.begin.clone(), region.end.clone()])
+        .collect();
+
+    let values = match symbol_values(elf_file, &names) {
+        Ok(values) => values,
+        Err(error) => {
+            log::debug!("Cannot search regions: {error:#}");
+            return Vec::new();
+        }
+    };
+    let value = |name: &str| {
+        values
+            .iter()
+            .find_map(|(symbol, value)| (symbol == name).then_some(*value))
+    };
+
+    let mut regions: Vec<Range<u32>> = symbols
+        .iter()
+        .filter_map(|region| Some(value(&region.begin)?..value(&region.end)?))
+        .filter(|region| !region.is_empty())
+        .collect();
+    regions.sort_unstable_by_key(|region| (region.start, region.end));
+    regions.dedup();
+
+    if regions.is_empty() {
+        log::debug!("Could not find a region; searched for symbols {names:?}");
+    }
+    regions
+}
+
+/// The reason a list of linked contexts ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEnd {
+    /// The last context held a null link.
+    Complete,
+    /// A link pointed to a context that was visited before.
+    Cycle { address: u32 },
+    /// A link pointed outside the CSA regions.
+    OutOfRegion { address: u32 },
+    /// A linked context could not be read.
+    ReadError { address: u32, error: String },
+}
+
+impl ChainEnd {
+    pub fn is_complete(&self) -> bool {
+        *self == ChainEnd::Complete
+    }
+}
+
+impl Display for ChainEnd {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChainEnd::Complete => write!(f, "complete"),
+            ChainEnd::Cycle { address } => {
+                write!(f, "link to {address:#010X} forms a cycle")
+            }
+            ChainEnd::OutOfRegion { address } => {
+                write!(f, "link to {address:#010X} points outside the CSA region")
+            }
+            ChainEnd::ReadError { address, error } => {
+                write!(f, "context at {address:#010X} cannot be read: {error}")
+            }
+        }
+    }
+}
+
+/// Checks the links while following a list of contexts, so that a corrupted
+/// list ends instead of running forever.
+#[derive(Debug, Clone)]
+pub struct ChainCheck {
+    regions: CsaRegions,
+    visited: HashSet<u32>,
+}
+
+impl ChainCheck {
+    pub fn new(regions: &CsaRegions) -> Self {
+        ChainCheck {
+            regions: regions.clone(),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// Validates the next link of the list.
+    pub fn follow(&mut self, address: u32) -> Result<(), ChainEnd> {
+        if !self.regions.contains(address) {
+            return Err(ChainEnd::OutOfRegion { address });
+        }
+        if !self.visited.insert(address) {
+            return Err(ChainEnd::Cycle { address });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_region_symbols() {
+        let symbols: RegionSymbols = "__CSA0:__CSA0_END".parse().unwrap();
+        assert_eq!(symbols.begin, "__CSA0");
+        assert_eq!(symbols.end, "__CSA0_END");
+        assert_eq!(symbols.to_string(), "__CSA0:__CSA0_END");
+        assert!("__CSA0".parse::<RegionSymbols>().is_err());
+        assert!(":__CSA0_END".parse::<RegionSymbols>().is_err());
+    }
+
+    #[test]
+    fn ends_at_cycles() {
+        let mut check = ChainCheck::new(&CsaRegions::default());
+        assert_eq!(check.follow(0x7000_0040), Ok(()));
+        assert_eq!(check.follow(0x7000_0080), Ok(()));
+        assert_eq!(
+            check.follow(0x7000_0040),
+            Err(ChainEnd::Cycle {
+                address: 0x7000_0040
+            })
+        );
+    }
+
+    #[test]
+    fn ends_outside_regions() {
+        let regions = CsaRegions::from_bounds(&[(0x7000_0000, 0x7000_0100)]);
+        assert!(regions.contains(0x7000_00C0));
+        assert!(!regions.contains(0x7000_0100));
+        // Without known regions every address is accepted
+        assert!(CsaRegions::default().contains(0x1234_5678));
+
+        let mut check = ChainCheck::new(&regions);
+        assert_eq!(check.follow(0x7000_00C0), Ok(()));
+        assert_eq!(
+            check.follow(0x7000_0400),
+            Err(ChainEnd::OutOfRegion {
+                address: 0x7000_0400
+            })
+        );
+        assert_eq!(
+            ChainEnd::OutOfRegion {
+                address: 0x7000_0400
+            }
+            .to_string(),
+            "link to 0x70000400 points outside the CSA region"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Reads the source code around the location of a frame, see [SourceCache].
+use std::{collections::HashMap, path::PathBuf};
+
+use colored::Colorize;
+
+use crate::defmt::location::{remap_path, PathRemap};
+
+/// Splits a location reported by addr2line, e.g.
+/// `/src/main.rs:42 (discriminator 1)`, into the path and the line.
+///
+/// Returns [None] if the location is unknown.
+pub fn parse_location(location: &str) -> Option<(&str, usize)> {
+    let location = location.split(" (").next()?;
+    let (path, line) = location.rsplit_once(':')?;
+    let line: usize = line.trim().parse().ok()?;
+    if path == "??" || line == 0 {
+        return None;
+    }
+    Some((path, line))
+}
+
+/// Loads the source files of the frames, each file is read at most once.
+pub struct SourceCache<'a> {
+    remaps: &'a [PathRemap],
+    files: HashMap<PathBuf, Option<Vec<String>>>,
+}
+
+impl<'a> SourceCache<'a> {
+    pub fn new(remaps: &'a [PathRemap]) -> Self {
+        SourceCache {
+            remaps,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Prints `context` lines before and after the line of the location,
+    /// nothing is printed if the file cannot be read.
+    pub fn print_snippet(&mut self, location: &str, context: usize, highlight: bool) {
+        let Some((path, line)) = parse_location(location) else {
+            return;
+        };
+        let path = PathBuf::from(remap_path(self.remaps, path));
+
+        let lines =
+            self.files
+                .entry(path)
+                .or_insert_with_key(|path| match std::fs::read_to_string(path) {
+                    Ok(content) => Some(content.lines().map(str::to_owned).collect()),
+                    Err(error) => {
+                        log::debug!("Cannot read source file {}: {error}", path.display());
+                        None
+                    }
+                });
+        let Some(lines) = lines else {
+            return;
+        };
+        if line > lines.len() {
+            return;
+        }
+
+        let first = line.saturating_sub(context).max(1);
+        let last = std::cmp::min(line + context, lines.len());
+        let width = last.to_string().len();
+
+        for number in first..=last {
+            let text = &lines[number - 1];
+            if number != line {
+                println!(
+                    "           {} {}",
+                    format!("{number:>width$} |").blue(),
+                    text.dimmed()
+                );
+            } else if highlight {
+                println!(
+                    "         {} {} {}",
+                    ">".bold().red(),
+                    format!("{number:>width$} |").bold().blue(),
+                    text.bold().red()
+                );
+            } else {
+                println!(
+                    "         {} {} {}",
+                    ">".bold(),
+                    format!("{number:>width$} |").bold().blue(),
+                    text.bold()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//! Computes the stack usage of a core, see [StackUsage].
+use std::{fmt::Display, ops::Range, path::Path};
+
+use anyhow::Context;
+
+use super::access::CoreAccess;
+use super::psw::PSW;
+use super::region::{resolve_regions, RegionSymbols};
+
+/// Symbols of the user stacks searched for by default, the stacks grow from
+/// the end towards the begin.
+pub const DEFAULT_USER_STACK_SYMBOLS: &[&str] = &[
+    "__USTACK_END:__USTACK",
+    "__USTACK0_END:__USTACK0",
+    "__USTACK1_END:__USTACK1",
+    "__USTACK2_END:__USTACK2",
+    "__USTACK3_END:__USTACK3",
+    "__USTACK4_END:__USTACK4",
+    "__USTACK5_END:__USTACK5",
+];
+
+/// Symbols of the interrupt stacks searched for by default.
+pub const DEFAULT_INTERRUPT_STACK_SYMBOLS: &[&str] = &[
+    "__ISTACK_END:__ISTACK",
+    "__ISTACK0_END:__ISTACK0",
+    "__ISTACK1_END:__ISTACK1",
+    "__ISTACK2_END:__ISTACK2",
+    "__ISTACK3_END:__ISTACK3",
+    "__ISTACK4_END:__ISTACK4",
+    "__ISTACK5_END:__ISTACK5",
+];
+
+/// Number of bytes read at once while scanning for the fill pattern.
+const SCAN_CHUNK_SIZE: u32 = 1024;
+
+/// Parses a fill pattern, either decimal or hexadecimal when prefixed with `0x`.
+pub fn parse_fill_pattern(s: &str) -> anyhow::Result<u32> {
+    let s = s.trim();
+    let pattern = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+
+    pattern.with_context(|| format!("Invalid fill pattern {s:?}"))
+}
+
+/// The stack regions declared by the linker.
+#[derive(Debug, Clone, Default)]
+pub struct StackRegions {
+    user: Vec<Range<u32>>,
+    interrupt: Vec<Range<u32>>,
+    /// The word the application fills the stacks with at startup.
+    fill_pattern: Option<u32>,
+}
+
+impl StackRegions {
+    /// Looks up the regions in the elf file.
+    pub fn resolve(
+        elf_file: &Path,
+        user: &[RegionSymbols],
+        interrupt: &[RegionSymbols],
+        fill_pattern: Option<u32>,
+    ) -> Self {
+        let regions = StackRegions {
+            user: resolve_regions(elf_file, user),
+            interrupt: resolve_regions(elf_file, interrupt),
+            fill_pattern,
+        };
+        log::debug!(
+            "User stacks at {:X?}, interrupt stacks at {:X?}",
+            regions.user,
+            regions.interrupt
+        );
+        regions
+    }
+
+    fn regions(&self, kind: StackKind) -> &[Range<u32>] {
+        match kind {
+            StackKind::User => &self.user,
+            StackKind::Interrupt => &self.interrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackKind {
+    User,
+    Interrupt,
+}
+
+impl StackKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            StackKind::User => "user",
+            StackKind::Interrupt => "interrupt",
+        }
+    }
+}
+
+/// Where the stack pointer is relative to the stack region.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackPosition {
+    /// The pointer is within the region, the given number of bytes is used.
+    Within { used: u32 },
+    /// The pointer is the given number of bytes below the region.
+    Overflow { by: u32 },
+    /// The pointer is the given number of bytes above the region.
+    Underflow { by: u32 },
+}
+
+/// The usage of a stack at the time the core halted.
+#[derive(Debug, Clone)]
+pub struct StackUsage {
+    pub kind: StackKind,
+    /// The value of A10 while the stack was in use.
+    pub pointer: u32,
+    /// The stack region, [None] if the pointer is not near any region.
+    pub region: Option<Range<u32>>,
+    pub position: Option<StackPosition>,
+    /// The maximum number of bytes ever used according to the fill pattern.
+    pub high_water: Option<u32>,
+}
+
+impl StackUsage {
+    /// Computes the usage of the user and interrupt stack from the most
+    /// recent stack pointer of each, given by the PSW and A10 of the upper
+    /// contexts from the current one onwards.
+    ///
+    /// A trap handler runs on the interrupt stack, the stack pointer of the
+    /// code that hit the trap is taken from the context saved on entry.
+    pub fn analyze<I: Iterator<Item = (u32, u32)>>(
+        core: &dyn CoreAccess,
+        regions: &StackRegions,
+        contexts: I,
+    ) -> Vec<StackUsage> {
+        let mut usages: Vec<StackUsage> = Vec::new();
+
+        for (psw, a10) in contexts {
+            let kind = if PSW::from(psw).interrupt_stack() {
+                StackKind::Interrupt
+            } else {
+                StackKind::User
+            };
+
+            if usages.iter().any(|usage| usage.kind == kind) || regions.regions(kind).is_empty() {
+                continue;
+            }
+
+            let (region, position) = match locate(regions.regions(kind), a10) {
+                Some((region, position)) => (Some(region), Some(position)),
+                None => (None, None),
+            };
+
+            let high_water = match (&region, regions.fill_pattern) {
+                (Some(region), Some(pattern)) => match high_water(core, region, pattern) {
+                    Ok(high_water) => Some(high_water),
+                    Err(error) => {
+                        log::debug!("Cannot scan {} stack: {error:#}", kind.name());
+                        None
+                    }
+                },
+                _ => None,
+            };
+
+            usages.push(StackUsage {
+                kind,
+                pointer: a10,
+                region,
+                position,
+                high_water,
+            });
+        }
+
+        usages.sort_by_key(|usage| usage.kind == StackKind::Interrupt);
+        usages
+    }
+
+    /// Returns true if the stack pointer left the stack region.
+    pub fn is_exceeded(&self) -> bool {
+        !matches!(self.position, Some(StackPosition::Within { .. }))
+    }
+}
+
+/// Finds the region the stack pointer belongs to, which is the region it is
+/// in or the closest one if it is at most the size of the region off.
+fn locate(regions: &[Range<u32>], pointer: u32) -> Option<(Range<u32>, StackPosition)> {
+    let position = |region: &Range<u32>| {
+        if pointer < region.start {
+            StackPosition::Overflow {
+                by: region.start - pointer,
+            }
+        } else if pointer > region.end {
+            StackPosition::Underflow {
+                by: pointer - region.end,
+            }
+        } else {
+            StackPosition::Within {
+                used: region.end - pointer,
+            }
+        }
+    };
+    let distance = |position: &StackPosition| match position {
+        StackPosition::Within { .. } => 0,
+        StackPosition::Overflow { by } | StackPosition::Underflow { by } => *by,
+    };
+
+    regions
+        .iter()
+        .map(|region| (region.clone(), position(region)))
+        .filter(|(region, position)| distance(position) <= region.len() as u32)
+        .min_by_key(|(_, position)| distance(position))
+}
+
+/// Returns the number of bytes below the end of the region that do not hold
+/// the fill pattern anymore.
+fn high_water(core: &dyn CoreAccess, region: &Range<u32>, pattern: u32) -> anyhow::Result<u32> {
+    let pattern = pattern.to_le_bytes();
+
+    let mut address = region.start;
+    while address < region.end {
+        let length = std::cmp::min(SCAN_CHUNK_SIZE, region.end - address);
+        let bytes = core.read_memory(address as u64, length as usize)?;
+
+        if let Some(index) = bytes.chunks(4).position(|word| word != pattern) {
+            return Ok(region.end - (address + index as u32 * 4));
+        }
+        address += length;
+    }
+
+    Ok(0)
+}
+
+impl Display for StackUsage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = self.kind.name();
+        let pointer = self.pointer;
+        let (Some(region), Some(position)) = (&self.region, self.position) else {
+            return write!(
+                f,
+                "A10 = {pointer:#010X} is outside the known {kind} stacks"
+            );
+        };
+        let size = region.len() as u32;
+
+        match position {
+            StackPosition::Within { used } => write!(
+                f,
+                "{kind} stack uses {used} of {size} bytes ({}%), A10 = {pointer:#010X}",
+                used as u64 * 100 / size as u64
+            )?,
+            StackPosition::Overflow { by } => write!(
+                f,
+                "{kind} stack overflowed by {by} bytes, A10 = {pointer:#010X} is below {:#010X}",
+                region.start
+            )?,
+            StackPosition::Underflow { by } => write!(
+                f,
+                "{kind} stack underflowed by {by} bytes, A10 = {pointer:#010X} is above {:#010X}",
+                region.end
+            )?,
+        }
+
+        match self.high_water {
+            Some(high_water) if high_water >= size => {
+                write!(f, ", the fill pattern is overwritten down to the limit")
+            }
+            Some(high_water) => write!(f, ", high-water mark {high_water} bytes"),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrace::access::MockCore;
+
+    const PATTERN: u32 = 0xA5A5_A5A5;
+
+    #[test]
+    fn parses_fill_patterns() {
+        assert_eq!(parse_fill_pattern("0xDEAD_BEEF").unwrap(), 0xDEAD_BEEF);
+        assert_eq!(parse_fill_pattern(" 0XA5A5A5A5 ").unwrap(), PATTERN);
+        assert_eq!(parse_fill_pattern("1_000").unwrap(), 1000);
+        assert!(parse_fill_pattern("0x1_0000_0000").is_err());
+        assert!(parse_fill_pattern("pattern").is_err());
+    }
+
+    #[test]
+    fn locates_pointer_in_region() {
+        let region = 0x7000_0000..0x7000_1000;
+        let regions = [region.clone()];
+        assert_eq!(
+            locate(&regions, 0x7000_1000),
+            Some((region.clone(), StackPosition::Within { used: 0 }))
+        );
+        assert_eq!(
+            locate(&regions, 0x7000_0000),
+            Some((region.clone(), StackPosition::Within { used: 0x1000 }))
+        );
+        assert_eq!(
+            locate(&regions, 0x6FFF_FFF0),
+            Some((region.clone(), StackPosition::Overflow { by: 0x10 }))
+        );
+        assert_eq!(
+            locate(&regions, 0x7000_1008),
+            Some((region.clone(), StackPosition::Underflow { by: 8 }))
+        );
+        // Further off than the size of the region
+        assert_eq!(locate(&regions, 0x6000_0000), None);
+    }
+
+    #[test]
+    fn locates_nearest_region() {
+        let first = 0x7000_0000..0x7000_1000;
+        let second = 0x7000_2000..0x7000_2800;
+        let regions = [first.clone(), second.clone()];
+        assert_eq!(
+            locate(&regions, 0x7000_0800),
+            Some((first, StackPosition::Within { used: 0x800 }))
+        );
+        // Closer to the begin of the second region than the end of the first
+        assert_eq!(
+            locate(&regions, 0x7000_1F00),
+            Some((second, StackPosition::Overflow { by: 0x100 }))
+        );
+    }
+
+    #[test]
+    fn finds_high_water_mark() {
+        let region = 0x7000_0000..0x7000_0800;
+        let mut words = vec![PATTERN; 0x800 / 4];
+        // The scan crosses a chunk boundary before the first used word
+        words[0x600 / 4] = 0;
+        let core = MockCore::default().words(0x7000_0000, &words);
+        assert_eq!(high_water(&core, &region, PATTERN).unwrap(), 0x200);
+
+        let core = MockCore::default().words(0x7000_0000, &[PATTERN; 0x800 / 4]);
+        assert_eq!(high_water(&core, &region, PATTERN).unwrap(), 0);
+
+        // The fill pattern is overwritten entirely
+        let core = MockCore::default().words(0x7000_0000, &[0; 0x800 / 4]);
+        assert_eq!(high_water(&core, &region, PATTERN).unwrap(), 0x800);
+
+        assert!(high_water(&MockCore::default(), &region, PATTERN).is_err());
+    }
+
+    #[test]
+    fn analyzes_user_and_interrupt_stack() {
+        let user = 0x7000_0000..0x7000_1000;
+        let interrupt = 0x7000_2000..0x7000_2100;
+        let regions = StackRegions {
+            user: vec![user.clone()],
+            interrupt: vec![interrupt.clone()],
+            fill_pattern: Some(PATTERN),
+        };
+
+        let mut user_words = vec![PATTERN; 0x1000 / 4];
+        user_words[0xE00 / 4..].fill(0);
+        let core = MockCore::default()
+            .words(0x7000_0000, &user_words)
+            .words(0x7000_2000, &[0; 0x100 / 4]);
+
+        // The trap handler runs on the interrupt stack, only the most recent
+        // pointer of each stack counts
+        let interrupt_psw = 0x200;
+        let contexts = [
+            (interrupt_psw, 0x7000_1FF0),
+            (interrupt_psw, 0x7000_20F0),
+            (0, 0x7000_0F00),
+        ];
+        let usages = StackUsage::analyze(&core, &regions, contexts.into_iter());
+        assert_eq!(usages.len(), 2);
+
+        assert_eq!(usages[0].kind, StackKind::User);
+        assert_eq!(usages[0].region, Some(user));
+        assert_eq!(usages[0].high_water, Some(0x200));
+        assert!(!usages[0].is_exceeded());
+        assert_eq!(
+            usages[0].to_string(),
+            "user stack uses 256 of 4096 bytes (6%), A10 = 0x70000F00, high-water mark 512 bytes"
+        );
+
+        assert_eq!(usages[1].kind, StackKind::Interrupt);
+        assert_eq!(usages[1].region, Some(interrupt));
+        assert_eq!(usages[1].position, Some(StackPosition::Overflow { by: 16 }));
+        assert!(usages[1].is_exceeded());
+        assert_eq!(
+            usages[1].to_string(),
+            "interrupt stack overflowed by 16 bytes, A10 = 0x70001FF0 is below 0x70002000, \
+             the fill pattern is overwritten down to the limit"
+        );
+    }
+
+    #[test]
+    fn skips_unknown_stacks() {
+        let user = 0x7000_0000..0x7000_1000;
+        let regions = StackRegions {
+            user: vec![user],
+            ..Default::default()
+        };
+        let contexts = [(0x200, 0x7000_2000), (0, 0x5000_0000)];
+        let usages = StackUsage::analyze(&MockCore::default(), &regions, contexts.into_iter());
+        assert_eq!(usages.len(), 1);
+        assert_eq!(usages[0].region, None);
+        assert_eq!(usages[0].high_water, None);
+        assert!(usages[0].is_exceeded());
+        assert_eq!(
+            usages[0].to_string(),
+            "A10 = 0x50000000 is outside the known user stacks"
+        );
+    }
+}
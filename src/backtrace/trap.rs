@@ -0,0 +1,270 @@
+//! Decodes TriCore traps, see [Trap].
+use std::fmt::Display;
+
+use super::access::CoreAccess;
+
+/// The class of a trap, given by the vector in the trap table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapClass {
+    Mmu,
+    InternalProtection,
+    InstructionError,
+    ContextManagement,
+    BusError,
+    Assertion,
+    SystemCall,
+    Nmi,
+}
+
+impl TrapClass {
+    /// Returns the class of the given trap vector, the table has eight vectors.
+    pub fn from_index(index: u8) -> Option<Self> {
+        Some(match index {
+            0 => TrapClass::Mmu,
+            1 => TrapClass::InternalProtection,
+            2 => TrapClass::InstructionError,
+            3 => TrapClass::ContextManagement,
+            4 => TrapClass::BusError,
+            5 => TrapClass::Assertion,
+            6 => TrapClass::SystemCall,
+            7 => TrapClass::Nmi,
+            _ => return None,
+        })
+    }
+
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrapClass::Mmu => "MMU",
+            TrapClass::InternalProtection => "internal protection",
+            TrapClass::InstructionError => "instruction error",
+            TrapClass::ContextManagement => "context management",
+            TrapClass::BusError => "system bus and peripheral error",
+            TrapClass::Assertion => "assertion",
+            TrapClass::SystemCall => "system call",
+            TrapClass::Nmi => "non-maskable interrupt",
+        }
+    }
+}
+
+/// A trap as identified by its class and trap identification number (TIN).
+///
+/// The hardware passes the TIN to the trap handler in D15.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+    pub class: TrapClass,
+    pub tin: u8,
+}
+
+impl Trap {
+    /// Returns the abbreviation and the description of the TIN as defined by
+    /// the TriCore architecture, system calls have the TIN given by the
+    /// application instead.
+    pub fn tin_name(&self) -> Option<(&'static str, &'static str)> {
+        Some(match (self.class, self.tin) {
+            (TrapClass::Mmu, 0) => ("VAF", "virtual address fill"),
+            (TrapClass::Mmu, 1) => ("VAP", "virtual address protection"),
+            (TrapClass::InternalProtection, 1) => ("PRIV", "privileged instruction"),
+            (TrapClass::InternalProtection, 2) => ("MPR", "memory protection read"),
+            (TrapClass::InternalProtection, 3) => ("MPW", "memory protection write"),
+            (TrapClass::InternalProtection, 4) => ("MPX", "memory protection execution"),
+            (TrapClass::InternalProtection, 5) => ("MPP", "memory protection peripheral access"),
+            (TrapClass::InternalProtection, 6) => ("MPN", "memory protection null address"),
+            (TrapClass::InternalProtection, 7) => ("GRWP", "global register write protection"),
+            (TrapClass::InstructionError, 1) => ("IOPC", "illegal opcode"),
+            (TrapClass::InstructionError, 2) => ("UOPC", "unimplemented opcode"),
+            (TrapClass::InstructionError, 3) => ("OPD", "invalid operand specification"),
+            (TrapClass::InstructionError, 4) => ("ALN", "data address alignment"),
+            (TrapClass::InstructionError, 5) => ("MEM", "invalid local memory address"),
+            (TrapClass::ContextManagement, 1) => ("FCD", "free context list depletion"),
+            (TrapClass::ContextManagement, 2) => ("CDO", "call depth overflow"),
+            (TrapClass::ContextManagement, 3) => ("CDU", "call depth underflow"),
+            (TrapClass::ContextManagement, 4) => ("FCU", "free context list underflow"),
+            (TrapClass::ContextManagement, 5) => ("CSU", "call stack underflow"),
+            (TrapClass::ContextManagement, 6) => ("CTYP", "context type error"),
+            (TrapClass::ContextManagement, 7) => ("NEST", "nesting error"),
+            (TrapClass::BusError, 1) => ("PSE", "program fetch synchronous error"),
+            (TrapClass::BusError, 2) => ("DSE", "data access synchronous error"),
+            (TrapClass::BusError, 3) => ("DAE", "data access asynchronous error"),
+            (TrapClass::BusError, 4) => ("CAE", "coprocessor trap asynchronous error"),
+            (TrapClass::BusError, 5) => ("PIE", "program memory integrity error"),
+            (TrapClass::BusError, 6) => ("DIE", "data memory integrity error"),
+            (TrapClass::BusError, 7) => ("TAE", "temporal asynchronous error"),
+            (TrapClass::Assertion, 1) => ("OVF", "arithmetic overflow"),
+            (TrapClass::Assertion, 2) => ("SOVF", "sticky arithmetic overflow"),
+            (TrapClass::Nmi, 0) => ("NMI", "non-maskable interrupt"),
+            _ => return None,
+        })
+    }
+
+    /// Returns the status registers that describe the cause of the trap.
+    pub fn status_registers(&self) -> &'static [TrapRegister] {
+        match (self.class, self.tin) {
+            (TrapClass::BusError, 1) => &[TrapRegister::Pstr],
+            (TrapClass::BusError, 2) => &[TrapRegister::Dstr, TrapRegister::Deadd],
+            (TrapClass::BusError, 3) => &[TrapRegister::Datr, TrapRegister::Deadd],
+            _ => &[],
+        }
+    }
+}
+
+impl Display for Trap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "class {} ({}), TIN {}",
+            self.class.index(),
+            self.class.name(),
+            self.tin
+        )?;
+
+        match self.tin_name() {
+            Some((abbreviation, description)) => write!(f, " {abbreviation}: {description}"),
+            None if self.class == TrapClass::SystemCall => write!(f, " (syscall number)"),
+            None => write!(f, " (reserved)"),
+        }
+    }
+}
+
+/// Core special function registers that capture the cause of bus errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapRegister {
+    /// Data synchronous trap register.
+    Dstr,
+    /// Data asynchronous trap register.
+    Datr,
+    /// Data error address register, the address of the faulting data access.
+    Deadd,
+    /// Program synchronous trap register.
+    Pstr,
+}
+
+impl TrapRegister {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrapRegister::Dstr => "DSTR",
+            TrapRegister::Datr => "DATR",
+            TrapRegister::Deadd => "DEADD",
+            TrapRegister::Pstr => "PSTR",
+        }
+    }
+}
+
+/// The trap status registers of a core at the time it halted.
+///
+/// Registers that the debugger does not provide are [None].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TrapStatus {
+    pub dstr: Option<u32>,
+    pub datr: Option<u32>,
+    pub deadd: Option<u32>,
+    pub pstr: Option<u32>,
+}
+
+impl TrapStatus {
+    /// Reads the status registers of the given core.
+    pub fn read(core: &dyn CoreAccess) -> Self {
+        let register = |register: TrapRegister| core.read_register_opt(register.name());
+
+        TrapStatus {
+            dstr: register(TrapRegister::Dstr),
+            datr: register(TrapRegister::Datr),
+            deadd: register(TrapRegister::Deadd),
+            pstr: register(TrapRegister::Pstr),
+        }
+    }
+
+    pub fn get(&self, register: TrapRegister) -> Option<u32> {
+        match register {
+            TrapRegister::Dstr => self.dstr,
+            TrapRegister::Datr => self.datr,
+            TrapRegister::Deadd => self.deadd,
+            TrapRegister::Pstr => self.pstr,
+        }
+    }
+
+    /// Returns the available status registers that describe the given trap.
+    pub fn describe(&self, trap: &Trap) -> Vec<(TrapRegister, u32)> {
+        trap.status_registers()
+            .iter()
+            .filter_map(|register| Some((*register, self.get(*register)?)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backtrace::access::MockCore;
+
+    fn trap(class: TrapClass, tin: u8) -> Trap {
+        Trap { class, tin }
+    }
+
+    #[test]
+    fn names_tins() {
+        assert_eq!(
+            trap(TrapClass::BusError, 2).tin_name(),
+            Some(("DSE", "data access synchronous error"))
+        );
+        assert_eq!(
+            trap(TrapClass::ContextManagement, 1).tin_name(),
+            Some(("FCD", "free context list depletion"))
+        );
+        assert_eq!(trap(TrapClass::SystemCall, 3).tin_name(), None);
+        assert_eq!(trap(TrapClass::InternalProtection, 0).tin_name(), None);
+        assert_eq!(TrapClass::from_index(8), None);
+        assert_eq!(TrapClass::from_index(4).map(|class| class.index()), Some(4));
+    }
+
+    #[test]
+    fn selects_status_registers() {
+        assert_eq!(
+            trap(TrapClass::BusError, 2).status_registers(),
+            &[TrapRegister::Dstr, TrapRegister::Deadd]
+        );
+        assert_eq!(
+            trap(TrapClass::BusError, 3).status_registers(),
+            &[TrapRegister::Datr, TrapRegister::Deadd]
+        );
+        assert_eq!(
+            trap(TrapClass::BusError, 1).status_registers(),
+            &[TrapRegister::Pstr]
+        );
+        assert!(trap(TrapClass::InstructionError, 4)
+            .status_registers()
+            .is_empty());
+    }
+
+    #[test]
+    fn displays_traps() {
+        assert_eq!(
+            trap(TrapClass::BusError, 2).to_string(),
+            "class 4 (system bus and peripheral error), TIN 2 DSE: data access synchronous error"
+        );
+        assert_eq!(
+            trap(TrapClass::SystemCall, 42).to_string(),
+            "class 6 (system call), TIN 42 (syscall number)"
+        );
+        assert_eq!(
+            trap(TrapClass::Assertion, 7).to_string(),
+            "class 5 (assertion), TIN 7 (reserved)"
+        );
+    }
+
+    #[test]
+    fn describes_available_status_registers() {
+        // The debugger does not provide DSTR
+        let core = MockCore::default().register("DEADD", 0x7000_0010);
+        let status = TrapStatus::read(&core);
+        assert_eq!(status.dstr, None);
+        assert_eq!(
+            status.describe(&trap(TrapClass::BusError, 2)),
+            vec![(TrapRegister::Deadd, 0x7000_0010)]
+        );
+        assert!(status.describe(&trap(TrapClass::Mmu, 0)).is_empty());
+    }
+}
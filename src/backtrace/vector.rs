@@ -0,0 +1,280 @@
+//! Locates the trap and interrupt vector tables, see [VectorTables].
+use std::path::Path;
+
+use anyhow::Context;
+use elf::{endian::AnyEndian, ElfBytes};
+
+use super::access::CoreAccess;
+
+use super::trap::TrapClass;
+
+/// Size of a vector in the trap table in bytes.
+const TRAP_VECTOR_SIZE: u32 = 32;
+
+/// Number of interrupt priorities, priority 0 never interrupts.
+const INTERRUPT_PRIORITIES: u32 = 256;
+
+/// Size of the code at the start of an interrupt vector that is attributed to
+/// the vector. Vectors only hold a short stub that jumps to the handler.
+const INTERRUPT_STUB_SIZE: u32 = 16;
+
+/// Symbols of trap tables searched for by default.
+pub const DEFAULT_TRAP_TABLE_SYMBOLS: &[&str] = &["first_trap_table", "BSP_TRAP_VECTOR_TABLE"];
+
+/// Symbols of interrupt tables searched for by default.
+pub const DEFAULT_INTERRUPT_TABLE_SYMBOLS: &[&str] =
+    &["first_interrupt_table", "BSP_INTERRUPT_VECTOR_TABLE"];
+
+/// Symbols of the vector tables in the elf file, used if the registers of a
+/// core are not available.
+#[derive(Debug, Clone)]
+pub struct VectorTableSymbols {
+    pub trap: Vec<String>,
+    pub interrupt: Vec<String>,
+}
+
+/// The vector table registers of a core at the time it halted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VectorRegisters {
+    /// Base address of the trap vector table.
+    pub btv: Option<u32>,
+    /// Base address of the interrupt vector table, bit 0 selects the vector
+    /// spacing.
+    pub biv: Option<u32>,
+}
+
+impl VectorRegisters {
+    /// Reads the registers of the given core, registers the debugger does not
+    /// provide are [None].
+    pub fn read(core: &dyn CoreAccess) -> Self {
+        VectorRegisters {
+            btv: core.read_register_opt("BTV"),
+            biv: core.read_register_opt("BIV"),
+        }
+    }
+}
+
+/// An interrupt vector table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct InterruptTable {
+    base: u32,
+    /// Distance between the vectors of two priorities in bytes.
+    spacing: u32,
+    /// Size of the table in bytes, applications usually only provide the
+    /// vectors up to the highest priority they use.
+    size: u32,
+}
+
+impl InterruptTable {
+    /// Creates a table whose size is taken from the symbol at its base, or
+    /// spans all priorities if there is no such symbol.
+    fn new(base: u32, spacing: u32, symbols: &[TableSymbol]) -> Self {
+        let size = symbols
+            .iter()
+            .find(|symbol| symbol.address == base && symbol.size != 0)
+            .map(|symbol| symbol.size)
+            .unwrap_or_else(|| {
+                log::debug!("No symbol with a size for the interrupt table at {base:#X}");
+                INTERRUPT_PRIORITIES * spacing
+            });
+
+        InterruptTable {
+            base,
+            spacing,
+            size,
+        }
+    }
+
+    /// Returns the priority of the vector whose stub contains the address.
+    fn priority(&self, address: u32) -> Option<u8> {
+        let offset = address.checked_sub(self.base)?;
+        if offset >= self.size || offset % self.spacing >= INTERRUPT_STUB_SIZE {
+            return None;
+        }
+        (offset / self.spacing).try_into().ok()
+    }
+}
+
+/// A vector table symbol in the elf file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TableSymbol {
+    address: u32,
+    /// Size of the table, 0 if the symbol does not define it.
+    size: u32,
+}
+
+/// The trap and interrupt vector tables a core uses.
+///
+/// The tables are taken from the BTV and BIV registers of the core, which is
+/// where each core of a multi-core application has its own tables. If the
+/// registers are not available, all tables found by symbol are used.
+#[derive(Debug, Clone, Default)]
+pub struct VectorTables {
+    trap_tables: Vec<u32>,
+    interrupt_tables: Vec<InterruptTable>,
+}
+
+impl VectorTables {
+    /// Resolves the tables of a core from its registers and the elf file.
+    pub fn resolve(
+        registers: &VectorRegisters,
+        elf_file: &Path,
+        symbols: &VectorTableSymbols,
+    ) -> Self {
+        let trap_tables = match registers.btv {
+            Some(btv) => vec![btv & !1],
+            None => find_tables(elf_file, &symbols.trap)
+                .into_iter()
+                .map(|symbol| symbol.address)
+                .collect(),
+        };
+
+        let interrupt_symbols = find_tables(elf_file, &symbols.interrupt);
+        let interrupt_tables = match registers.biv {
+            Some(biv) => {
+                // The vector spacing select bit reduces the spacing to 8 bytes
+                let spacing = if biv & 1 != 0 { 8 } else { 32 };
+                vec![InterruptTable::new(biv & !1, spacing, &interrupt_symbols)]
+            }
+            None => interrupt_symbols
+                .iter()
+                .map(|symbol| InterruptTable::new(symbol.address, 32, &interrupt_symbols))
+                .collect(),
+        };
+
+        log::debug!("Trap tables at {trap_tables:X?}, interrupt tables {interrupt_tables:X?}");
+        VectorTables {
+            trap_tables,
+            interrupt_tables,
+        }
+    }
+
+    /// Returns the class of the trap vector the address belongs to.
+    ///
+    /// This address is usually the program counter of the program when it hit the trap.
+    pub fn trap_class(&self, address: u32) -> Option<TrapClass> {
+        self.trap_tables.iter().find_map(|base| {
+            let offset = address.checked_sub(*base)?;
+            TrapClass::from_index((offset / TRAP_VECTOR_SIZE).try_into().ok()?)
+        })
+    }
+
+    /// Returns the priority of the interrupt vector the address belongs to.
+    ///
+    /// Only the stub at the start of each vector within the size of the table
+    /// is matched, so handlers the linker placed next to the vectors are not
+    /// mistaken for them.
+    pub fn interrupt_priority(&self, address: u32) -> Option<u8> {
+        self.interrupt_tables
+            .iter()
+            .find_map(|table| table.priority(address))
+    }
+}
+
+/// Returns all given symbols that are defined in the elf file.
+fn find_tables(elf_file: &Path, names: &[String]) -> Vec<TableSymbol> {
+    match find_symbols(elf_file, names) {
+        Ok(tables) => {
+            if tables.is_empty() {
+                log::debug!("Could not find a vector table; searched for symbols {names:?}");
+            }
+            tables
+        }
+        Err(error) => {
+            log::debug!("Cannot search vector tables: {error:#}");
+            Vec::new()
+        }
+    }
+}
+
+fn find_symbols(elf_file: &Path, names: &[String]) -> anyhow::Result<Vec<TableSymbol>> {
+    let mut tables: Vec<TableSymbol> = symbols(elf_file, names)?
+        .into_iter()
+        .map(|(_, address, size)| TableSymbol { address, size })
+        .collect();
+    tables.sort_unstable_by_key(|table| (table.address, std::cmp::Reverse(table.size)));
+    tables.dedup_by_key(|table| table.address);
+
+    Ok(tables)
+}
+
+/// Returns the name and value of all given symbols that are defined in the elf file.
+pub(super) fn symbol_values(
+    elf_file: &Path,
+    names: &[String],
+) -> anyhow::Result<Vec<(String, u32)>> {
+    Ok(symbols(elf_file, names)?
+        .into_iter()
+        .map(|(name, value, _)| (name, value))
+        .collect())
+}
+
+/// Returns the name, value and size of all given symbols that are defined in
+/// the elf file.
+fn symbols(elf_file: &Path, names: &[String]) -> anyhow::Result<Vec<(String, u32, u32)>> {
+    let elf_data = std::fs::read(elf_file).context("Cannot read elf file")?;
+    let elf =
+        ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).context("Cannot parse elf file")?;
+
+    let (symbols, strings) = elf
+        .symbol_table()
+        .with_context(|| "Could not parse symbol table from elf file")?
+        .with_context(|| "Elf file does not have symbol table")?;
+
+    Ok(symbols
+        .iter()
+        .filter_map(|symbol| {
+            let name = strings.get(symbol.st_name as usize).ok()?;
+            if !names.iter().any(|n| n == name) {
+                return None;
+            }
+            Some((
+                name.to_owned(),
+                symbol.st_value.try_into().ok()?,
+                symbol.st_size.try_into().ok()?,
+            ))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables(table: InterruptTable) -> VectorTables {
+        VectorTables {
+            trap_tables: Vec::new(),
+            interrupt_tables: vec![table],
+        }
+    }
+
+    #[test]
+    fn matches_vector_stubs() {
+        let tables = tables(InterruptTable::new(
+            0x8000_2000,
+            32,
+            &[TableSymbol {
+                address: 0x8000_2000,
+                size: 4 * 32,
+            }],
+        ));
+
+        assert_eq!(tables.interrupt_priority(0x8000_2000), Some(0));
+        assert_eq!(tables.interrupt_priority(0x8000_2024), Some(1));
+        assert_eq!(tables.interrupt_priority(0x8000_206E), Some(3));
+        // Past the stub of a vector
+        assert_eq!(tables.interrupt_priority(0x8000_2030), None);
+        // Before and past the table
+        assert_eq!(tables.interrupt_priority(0x8000_1FFE), None);
+        assert_eq!(tables.interrupt_priority(0x8000_2080), None);
+    }
+
+    #[test]
+    fn matches_all_priorities_without_symbol_size() {
+        let tables = tables(InterruptTable::new(0x8000_2000, 8, &[]));
+
+        assert_eq!(tables.interrupt_priority(0x8000_2004), Some(0));
+        assert_eq!(tables.interrupt_priority(0x8000_2000 + 255 * 8), Some(255));
+        assert_eq!(tables.interrupt_priority(0x8000_2000 + 256 * 8), None);
+    }
+}
@@ -1,16 +1,22 @@
-use crate::backtrace::Stacktrace;
+use crate::backtrace::access::CoreAccess;
+use crate::backtrace::{FrameAnalysis, MemoryLayout, Stacktrace, StacktraceExt};
 use anyhow::{bail, Context};
 use rust_mcd::connection::{Scan, ServerInfo};
+use rust_mcd::core::CoreState;
 use rust_mcd::system::System;
 use std::fs;
-use std::io::Write;
 use std::path::Path;
 use std::time::Duration;
 
 use crate::das;
-use crate::defmt::{decode_rtt, HaltReason};
-use crate::elf::elf_to_hex;
+use crate::dump::CoreDump;
+use crate::elf::{elf_to_hex, firmware_regions, FirmwareRegion};
 use crate::flash::AurixFlasherUpload;
+use crate::output::RecentLogs;
+use crate::reset::list_reset_classes;
+use crate::rtt::locate::MemoryRange;
+use crate::rtt::{decode_rtt, HaltReason, RttConfig};
+use crate::trace::{read_program_history, start_program_trace, InstructionHistory};
 
 #[derive(Debug, Clone, Copy)]
 pub struct DeviceSelection {
@@ -18,6 +24,28 @@ pub struct DeviceSelection {
     pub info: ServerInfo,
 }
 
+/// Information obtained from the device after it halted.
+pub struct HaltInfo {
+    /// Index of the core that halted first.
+    pub core: usize,
+    /// The state of every active core, ordered by core index.
+    pub cores: Vec<CoreHaltInfo>,
+    /// The decoded program flow trace per core index, only set when tracing
+    /// was requested.
+    pub traces: Vec<(usize, InstructionHistory)>,
+}
+
+/// The state of a single core after the device halted.
+pub struct CoreHaltInfo {
+    pub core: usize,
+    pub state: CoreState,
+    /// The reason the core stopped as reported by the debugger, may be empty.
+    pub stop_reason: String,
+    /// The backtrace of the core, reading it fails e.g. if the core did not
+    /// start yet.
+    pub stacktrace: anyhow::Result<Stacktrace>,
+}
+
 pub struct ChipCommunication {
     device: Option<DeviceSelection>,
     scan_result: Option<Scan>,
@@ -85,12 +113,48 @@ impl ChipCommunication {
         self.flash_hex(ihex)
     }
 
-    pub(crate) fn read_rtt<W: Write>(
+    /// Compares the firmware image of the elf file with the memory of the
+    /// device and returns the regions that differ, see [firmware_regions].
+    pub(crate) fn verify_elf(&mut self, elf_file: &Path) -> anyhow::Result<Vec<FirmwareRegion>> {
+        let elf_data = fs::read(elf_file).context("Cannot load elf file")?;
+        let regions = firmware_regions(&elf_data)?;
+
+        let system = self.get_system()?;
+        let core = system.get_core(0)?;
+
+        let mut mismatches = Vec::new();
+        for region in regions {
+            let data = core
+                .read_memory(region.address, region.data.len())
+                .with_context(|| {
+                    format!(
+                        "Cannot read {} from the device at {:#X}",
+                        region.description, region.address
+                    )
+                })?;
+
+            if data != region.data {
+                log::debug!(
+                    "The {} at {:#X} differs from the device",
+                    region.description,
+                    region.address
+                );
+                mismatches.push(region);
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    pub(crate) fn read_rtt(
         &mut self,
-        rtt_control_block_address: u64,
-        decoder: W,
+        config: RttConfig,
         active_cores: Option<u8>,
-    ) -> anyhow::Result<Stacktrace> {
+        trace_depth: Option<u32>,
+        layout: &MemoryLayout,
+        analysis: Option<&FrameAnalysis>,
+        recent_logs: &RecentLogs,
+    ) -> anyhow::Result<HaltInfo> {
         let system = self.get_system()?;
         let core_count = system.core_count();
         let active_cores = match active_cores {
@@ -104,14 +168,101 @@ impl ChipCommunication {
             .collect();
         let mut secondary_cores = secondary_cores?;
         log::debug!("Secondary cores: {:#?}", secondary_cores);
-        let HaltReason::DebugHit(halt_reason) = decode_rtt(
-            &mut core,
-            &mut secondary_cores,
-            active_cores,
-            rtt_control_block_address,
-            decoder,
-        )?;
-        anyhow::Ok(halt_reason)
+
+        if trace_depth.is_some() {
+            for (core_index, core) in [&core]
+                .into_iter()
+                .chain(secondary_cores.iter())
+                .enumerate()
+                .take(active_cores)
+            {
+                if !start_program_trace(core)? {
+                    log::warn!("Core {core_index} does not provide a program flow trace");
+                }
+            }
+        }
+
+        let HaltReason::DebugHit { core: halted_core } =
+            decode_rtt(&mut core, &mut secondary_cores, active_cores, config)?;
+
+        let mut cores = Vec::new();
+        for (core_index, core) in [&core]
+            .into_iter()
+            .chain(secondary_cores.iter())
+            .enumerate()
+            .take(active_cores)
+        {
+            let core_info = core
+                .query_state()
+                .with_context(|| format!("Cannot query state of core {core_index}"))?;
+            log::trace!("Acquiring backtrace of core {core_index}");
+            let mut stacktrace = core
+                .read_current(layout)
+                .with_context(|| format!("Cannot read backtrace of core {core_index}"));
+            if let (Ok(stacktrace), Some(analysis)) = (&mut stacktrace, analysis) {
+                stacktrace.analyze_frames(core, analysis);
+                if let Some(panic) = &mut stacktrace.panic {
+                    panic.logged = panic.was_logged(&recent_logs.lines(core_index));
+                }
+            }
+            cores.push(CoreHaltInfo {
+                core: core_index,
+                state: core_info.state,
+                stop_reason: core_info.stop_reason().to_owned(),
+                stacktrace,
+            });
+        }
+
+        let mut traces = Vec::new();
+        if let Some(trace_depth) = trace_depth {
+            for (core_index, core) in [&core]
+                .into_iter()
+                .chain(secondary_cores.iter())
+                .enumerate()
+                .take(active_cores)
+            {
+                if let Some(history) = read_program_history(core, trace_depth)
+                    .with_context(|| format!("Cannot read trace of core {core_index}"))?
+                {
+                    traces.push((core_index, history));
+                }
+            }
+        }
+
+        anyhow::Ok(HaltInfo {
+            core: halted_core,
+            cores,
+            traces,
+        })
+    }
+
+    /// Stops the active cores and captures their state, see [CoreDump::capture].
+    pub(crate) fn dump(
+        &mut self,
+        active_cores: Option<u8>,
+        layout: &MemoryLayout,
+        analysis: Option<&FrameAnalysis>,
+        regions: &[MemoryRange],
+    ) -> anyhow::Result<CoreDump> {
+        let system = self.get_system()?;
+        let core_count = system.core_count();
+        let active_cores = match active_cores {
+            Some(cores) => std::cmp::min(core_count, cores as usize),
+            None => core_count,
+        };
+        let cores: Vec<_> = (0..active_cores)
+            .map(|core_index| system.get_core(core_index))
+            .collect::<Result<_, _>>()?;
+
+        CoreDump::capture(&cores, layout, analysis, regions)
+    }
+
+    /// Returns the index and description of the reset classes supported by the
+    /// device.
+    pub(crate) fn list_reset_classes(&mut self) -> anyhow::Result<Vec<(u8, String)>> {
+        let system = self.get_system()?;
+        let core = system.get_core(0)?;
+        list_reset_classes(&core)
     }
 
     /// Returns the selected device.
@@ -0,0 +1,220 @@
+//! Filters decoded defmt frames on the host, see [LogFilter].
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// Severity of a defmt frame, ordered from the most to the least verbose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl FromStr for Level {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim().to_ascii_lowercase().as_str() {
+            "trace" => Level::Trace,
+            "debug" => Level::Debug,
+            "info" => Level::Info,
+            "warn" => Level::Warn,
+            "error" => Level::Error,
+            _ => bail!("Unknown log level {s:?}"),
+        })
+    }
+}
+
+/// A single directive of a [LogFilter], [None] as level disables the output.
+#[derive(Debug, Clone, PartialEq)]
+struct Directive {
+    /// Module path the directive applies to, applies to all modules if empty.
+    module: String,
+    level: Option<Level>,
+}
+
+/// Selects the decoded frames that are printed by their module path and level.
+///
+/// The filter uses the syntax of `env_logger`: a comma separated list of
+/// directives in the form `<module>=<level>` or `<level>`, e.g.
+/// `my_app=info,driver::can=trace`. The level `off` disables the output of a
+/// module. The directive with the longest module path matching the module of a
+/// frame applies, a directive for `a::b` matches the modules `a::b` and
+/// `a::b::c` but not `a::bc`. Frames of modules without a matching directive
+/// are printed, as are frames without a level, e.g. from `defmt::println!`.
+///
+/// The filter is applied on the host only, the device still emits all frames.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogFilter {
+    directives: Vec<Directive>,
+}
+
+impl LogFilter {
+    /// Returns true if a frame of the given module and level is printed.
+    pub fn enabled(&self, module: &str, level: Option<Level>) -> bool {
+        let Some(level) = level else {
+            return true;
+        };
+
+        let directive = self
+            .directives
+            .iter()
+            .filter(|directive| matches_module(&directive.module, module))
+            .max_by_key(|directive| directive.module.len());
+
+        match directive {
+            Some(directive) => directive.level.is_some_and(|minimum| level >= minimum),
+            None => true,
+        }
+    }
+}
+
+/// Returns true if the module is the given path or one of its submodules.
+fn matches_module(path: &str, module: &str) -> bool {
+    match module.strip_prefix(path) {
+        Some(rest) => path.is_empty() || rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut directives = Vec::new();
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let (module, level) = match directive.split_once('=') {
+                Some((module, level)) => (module.trim(), level),
+                // A single word is either a level for all modules or a module
+                // that is printed entirely
+                None => match directive.parse::<Level>() {
+                    Ok(_) => ("", directive),
+                    Err(_) if directive.eq_ignore_ascii_case("off") => ("", directive),
+                    Err(_) => (directive, "trace"),
+                },
+            };
+
+            let level = if level.trim().eq_ignore_ascii_case("off") {
+                None
+            } else {
+                Some(level.parse()?)
+            };
+            directives.push(Directive {
+                module: module.to_owned(),
+                level,
+            });
+        }
+
+        Ok(LogFilter { directives })
+    }
+}
+
+impl std::fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let directives: Vec<String> = self
+            .directives
+            .iter()
+            .map(|directive| {
+                let level = match directive.level {
+                    Some(level) => format!("{level:?}").to_ascii_lowercase(),
+                    None => "off".to_owned(),
+                };
+                if directive.module.is_empty() {
+                    level
+                } else {
+                    format!("{}={level}", directive.module)
+                }
+            })
+            .collect();
+
+        write!(f, "{}", directives.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(s: &str) -> LogFilter {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!(" Warn ".parse::<Level>().unwrap(), Level::Warn);
+        assert!("verbose".parse::<Level>().is_err());
+        assert!(Level::Trace < Level::Debug && Level::Warn < Level::Error);
+    }
+
+    #[test]
+    fn parses_directives() {
+        assert_eq!(filter(""), LogFilter::default());
+        assert_eq!(
+            filter("info, app::driver=trace,noisy=off,app::can"),
+            LogFilter {
+                directives: vec![
+                    Directive {
+                        module: String::new(),
+                        level: Some(Level::Info),
+                    },
+                    Directive {
+                        module: "app::driver".to_owned(),
+                        level: Some(Level::Trace),
+                    },
+                    Directive {
+                        module: "noisy".to_owned(),
+                        level: None,
+                    },
+                    Directive {
+                        module: "app::can".to_owned(),
+                        level: Some(Level::Trace),
+                    },
+                ],
+            }
+        );
+        assert_eq!(
+            filter("OFF"),
+            LogFilter {
+                directives: vec![Directive {
+                    module: String::new(),
+                    level: None,
+                }],
+            }
+        );
+        assert!("app=loud".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn displays_directives() {
+        let filter = filter("warn,app::driver=trace,noisy=off");
+        assert_eq!(filter.to_string(), "warn,app::driver=trace,noisy=off");
+        assert_eq!(filter.to_string().parse::<LogFilter>().unwrap(), filter);
+    }
+
+    #[test]
+    fn selects_longest_matching_module() {
+        let filter = filter("warn,app=info,app::driver=trace,app::noisy=off");
+
+        assert!(filter.enabled("other", Some(Level::Warn)));
+        assert!(!filter.enabled("other", Some(Level::Info)));
+        assert!(filter.enabled("app", Some(Level::Info)));
+        assert!(!filter.enabled("app::can", Some(Level::Debug)));
+        assert!(filter.enabled("app::driver::spi", Some(Level::Trace)));
+        assert!(!filter.enabled("app::noisy", Some(Level::Error)));
+        // Only whole path segments match
+        assert!(!filter.enabled("application", Some(Level::Info)));
+        assert!(!filter.enabled("app::driverx", Some(Level::Trace)));
+    }
+
+    #[test]
+    fn prints_unmatched_and_unleveled_frames() {
+        let filter = filter("app=error");
+
+        assert!(filter.enabled("other", Some(Level::Trace)));
+        assert!(filter.enabled("app", None));
+        assert!(LogFilter::default().enabled("app", Some(Level::Trace)));
+    }
+}
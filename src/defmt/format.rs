@@ -0,0 +1,174 @@
+//! Layout of printed defmt frames, see [FrameFormat].
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// Layout of a decoded frame in text output.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum FrameFormat {
+    /// Timestamp, level and message on one line, the location is omitted.
+    Compact,
+    /// Timestamp, level and message followed by a line with the module and
+    /// the location.
+    #[default]
+    Verbose,
+    /// A single line in the given template, see [FrameFormat::from_str].
+    Template(String),
+}
+
+/// Placeholders of a [FrameFormat::Template].
+const PLACEHOLDERS: [&str; 6] = ["{t}", "{L}", "{s}", "{f}", "{l}", "{m}"];
+
+impl FromStr for FrameFormat {
+    type Err = anyhow::Error;
+
+    /// Parses `compact`, `verbose` or a template.
+    ///
+    /// A template may contain the placeholders `{t}` for the timestamp, `{L}`
+    /// for the level, `{s}` for the message, `{f}` for the path of the source
+    /// file, `{l}` for the line and `{m}` for the module path, e.g.
+    /// `{t} {L} {s} {f}:{l}`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "compact" => FrameFormat::Compact,
+            "verbose" => FrameFormat::Verbose,
+            template => {
+                if !PLACEHOLDERS
+                    .iter()
+                    .any(|placeholder| template.contains(placeholder))
+                {
+                    bail!(
+                        "Expected compact, verbose or a template containing any of {}",
+                        PLACEHOLDERS.join(" ")
+                    );
+                }
+                FrameFormat::Template(template.to_owned())
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for FrameFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameFormat::Compact => write!(f, "compact"),
+            FrameFormat::Verbose => write!(f, "verbose"),
+            FrameFormat::Template(template) => write!(f, "{template}"),
+        }
+    }
+}
+
+/// Fields of a frame as substituted into a template.
+pub struct TemplateFields<'a> {
+    pub timestamp: &'a str,
+    pub level: &'a str,
+    pub message: &'a str,
+    /// Empty if the location is hidden.
+    pub file: &'a str,
+    /// Empty if the location is hidden or unknown.
+    pub line: &'a str,
+    pub module: &'a str,
+}
+
+/// Substitutes the placeholders of the template in a single pass, so fields
+/// that contain placeholders themselves are printed as is.
+///
+/// Without a file, the separator of `{f}:{l}` is omitted as well.
+pub fn render_template(template: &str, fields: &TemplateFields) -> String {
+    let template = if fields.file.is_empty() {
+        template.replace("{f}:{l}", "{f}")
+    } else {
+        template.to_owned()
+    };
+
+    let mut rendered = String::with_capacity(template.len() + fields.message.len());
+    let mut rest = template.as_str();
+    while let Some(start) = rest.find('{') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let value = match rest.get(..3) {
+            Some("{t}") => Some(fields.timestamp),
+            Some("{L}") => Some(fields.level),
+            Some("{s}") => Some(fields.message),
+            Some("{f}") => Some(fields.file),
+            Some("{l}") => Some(fields.line),
+            Some("{m}") => Some(fields.module),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                rendered.push_str(value);
+                rest = &rest[3..];
+            }
+            None => {
+                rendered.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    rendered.push_str(rest);
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS: TemplateFields = TemplateFields {
+        timestamp: "0.000123",
+        level: "INFO",
+        message: "Value {s} is {L}",
+        file: "src/main.rs",
+        line: "42",
+        module: "app::driver",
+    };
+
+    #[test]
+    fn parses_formats() {
+        assert_eq!(
+            "compact".parse::<FrameFormat>().unwrap(),
+            FrameFormat::Compact
+        );
+        assert_eq!(
+            "verbose".parse::<FrameFormat>().unwrap(),
+            FrameFormat::Verbose
+        );
+        assert_eq!(
+            "{L} {s}".parse::<FrameFormat>().unwrap(),
+            FrameFormat::Template("{L} {s}".to_owned())
+        );
+        assert!("short".parse::<FrameFormat>().is_err());
+        assert!("{x}".parse::<FrameFormat>().is_err());
+    }
+
+    #[test]
+    fn renders_all_placeholders() {
+        assert_eq!(
+            render_template("[{t}] {L} {m}: {s} ({f}:{l})", &FIELDS),
+            "[0.000123] INFO app::driver: Value {s} is {L} (src/main.rs:42)"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_braces() {
+        assert_eq!(render_template("{{L}} {x} {", &FIELDS), "{INFO} {x} {");
+    }
+
+    #[test]
+    fn omits_separator_without_file() {
+        let fields = TemplateFields {
+            file: "",
+            line: "",
+            ..FIELDS
+        };
+        assert_eq!(
+            render_template("{s} @ {f}:{l}", &fields),
+            "Value {s} is {L} @ "
+        );
+
+        let fields = TemplateFields { line: "", ..FIELDS };
+        assert_eq!(render_template("{f}:{l}", &fields), "src/main.rs:");
+    }
+}
@@ -0,0 +1,142 @@
+//! Presentation of the source locations of defmt frames, see [PathRemap].
+use std::str::FromStr;
+
+use anyhow::bail;
+
+/// Replaces the prefix of the source file paths stored in the elf file, in
+/// the form `<from>=<to>` as known from `rustc --remap-path-prefix`.
+///
+/// The elf file contains the paths of the machine that built it. Remapping
+/// them to the local checkout makes the locations clickable in editors. If
+/// the replacement only contains separators of one style, the separators of
+/// the rest of the path are converted to that style, e.g. to map the paths of
+/// a Windows build machine to a Linux workstation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathRemap {
+    from: String,
+    to: String,
+}
+
+impl PathRemap {
+    /// Returns the remapped path if the path starts with the prefix.
+    fn apply(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(&self.from)?;
+        let rest = match (self.to.contains('/'), self.to.contains('\\')) {
+            (true, false) => rest.replace('\\', "/"),
+            (false, true) => rest.replace('/', "\\"),
+            _ => rest.to_owned(),
+        };
+
+        Some(format!("{}{rest}", self.to))
+    }
+}
+
+impl FromStr for PathRemap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Paths may contain '=' themselves, the replacement is expected not to
+        let Some((from, to)) = s.rsplit_once('=') else {
+            bail!("Expected a path prefix mapping in the form <from>=<to>");
+        };
+        if from.is_empty() {
+            bail!("The path prefix to replace must not be empty");
+        }
+
+        Ok(PathRemap {
+            from: from.to_owned(),
+            to: to.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for PathRemap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.from, self.to)
+    }
+}
+
+/// Applies the given mappings to a path, later mappings take precedence just
+/// as with rustc.
+pub fn remap_path(remaps: &[PathRemap], path: &str) -> String {
+    remaps
+        .iter()
+        .rev()
+        .find_map(|remap| remap.apply(path))
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Returns true if the path belongs to a dependency, i.e. a crate from the
+/// cargo registry or a git checkout, or to the standard library.
+pub fn is_dependency(path: &str) -> bool {
+    let path = path.replace('\\', "/");
+    [
+        ".cargo/registry/",
+        ".cargo/git/",
+        "/rustc/",
+        "/rustlib/src/",
+    ]
+    .iter()
+    .any(|marker| path.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(remaps: &[&str]) -> Vec<PathRemap> {
+        remaps.iter().map(|remap| remap.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_remaps() {
+        let remap: PathRemap = "/build/a=b=/src".parse().unwrap();
+        assert_eq!(remap.to_string(), "/build/a=b=/src");
+        assert!("/build".parse::<PathRemap>().is_err());
+        assert!("=/src".parse::<PathRemap>().is_err());
+    }
+
+    #[test]
+    fn remaps_prefix() {
+        let remaps = parse(&["/build=/home/me/app", "/build/vendor=/vendor"]);
+        assert_eq!(
+            remap_path(&remaps, "/build/src/main.rs"),
+            "/home/me/app/src/main.rs"
+        );
+        // Later mappings take precedence
+        assert_eq!(
+            remap_path(&remaps, "/build/vendor/lib.rs"),
+            "/vendor/lib.rs"
+        );
+        assert_eq!(remap_path(&remaps, "/other/main.rs"), "/other/main.rs");
+    }
+
+    #[test]
+    fn converts_separators() {
+        let remaps = parse(&["C:\\build=/home/me/app"]);
+        assert_eq!(
+            remap_path(&remaps, "C:\\build\\src\\main.rs"),
+            "/home/me/app/src/main.rs"
+        );
+
+        let remaps = parse(&["/build=C:\\app"]);
+        assert_eq!(
+            remap_path(&remaps, "/build/src/main.rs"),
+            "C:\\app\\src\\main.rs"
+        );
+    }
+
+    #[test]
+    fn detects_dependencies() {
+        assert!(is_dependency(
+            "/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/defmt-0.3.5/src/lib.rs"
+        ));
+        assert!(is_dependency(
+            "C:\\Users\\me\\.cargo\\git\\checkouts\\hal\\src\\lib.rs"
+        ));
+        assert!(is_dependency(
+            "/rustc/59807616e1fa2540724bfbac14d7976d7e4a3860/library/core/src/panicking.rs"
+        ));
+        assert!(!is_dependency("/home/me/app/src/main.rs"));
+    }
+}
@@ -0,0 +1,338 @@
+//! Handles decoding of defmt byte streams, see [DefmtDecoder].
+use std::{
+    io::{BufRead, BufReader, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+    thread::JoinHandle,
+};
+
+use anyhow::Context;
+use colored::Colorize;
+
+use crate::output::{Event, LogSource, OutputFormat, RecentLogs};
+
+use self::filter::{Level, LogFilter};
+use self::format::{render_template, FrameFormat, TemplateFields};
+use self::location::PathRemap;
+
+pub mod filter;
+pub mod format;
+pub mod location;
+
+/// This structure handles decoding a byte stream as defmt data.
+///
+/// This is implemented by spawning `defmt-print` and printing its output on
+/// the parents stdout. Note that this object implements [Write], so input data is
+/// written into this object through this trait.
+pub struct DefmtDecoder {
+    spawned_decoder: Child,
+    /// Prints the frames decoded by the process.
+    printer: Option<JoinHandle<()>>,
+}
+
+/// Configures how decoded frames are printed.
+#[derive(Debug, Clone, Default)]
+pub struct DefmtOptions {
+    /// Frames that are printed.
+    pub filter: LogFilter,
+    /// Layout of the frames in text output.
+    pub format: FrameFormat,
+    /// Set to omit the locations of frames logged by dependencies.
+    pub hide_dependency_locations: bool,
+    /// Mappings applied to the paths of source files.
+    pub remap_path_prefixes: Vec<PathRemap>,
+    /// Set if the elf file does not match the firmware on the device, frames
+    /// are then tagged as untrusted.
+    pub untrusted: bool,
+}
+
+/// Separates the fields of a frame in the output of `defmt-print`.
+const FIELD_SEPARATOR: char = '\u{1f}';
+
+impl DefmtDecoder {
+    /// Starts a new decoding process.
+    ///
+    /// Frames selected by the filter of the options will be written to the
+    /// current standard output in the configured format. If the source is tagged by core, every line of
+    /// output is prefixed with the core index. With [OutputFormat::Json],
+    /// every frame is written as a log [Event]. Printed frames are recorded in
+    /// the given [RecentLogs].
+    ///
+    /// This function will fail if the user did not install the program, e.g. via
+    /// `cargo install defmt-print`.
+    pub fn spawn(
+        elf_file: &Path,
+        source: LogSource,
+        output: OutputFormat,
+        options: &DefmtOptions,
+        recent_logs: Option<RecentLogs>,
+    ) -> anyhow::Result<DefmtDecoder> {
+        // The frames are printed with their fields separated, so they can be
+        // filtered and formatted here
+        let mut spawned_decoder = Command::new("defmt-print")
+            .stdin(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .stdout(Stdio::piped())
+            .env("DEFMT_LOG", "trace")
+            .env("RUST_LOG", "trace")
+            .arg("--log-format")
+            .arg(["{t}", "{L}", "{F}", "{l}", "{m}", "{s}"].join(&FIELD_SEPARATOR.to_string()))
+            .arg("-e")
+            .arg(format!("{}", elf_file.display()))
+            .spawn()
+            .with_context(|| "Cannot spawn 'defmt-print' to decode log frames. Did you run 'cargo install defmt-print'?")?;
+
+        let decoded = spawned_decoder
+            .stdout
+            .take()
+            .expect("Process must be set up with stdout");
+        let options = options.clone();
+        let printer = std::thread::spawn(move || {
+            // Messages may span multiple lines, the lines following a frame
+            // are printed only if the frame itself passed the filter
+            let mut frame_enabled = true;
+            for line in BufReader::new(decoded).lines() {
+                let Ok(line) = line else {
+                    return;
+                };
+
+                let message = match Frame::parse(&line) {
+                    Some(frame) => {
+                        frame_enabled = options.filter.enabled(frame.module, frame.level());
+                        if frame_enabled {
+                            frame.print(source, output, &options);
+                        }
+                        frame.message
+                    }
+                    None => {
+                        if frame_enabled {
+                            print_continuation(source, output, &options, &line);
+                        }
+                        &line
+                    }
+                };
+
+                if let (true, Some(recent_logs)) = (frame_enabled, &recent_logs) {
+                    recent_logs.record(source, message);
+                }
+            }
+        });
+
+        Ok(DefmtDecoder {
+            spawned_decoder,
+            printer: Some(printer),
+        })
+    }
+}
+
+/// A frame as printed by `defmt-print`.
+struct Frame<'a> {
+    timestamp: &'a str,
+    level: &'a str,
+    file: &'a str,
+    line: Option<u32>,
+    module: &'a str,
+    message: &'a str,
+}
+
+impl<'a> Frame<'a> {
+    /// Splits a line into the fields of a frame, returns [None] for lines
+    /// that continue the message of the previous frame.
+    fn parse(line: &'a str) -> Option<Self> {
+        let fields: Vec<&str> = line.splitn(6, FIELD_SEPARATOR).collect();
+        let [timestamp, level, file, line, module, message] = fields.as_slice() else {
+            return None;
+        };
+
+        Some(Frame {
+            timestamp: timestamp.trim(),
+            level: level.trim(),
+            file: file.trim(),
+            line: line.trim().parse().ok(),
+            module: module.trim(),
+            message,
+        })
+    }
+
+    /// Returns the level, frames printed with `defmt::println!` have none.
+    fn level(&self) -> Option<Level> {
+        self.level.parse().ok()
+    }
+
+    fn print(&self, source: LogSource, output: OutputFormat, options: &DefmtOptions) {
+        let file = location::remap_path(&options.remap_path_prefixes, self.file);
+
+        if output == OutputFormat::Json {
+            Event::Log {
+                source,
+                timestamp: Some(self.timestamp).filter(|timestamp| !timestamp.is_empty()),
+                host_time: None,
+                level: Some(self.level).filter(|level| !level.is_empty()),
+                file: Some(&file),
+                line: self.line,
+                module: Some(self.module),
+                message: self.message,
+                untrusted: options.untrusted,
+            }
+            .emit();
+            return;
+        }
+
+        let level = match self.level() {
+            Some(Level::Trace) => self.level.dimmed(),
+            Some(Level::Debug) => self.level.white(),
+            Some(Level::Info) => self.level.green(),
+            Some(Level::Warn) => self.level.yellow(),
+            Some(Level::Error) => self.level.red(),
+            None => self.level.normal(),
+        };
+        let show_location =
+            !(options.hide_dependency_locations && location::is_dependency(self.file));
+        let mut prefix = core_prefix(source);
+        if options.untrusted {
+            prefix.push_str(&format!("{} ", "[untrusted]".yellow()));
+        }
+
+        match &options.format {
+            FrameFormat::Compact | FrameFormat::Verbose => {
+                let header: Vec<String> = [self.timestamp.to_owned(), level.to_string()]
+                    .into_iter()
+                    .filter(|field| !field.is_empty())
+                    .collect();
+                println!("{prefix}{} {}", header.join(" "), self.message);
+
+                if options.format == FrameFormat::Verbose && show_location {
+                    let location = match self.line {
+                        Some(line) => format!("└─ {} @ {file}:{line}", self.module),
+                        None => format!("└─ {} @ {file}", self.module),
+                    };
+                    println!("{prefix}{}", location.dimmed());
+                }
+            }
+            FrameFormat::Template(template) => {
+                let line = match (show_location, self.line) {
+                    (true, Some(line)) => line.to_string(),
+                    _ => String::new(),
+                };
+                let rendered = render_template(
+                    template,
+                    &TemplateFields {
+                        timestamp: self.timestamp,
+                        level: &level.to_string(),
+                        message: self.message,
+                        file: if show_location { &file } else { "" },
+                        line: &line,
+                        module: self.module,
+                    },
+                );
+                println!("{prefix}{}", rendered.trim_end());
+            }
+        }
+    }
+}
+
+/// Prints a line continuing the message of the previous frame.
+fn print_continuation(source: LogSource, output: OutputFormat, options: &DefmtOptions, line: &str) {
+    match output {
+        OutputFormat::Json => Event::Log {
+            source,
+            timestamp: None,
+            host_time: None,
+            level: None,
+            file: None,
+            line: None,
+            module: None,
+            message: line,
+            untrusted: options.untrusted,
+        }
+        .emit(),
+        OutputFormat::Text => println!("{}{line}", core_prefix(source)),
+    }
+}
+
+fn core_prefix(source: LogSource) -> String {
+    match source.core {
+        Some(core) => format!("[core {core}] "),
+        None => String::new(),
+    }
+}
+
+impl Write for DefmtDecoder {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.spawned_decoder
+            .stdin
+            .as_mut()
+            .expect("Process must be set up with stdin")
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.spawned_decoder
+            .stdin
+            .as_mut()
+            .expect("Process must be set up with stdin")
+            .flush()
+    }
+}
+
+impl Drop for DefmtDecoder {
+    fn drop(&mut self) {
+        // Closing stdin makes the process exit once it decoded the remaining
+        // frames, which are printed before returning
+        drop(self.spawned_decoder.stdin.take());
+        if let Err(error) = self.spawned_decoder.wait() {
+            log::warn!("Cannot wait for 'defmt-print' to exit: {error}");
+        }
+        if let Some(printer) = self.printer.take() {
+            let _ = printer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(fields: [&str; 6]) -> String {
+        fields.join(&FIELD_SEPARATOR.to_string())
+    }
+
+    #[test]
+    fn parses_frame() {
+        let line = line([
+            "0.000123",
+            "INFO ",
+            "src/main.rs",
+            "42",
+            "app::driver",
+            "Value: 1 \u{1f} 2",
+        ]);
+        let frame = Frame::parse(&line).unwrap();
+
+        assert_eq!(frame.timestamp, "0.000123");
+        assert_eq!(frame.level, "INFO");
+        assert_eq!(frame.level(), Some(Level::Info));
+        assert_eq!(frame.file, "src/main.rs");
+        assert_eq!(frame.line, Some(42));
+        assert_eq!(frame.module, "app::driver");
+        // The message is the last field, separators within it are kept
+        assert_eq!(frame.message, "Value: 1 \u{1f} 2");
+    }
+
+    #[test]
+    fn parses_frame_without_level_or_line() {
+        let line = line(["", "", "src/main.rs", "", "app", "println"]);
+        let frame = Frame::parse(&line).unwrap();
+
+        assert_eq!(frame.level(), None);
+        assert_eq!(frame.line, None);
+        assert_eq!(frame.message, "println");
+    }
+
+    #[test]
+    fn rejects_continuation_lines() {
+        assert!(Frame::parse("second line of a message").is_none());
+        assert!(Frame::parse("").is_none());
+        assert!(Frame::parse(&["a", "b", "c"].join(&FIELD_SEPARATOR.to_string())).is_none());
+    }
+}
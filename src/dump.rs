@@ -0,0 +1,625 @@
+//! Captures the state of the halted cores to a file and analyzes it later
+//! without the device, see [CoreDump].
+//!
+//! A dump starts with a header followed by records:
+//!
+//! ```text
+//! header    "TPCOREDM" u32 version, u64 time of the dump in microseconds since the unix epoch
+//! core      u8 0, u8 core, u8 state, u16 stop reason length, stop reason
+//! register  u8 1, u8 core, u8 name length, name, u32 value
+//! memory    u8 2, u8 core, u32 address, u32 length, data
+//! ```
+//!
+//! The state is the one the core was in before it was stopped for the dump: 0
+//! for unknown, 1 for running, 2 for halted, 3 for debug and 4 for a custom
+//! state. Memory in the core local segments 0xC and 0xD belongs to the core of
+//! the record, other memory is seen by all cores. All numbers are little
+//! endian.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use colored::Colorize;
+use rust_mcd::core::{Core, CoreState};
+
+use crate::backtrace::access::CoreAccess;
+use crate::backtrace::{FrameAnalysis, MemoryLayout, StacktraceExt};
+use crate::chip_communication::{CoreHaltInfo, HaltInfo};
+use crate::output::{Event, OutputFormat};
+use crate::rtt::locate::MemoryRange;
+
+const MAGIC: &[u8; 8] = b"TPCOREDM";
+const VERSION: u32 = 1;
+
+const CORE_RECORD: u8 = 0;
+const REGISTER_RECORD: u8 = 1;
+const MEMORY_RECORD: u8 = 2;
+
+/// Returns true if the address is in a segment that maps to the local memory
+/// of the accessing core, e.g. its DSPR at 0xD0000000.
+fn is_core_local(address: u32) -> bool {
+    matches!(address >> 28, 0xC | 0xD)
+}
+
+fn state_code(state: CoreState) -> u8 {
+    match state {
+        CoreState::Unknown => 0,
+        CoreState::Running => 1,
+        CoreState::Halted => 2,
+        CoreState::Debug => 3,
+        CoreState::Custom => 4,
+    }
+}
+
+fn state_from_code(code: u8) -> anyhow::Result<CoreState> {
+    Ok(match code {
+        0 => CoreState::Unknown,
+        1 => CoreState::Running,
+        2 => CoreState::Halted,
+        3 => CoreState::Debug,
+        4 => CoreState::Custom,
+        _ => bail!("Unknown core state {code}"),
+    })
+}
+
+/// The registers and state of a core at the time of the dump.
+#[derive(Debug, Clone)]
+pub struct CoreRecord {
+    pub core: usize,
+    /// The state before the core was stopped for the dump.
+    pub state: CoreState,
+    pub stop_reason: String,
+    registers: HashMap<String, u32>,
+}
+
+#[derive(Debug, Clone)]
+struct MemoryBlock {
+    core: usize,
+    address: u32,
+    data: Vec<u8>,
+}
+
+impl MemoryBlock {
+    /// Returns true if the block holds the address as seen by the core.
+    fn contains(&self, core: usize, address: u32) -> bool {
+        (self.core == core || !is_core_local(address))
+            && address >= self.address
+            && ((address - self.address) as usize) < self.data.len()
+    }
+}
+
+/// The registers and memory of the cores of a device.
+#[derive(Debug, Clone)]
+pub struct CoreDump {
+    /// The time of the dump since the unix epoch.
+    pub time: Duration,
+    pub cores: Vec<CoreRecord>,
+    memory: Vec<MemoryBlock>,
+}
+
+impl CoreDump {
+    /// Stops the cores and records their registers, the memory the backtrace
+    /// of each core is built from and the given memory regions.
+    ///
+    /// If the elf file is known, the reads needed for the stack usage, the
+    /// panic message and the variables of each frame are recorded as well.
+    pub fn capture(
+        cores: &[Core],
+        layout: &MemoryLayout,
+        analysis: Option<&FrameAnalysis>,
+        regions: &[MemoryRange],
+    ) -> anyhow::Result<Self> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        let mut records = Vec::new();
+        for (index, core) in cores.iter().enumerate() {
+            let info = core
+                .query_state()
+                .with_context(|| format!("Cannot query state of core {index}"))?;
+            if info.state == CoreState::Running {
+                log::info!("Stopping core {index}");
+                core.stop()
+                    .with_context(|| format!("Cannot stop core {index}"))?;
+            }
+            records.push(CoreRecord {
+                core: index,
+                state: info.state,
+                stop_reason: info.stop_reason().to_owned(),
+                registers: HashMap::new(),
+            });
+        }
+
+        let mut memory = Vec::new();
+        for (record, core) in records.iter_mut().zip(cores) {
+            let index = record.core;
+            log::info!("Recording registers of core {index}");
+            record.registers = read_registers(core)
+                .with_context(|| format!("Cannot read registers of core {index}"))?;
+
+            let recorder = Recorder {
+                core,
+                index,
+                memory: RefCell::new(Vec::new()),
+            };
+            match recorder.read_current(layout) {
+                Ok(mut stacktrace) => {
+                    if let Some(analysis) = analysis {
+                        stacktrace.analyze_frames(&recorder, analysis);
+                    }
+                }
+                Err(error) => log::warn!("Cannot read backtrace of core {index}: {error:#}"),
+            }
+            memory.extend(recorder.memory.into_inner());
+        }
+
+        for region in regions {
+            let start = region.start as u32;
+            let length = (region.end - region.start) as usize;
+
+            // Local memory is recorded for every core, other memory once
+            let readers = if is_core_local(start) { cores.len() } else { 1 };
+            for (index, core) in cores.iter().enumerate().take(readers) {
+                log::info!("Recording {region} through core {index}");
+                let data = core
+                    .read_memory(region.start, length)
+                    .with_context(|| format!("Cannot read {region} through core {index}"))?;
+                memory.push(MemoryBlock {
+                    core: index,
+                    address: start,
+                    data,
+                });
+            }
+        }
+
+        Ok(CoreDump {
+            time,
+            cores: records,
+            memory,
+        })
+    }
+
+    /// Writes the dump to the file, an existing file is truncated.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create dump file {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+
+        file.write_all(MAGIC)?;
+        file.write_u32::<LE>(VERSION)?;
+        file.write_u64::<LE>(self.time.as_micros() as u64)?;
+
+        for record in self.cores.iter() {
+            let stop_reason = record.stop_reason.as_bytes();
+            file.write_u8(CORE_RECORD)?;
+            file.write_u8(record.core as u8)?;
+            file.write_u8(state_code(record.state))?;
+            file.write_u16::<LE>(stop_reason.len() as u16)?;
+            file.write_all(stop_reason)?;
+
+            let mut registers: Vec<_> = record.registers.iter().collect();
+            registers.sort();
+            for (name, value) in registers {
+                file.write_u8(REGISTER_RECORD)?;
+                file.write_u8(record.core as u8)?;
+                file.write_u8(name.len() as u8)?;
+                file.write_all(name.as_bytes())?;
+                file.write_u32::<LE>(*value)?;
+            }
+        }
+
+        for block in self.memory.iter() {
+            file.write_u8(MEMORY_RECORD)?;
+            file.write_u8(block.core as u8)?;
+            file.write_u32::<LE>(block.address)?;
+            file.write_u32::<LE>(block.data.len() as u32)?;
+            file.write_all(&block.data)?;
+        }
+
+        file.flush()
+            .with_context(|| format!("Cannot write dump file {}", path.display()))
+    }
+
+    /// Reads a dump written by [CoreDump::save].
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Cannot open dump file {}", path.display()))?;
+        let mut reader = BufReader::new(file);
+
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("Cannot read dump header")?;
+        if &magic != MAGIC {
+            bail!("{} is not a core dump", path.display());
+        }
+        let version = reader.read_u32::<LE>()?;
+        if version != VERSION {
+            bail!("Unsupported dump version {version}, expected {VERSION}");
+        }
+        let time = Duration::from_micros(reader.read_u64::<LE>()?);
+
+        let mut cores: Vec<CoreRecord> = Vec::new();
+        let mut memory = Vec::new();
+        loop {
+            let record = match reader.read_u8() {
+                Ok(record) => record,
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error).context("Cannot read dump"),
+            };
+
+            match record {
+                CORE_RECORD => {
+                    let core = reader.read_u8()? as usize;
+                    let state = state_from_code(reader.read_u8()?)?;
+                    let mut stop_reason = vec![0; reader.read_u16::<LE>()? as usize];
+                    reader.read_exact(&mut stop_reason)?;
+                    cores.push(CoreRecord {
+                        core,
+                        state,
+                        stop_reason: String::from_utf8_lossy(&stop_reason).into_owned(),
+                        registers: HashMap::new(),
+                    });
+                }
+                REGISTER_RECORD => {
+                    let core = reader.read_u8()? as usize;
+                    let mut name = vec![0; reader.read_u8()? as usize];
+                    reader.read_exact(&mut name)?;
+                    let value = reader.read_u32::<LE>()?;
+
+                    let Some(record) = cores.iter_mut().find(|record| record.core == core) else {
+                        bail!("Register of core {core} precedes the core record");
+                    };
+                    record
+                        .registers
+                        .insert(String::from_utf8_lossy(&name).into_owned(), value);
+                }
+                MEMORY_RECORD => {
+                    let core = reader.read_u8()? as usize;
+                    let address = reader.read_u32::<LE>()?;
+                    let mut data = vec![0; reader.read_u32::<LE>()? as usize];
+                    reader
+                        .read_exact(&mut data)
+                        .context("The dump ends within a memory record")?;
+                    memory.push(MemoryBlock {
+                        core,
+                        address,
+                        data,
+                    });
+                }
+                _ => bail!("Unknown record {record}, the dump is corrupted"),
+            }
+        }
+
+        if cores.is_empty() {
+            bail!("{} does not contain any core", path.display());
+        }
+
+        Ok(CoreDump {
+            time,
+            cores,
+            memory,
+        })
+    }
+
+    /// Returns the registers and memory of a core as if it was read from the
+    /// device.
+    pub fn core<'a>(&'a self, record: &'a CoreRecord) -> DumpedCore<'a> {
+        DumpedCore { dump: self, record }
+    }
+
+    /// Rebuilds the backtrace of every core from the dump.
+    ///
+    /// The first core that was not running is reported as the one that
+    /// halted first.
+    pub fn halt_info(&self, layout: &MemoryLayout, analysis: Option<&FrameAnalysis>) -> HaltInfo {
+        let cores = self
+            .cores
+            .iter()
+            .map(|record| {
+                let core = self.core(record);
+                let mut stacktrace = core
+                    .read_current(layout)
+                    .with_context(|| format!("Cannot read backtrace of core {}", record.core));
+                if let (Ok(stacktrace), Some(analysis)) = (&mut stacktrace, analysis) {
+                    stacktrace.analyze_frames(&core, analysis);
+                }
+                CoreHaltInfo {
+                    core: record.core,
+                    state: record.state,
+                    stop_reason: record.stop_reason.clone(),
+                    stacktrace,
+                }
+            })
+            .collect();
+
+        let halted = self
+            .cores
+            .iter()
+            .find(|record| record.state != CoreState::Running)
+            .unwrap_or(&self.cores[0]);
+
+        HaltInfo {
+            core: halted.core,
+            cores,
+            traces: Vec::new(),
+        }
+    }
+
+    /// Prints the memory of the range, local memory is printed for every core.
+    pub fn print_memory(&self, range: &MemoryRange, output: OutputFormat) {
+        let cores = if is_core_local(range.start as u32) {
+            &self.cores[..]
+        } else {
+            &self.cores[..1]
+        };
+
+        for record in cores {
+            let data = self
+                .core(record)
+                .read_memory(range.start, (range.end - range.start) as usize);
+
+            if output == OutputFormat::Json {
+                Event::Memory {
+                    core: record.core,
+                    address: range.start,
+                    data: data
+                        .as_ref()
+                        .ok()
+                        .map(|data| data.iter().map(|byte| format!("{byte:02X}")).collect()),
+                    error: data.as_ref().err().map(|error| format!("{error:#}")),
+                }
+                .emit();
+                continue;
+            }
+
+            println!(
+                "{}",
+                format!("Memory of core {} at {range}", record.core).red()
+            );
+            let data = match data {
+                Ok(data) => data,
+                Err(error) => {
+                    println!("{}", format!("{error:#}").yellow());
+                    continue;
+                }
+            };
+            for (index, line) in data.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02X}")).collect();
+                let text: String = line
+                    .iter()
+                    .map(|byte| match *byte {
+                        byte @ 0x20..=0x7E => byte as char,
+                        _ => '.',
+                    })
+                    .collect();
+                println!(
+                    "{} {:<47} {}",
+                    format!("{:#010X}", range.start + index as u64 * 16).blue(),
+                    hex.join(" "),
+                    text.dimmed()
+                );
+            }
+        }
+    }
+}
+
+/// Reads all registers the debugger provides for the core, registers that
+/// cannot be read are left out.
+fn read_registers(core: &Core) -> anyhow::Result<HashMap<String, u32>> {
+    let groups = core.register_groups()?;
+    let group = groups.get_group(0)?;
+
+    let mut registers = HashMap::new();
+    for register in group.registers() {
+        match register.read() {
+            Ok(value) => {
+                registers.insert(register.name(), value);
+            }
+            Err(error) => log::debug!("Cannot read {}: {error:#}", register.name()),
+        }
+    }
+    Ok(registers)
+}
+
+/// Forwards reads to the device and records the memory that was read.
+struct Recorder<'a> {
+    core: &'a Core<'a>,
+    index: usize,
+    memory: RefCell<Vec<MemoryBlock>>,
+}
+
+impl CoreAccess for Recorder<'_> {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        self.core.read_register(name)
+    }
+
+    fn read_memory(&self, address: u64, length: usize) -> anyhow::Result<Vec<u8>> {
+        let data = self.core.read_memory(address, length)?;
+        self.memory.borrow_mut().push(MemoryBlock {
+            core: self.index,
+            address: address as u32,
+            data: data.clone(),
+        });
+        Ok(data)
+    }
+}
+
+/// A core of a dump, see [CoreDump::core].
+pub struct DumpedCore<'a> {
+    dump: &'a CoreDump,
+    record: &'a CoreRecord,
+}
+
+impl CoreAccess for DumpedCore<'_> {
+    fn read_register(&self, name: &str) -> anyhow::Result<u32> {
+        self.record
+            .registers
+            .get(name)
+            .copied()
+            .with_context(|| format!("{name} register is not part of the dump"))
+    }
+
+    fn read_memory(&self, address: u64, length: usize) -> anyhow::Result<Vec<u8>> {
+        let core = self.record.core;
+        let mut data = Vec::with_capacity(length);
+
+        // A read may span multiple recorded blocks
+        while data.len() < length {
+            let current = address as u32 + data.len() as u32;
+            let Some(block) = self
+                .dump
+                .memory
+                .iter()
+                .find(|block| block.contains(core, current))
+            else {
+                bail!("Memory at {current:#010X} is not part of the dump");
+            };
+
+            let offset = (current - block.address) as usize;
+            let available = std::cmp::min(block.data.len() - offset, length - data.len());
+            data.extend_from_slice(&block.data[offset..offset + available]);
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump() -> CoreDump {
+        let registers = HashMap::from([
+            ("PC".to_string(), 0x8000_1234),
+            ("A10".to_string(), 0x7000_0100),
+        ]);
+        CoreDump {
+            time: Duration::from_micros(1_700_000_000_123_456),
+            cores: vec![
+                CoreRecord {
+                    core: 0,
+                    state: CoreState::Halted,
+                    stop_reason: "Debug".to_string(),
+                    registers,
+                },
+                CoreRecord {
+                    core: 1,
+                    state: CoreState::Running,
+                    stop_reason: String::new(),
+                    registers: HashMap::from([("PC".to_string(), 0x8000_5678)]),
+                },
+            ],
+            memory: vec![
+                MemoryBlock {
+                    core: 0,
+                    address: 0x9000_0000,
+                    data: vec![1, 2, 3, 4],
+                },
+                MemoryBlock {
+                    core: 0,
+                    address: 0x9000_0004,
+                    data: vec![5, 6],
+                },
+                MemoryBlock {
+                    core: 0,
+                    address: 0xD000_0000,
+                    data: vec![0xA0, 0xA1],
+                },
+                MemoryBlock {
+                    core: 1,
+                    address: 0xD000_0000,
+                    data: vec![0xB0, 0xB1],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trips_through_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("core.dump");
+        let dump = dump();
+        dump.save(&path).unwrap();
+
+        let loaded = CoreDump::load(&path).unwrap();
+        assert_eq!(loaded.time, dump.time);
+        assert_eq!(loaded.cores.len(), 2);
+        for (loaded, record) in loaded.cores.iter().zip(dump.cores.iter()) {
+            assert_eq!(loaded.core, record.core);
+            assert_eq!(state_code(loaded.state), state_code(record.state));
+            assert_eq!(loaded.stop_reason, record.stop_reason);
+            assert_eq!(loaded.registers, record.registers);
+        }
+        assert_eq!(loaded.memory.len(), dump.memory.len());
+        for (loaded, block) in loaded.memory.iter().zip(dump.memory.iter()) {
+            assert_eq!(
+                (loaded.core, loaded.address, &loaded.data),
+                (block.core, block.address, &block.data)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("other.bin");
+        std::fs::write(&path, b"NOTADUMP\x01\0\0\0").unwrap();
+        let error = CoreDump::load(&path).unwrap_err();
+        assert!(error.to_string().contains("is not a core dump"));
+
+        // A dump that ends within a memory record
+        let path = directory.path().join("truncated.dump");
+        dump().save(&path).unwrap();
+        let mut data = std::fs::read(&path).unwrap();
+        data.truncate(data.len() - 1);
+        std::fs::write(&path, data).unwrap();
+        let error = CoreDump::load(&path).unwrap_err();
+        assert!(format!("{error:#}").contains("ends within a memory record"));
+    }
+
+    #[test]
+    fn reads_registers() {
+        let dump = dump();
+        let core = dump.core(&dump.cores[1]);
+        assert_eq!(core.read_register("PC").unwrap(), 0x8000_5678);
+        let error = core.read_register("A10").unwrap_err();
+        assert!(error.to_string().contains("not part of the dump"));
+    }
+
+    #[test]
+    fn reads_memory_across_blocks() {
+        let dump = dump();
+        let core = dump.core(&dump.cores[0]);
+        assert_eq!(core.read_memory(0x9000_0002, 4).unwrap(), vec![3, 4, 5, 6]);
+
+        let error = core.read_memory(0x9000_0004, 4).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Memory at 0x90000006 is not part of the dump"
+        );
+        assert!(core.read_memory(0x8FFF_FFFF, 2).is_err());
+    }
+
+    #[test]
+    fn reads_core_local_memory_of_the_core() {
+        let dump = dump();
+        let first = dump.core(&dump.cores[0]);
+        let second = dump.core(&dump.cores[1]);
+        assert_eq!(first.read_memory(0xD000_0000, 2).unwrap(), vec![0xA0, 0xA1]);
+        assert_eq!(
+            second.read_memory(0xD000_0000, 2).unwrap(),
+            vec![0xB0, 0xB1]
+        );
+
+        // Shared memory recorded by one core is seen by all cores
+        assert_eq!(second.read_memory(0x9000_0000, 2).unwrap(), vec![1, 2]);
+    }
+}
@@ -2,9 +2,72 @@
 
 use std::process::{Command, Stdio};
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use elf::{abi, endian::AnyEndian, ElfBytes};
 use tempfile::TempDir;
 
+/// A part of the firmware image as it is expected in the memory of the device.
+#[derive(Debug, Clone)]
+pub struct FirmwareRegion {
+    /// Where the data is expected in the memory of the device.
+    pub address: u64,
+    pub data: Vec<u8>,
+    /// Names the origin of the region in the elf file.
+    pub description: String,
+}
+
+/// Returns the parts of the firmware image that identify the elf file on the
+/// device.
+///
+/// If the linker placed the build-id note in memory, it identifies the build
+/// on its own and is the only region. Otherwise every non-writable loadable
+/// segment is compared entirely at its load address in flash, which is
+/// slower. Writable segments are skipped, the application changes their
+/// content at runtime.
+pub fn firmware_regions(elf_data: &[u8]) -> anyhow::Result<Vec<FirmwareRegion>> {
+    let elf =
+        ElfBytes::<'_, AnyEndian>::minimal_parse(elf_data).context("Cannot parse elf file")?;
+
+    if let Some(build_id) = elf
+        .section_header_by_name(".note.gnu.build-id")
+        .context("Cannot parse section headers of elf file")?
+    {
+        if build_id.sh_flags & abi::SHF_ALLOC as u64 != 0 && build_id.sh_addr != 0 {
+            let (data, _) = elf
+                .section_data(&build_id)
+                .context("Cannot read build-id of elf file")?;
+            return Ok(vec![FirmwareRegion {
+                address: build_id.sh_addr,
+                data: data.to_vec(),
+                description: "build-id".to_owned(),
+            }]);
+        }
+    }
+
+    let segments = elf
+        .segments()
+        .context("Elf file does not have program headers")?;
+    let mut regions = Vec::new();
+    for (index, segment) in segments.iter().enumerate().filter(|(_, segment)| {
+        segment.p_type == abi::PT_LOAD && segment.p_filesz > 0 && segment.p_flags & abi::PF_W == 0
+    }) {
+        let data = elf
+            .segment_data(&segment)
+            .with_context(|| format!("Cannot read segment {index} of elf file"))?;
+        regions.push(FirmwareRegion {
+            address: segment.p_paddr,
+            data: data.to_vec(),
+            description: format!("read-only segment {index}"),
+        });
+    }
+
+    if regions.is_empty() {
+        bail!("The elf file has neither a build-id in memory nor read-only segments to compare");
+    }
+
+    Ok(regions)
+}
+
 /// Interprets the given data as a hex file and returns it in Intel hex format.
 ///
 /// This function relies on the gnu utility 'objcopy' to be installed on the system.
@@ -59,3 +122,74 @@ pub fn elf_to_hex(data: &[u8]) -> anyhow::Result<String> {
     drop(temporary_directory);
     Ok(hex_file)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a little endian ELF32 file with the given loadable segments,
+    /// given by load address, flags and data.
+    fn elf_file(segments: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let data_offset = 52 + 32 * segments.len() as u32;
+
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(16, 0);
+        for half in [2u16, 44] {
+            elf.extend(half.to_le_bytes());
+        }
+        for word in [1u32, 0x8000_0000, 52, 0, 0] {
+            elf.extend(word.to_le_bytes());
+        }
+        for half in [52u16, 32, segments.len() as u16, 40, 0, 0] {
+            elf.extend(half.to_le_bytes());
+        }
+
+        let mut offset = data_offset;
+        for (address, flags, data) in segments {
+            let size = data.len() as u32;
+            for word in [
+                abi::PT_LOAD,
+                offset,
+                *address,
+                *address,
+                size,
+                size,
+                *flags,
+                4,
+            ] {
+                elf.extend(word.to_le_bytes());
+            }
+            offset += size;
+        }
+        for (_, _, data) in segments {
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    #[test]
+    fn compares_read_only_segments_entirely() {
+        let code = vec![0xAB; 4096];
+        let elf = elf_file(&[
+            (0x8000_0000, abi::PF_R | abi::PF_X, &code),
+            (0x8000_2000, abi::PF_R | abi::PF_W, &[1, 2, 3, 4]),
+            (0x8000_3000, abi::PF_R, &[5, 6]),
+        ]);
+
+        let regions = firmware_regions(&elf).unwrap();
+        let regions: Vec<_> = regions
+            .iter()
+            .map(|region| (region.address, region.data.as_slice()))
+            .collect();
+        assert_eq!(
+            regions,
+            [(0x8000_0000, code.as_slice()), (0x8000_3000, &[5, 6][..])]
+        );
+    }
+
+    #[test]
+    fn rejects_image_without_read_only_data() {
+        let elf = elf_file(&[(0x7000_0000, abi::PF_R | abi::PF_W, &[1, 2])]);
+        assert!(firmware_regions(&elf).is_err());
+    }
+}
@@ -2,31 +2,77 @@
 #![cfg_attr(target_os = "linux", allow(dead_code))]
 
 use crate::chip_communication::DeviceSelection;
+use crate::defmt::filter::LogFilter;
+use crate::defmt::format::FrameFormat;
+use crate::defmt::location::PathRemap;
+use crate::defmt::DefmtOptions;
+use crate::output::OutputFormat;
+use crate::reset::ResetStrategy;
+use crate::rtt::locate::{self, CoreBlockMapping, MemoryRange};
+use crate::rtt::sink::RttFormat;
+use crate::rtt::{ChannelRoute, InputRoute};
 use anyhow::{bail, Context};
 use clap::Parser;
 use env_logger::{Builder, Target};
 use log::LevelFilter;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 pub mod backtrace;
 mod chip_communication;
 pub mod das;
 pub mod defmt;
+pub mod dump;
 pub mod elf;
 pub mod flash;
+pub mod output;
+pub mod reset;
+pub mod rtt;
+pub mod trace;
 
 /// Simple program to flash and interface with tricore chips.
 #[derive(Parser, Debug)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+
     /// Set when flashing should be skipped.
     #[arg(long, default_value_t = false)]
     no_flash: bool,
 
+    /// Set to stream RTT data even if the firmware on the device does not
+    /// match the elf file.
+    ///
+    /// The firmware is compared whenever flashing is skipped. By default a
+    /// mismatch is an error, with this flag decoded defmt frames are tagged as
+    /// untrusted instead, as they may be decoded incorrectly.
+    #[arg(long, default_value_t = false)]
+    ignore_elf_mismatch: bool,
+
     /// Set this flag to print a list of available devices and exit.
     #[arg(long, default_value_t = false)]
     list_devices: bool,
 
+    /// Set this flag to print the reset classes supported by the device and exit.
+    #[arg(long, default_value_t = false)]
+    list_reset_classes: bool,
+
+    /// Reset class used to reset the device before streaming RTT data.
+    ///
+    /// The class is selected by matching the given name against the descriptions
+    /// reported by `--list-reset-classes`, e.g. "system" or "application". Use
+    /// "none" to not reset the device at all. When not specified, the first
+    /// reported reset class is used.
+    #[arg(long)]
+    reset: Option<ResetStrategy>,
+
+    /// Set to attach to an already running application.
+    ///
+    /// The device is neither flashed, reset nor halted. Streaming starts at the
+    /// current write position of the RTT block that the application initialized.
+    #[arg(long, default_value_t = false, conflicts_with = "reset")]
+    attach: bool,
+
     /// Target device to be used.
     ///
     /// When not specified, the tool will pick an available device if exactly one
@@ -42,9 +88,252 @@ struct Args {
     #[arg(short, long)]
     cores: Option<u8>,
 
+    /// Routes an RTT up channel to a sink, in the form `<channel>=<sink>`.
+    ///
+    /// Available sinks are `defmt` to decode defmt frames, `terminal` to print
+    /// the raw data, `file:<path>` to write it to a file and `tcp:<address>:<port>`
+    /// to serve it to a TCP client. `text` prints the data line by line with
+    /// timestamps and the channel name. May be given multiple times. When not
+    /// specified, channel 0 is handled as given by `--rtt-format`.
+    #[arg(long = "rtt-channel")]
+    rtt_channels: Vec<ChannelRoute>,
+
+    /// Format of the data on RTT channel 0.
+    ///
+    /// Use `text` for firmware that writes plain text, e.g. with `printf`.
+    #[arg(long, value_enum, default_value_t = RttFormat::Defmt, conflicts_with = "rtt_channels")]
+    rtt_format: RttFormat,
+
+    /// Writes data to an RTT down channel, in the form `<channel>=<source>`.
+    ///
+    /// Available sources are `stdin` to forward the terminal input and
+    /// `tcp:<address>:<port>` to forward the data sent by a TCP client. May be
+    /// given multiple times.
+    #[arg(long = "rtt-input")]
+    rtt_inputs: Vec<InputRoute>,
+
+    /// Address of the RTT control block, e.g. `0x70001000`.
+    ///
+    /// When not specified, the address is taken from the control block symbol
+    /// in the elf file.
+    #[arg(long, value_parser = locate::parse_address, conflicts_with_all = ["rtt_symbol", "rtt_scan"])]
+    rtt_address: Option<u64>,
+
+    /// Name of the RTT control block symbol in the elf file.
+    #[arg(long, default_value = locate::DEFAULT_SYMBOL)]
+    rtt_symbol: String,
+
+    /// Memory range searched for the RTT control block if the elf file does not
+    /// provide the symbol, in the form `<start>..<end>` or `<start>+<size>`.
+    ///
+    /// May be given multiple times, e.g. to scan the DSPRs of all cores and the LMU.
+    #[arg(long)]
+    rtt_scan: Vec<MemoryRange>,
+
+    /// RTT control block of a secondary core, in the form `<core>=<address>`
+    /// or `<core>=<symbol>`.
+    ///
+    /// Each control block is streamed with the same channel routes, the output
+    /// is tagged with the core index. Symbols following the convention
+    /// `_SEGGER_RTT_CPU<n>` are picked up without this option. May be given
+    /// multiple times.
+    #[arg(long = "rtt-core")]
+    rtt_cores: Vec<CoreBlockMapping>,
+
+    /// Minimum interval in milliseconds between two polls of the RTT buffers.
+    ///
+    /// Polling happens at this interval while the application writes data and
+    /// slows down while it is idle.
+    #[arg(long, default_value_t = 0)]
+    rtt_poll_min: u64,
+
+    /// Maximum interval in milliseconds between two polls of the RTT buffers.
+    #[arg(long, default_value_t = 50)]
+    rtt_poll_max: u64,
+
+    /// Time in milliseconds to collect remaining RTT data after the device halted.
+    ///
+    /// The other active cores keep running meanwhile, they are stopped afterwards
+    /// to print their backtraces.
+    #[arg(long, default_value_t = 300)]
+    rtt_drain: u64,
+
+    /// Records the raw RTT data with timestamps to the given file.
+    ///
+    /// The capture can be decoded later with the `decode` subcommand.
+    #[arg(long)]
+    save_rtt: Option<PathBuf>,
+
+    /// Set to record the program flow with the on-chip trace unit and print the
+    /// most recently executed code next to the backtrace when the device halts.
+    #[arg(long, default_value_t = false)]
+    trace: bool,
+
+    /// Number of trace frames to decode per core when tracing is enabled.
+    #[arg(long, default_value_t = 64)]
+    trace_depth: u32,
+
+    /// Set to print the saved context of each frame in backtraces, i.e. the
+    /// CSA address, the PCXI link, the decoded PSW and the saved registers.
+    #[arg(long, default_value_t = false)]
+    backtrace_verbose: bool,
+
+    /// Number of source lines printed before and after the line of each frame
+    /// in backtraces, 0 disables the source code.
+    ///
+    /// The source files are read from the paths in the elf file after applying
+    /// `--remap-path-prefix`, frames without readable source are skipped.
+    #[arg(long, default_value_t = 2)]
+    backtrace_source_lines: usize,
+
+    /// Set to print the arguments and local variables of each frame in
+    /// backtraces, read with the DWARF debug information of the elf file.
+    ///
+    /// Values held in registers are only available if the registers were
+    /// saved, which is not the case for most registers of calling functions.
+    #[arg(long, default_value_t = false)]
+    locals: bool,
+
+    /// Symbol of a trap vector table in the elf file, used if the BTV register
+    /// of a core cannot be read. May be given multiple times.
+    #[arg(long = "trap-table-symbol", default_values = backtrace::vector::DEFAULT_TRAP_TABLE_SYMBOLS)]
+    trap_table_symbols: Vec<String>,
+
+    /// Symbol of an interrupt vector table in the elf file, used if the BIV
+    /// register of a core cannot be read. May be given multiple times.
+    #[arg(
+        long = "interrupt-table-symbol",
+        default_values = backtrace::vector::DEFAULT_INTERRUPT_TABLE_SYMBOLS
+    )]
+    interrupt_table_symbols: Vec<String>,
+
+    /// Symbols of the first and past the last byte of a CSA region in the elf
+    /// file, given as `BEGIN:END`. May be given multiple times.
+    ///
+    /// Links of the CSA chain and the free context list outside all regions
+    /// are reported as corrupted. Nothing is checked if no region is found.
+    #[arg(
+        long = "csa-region-symbols",
+        default_values = backtrace::region::DEFAULT_CSA_REGION_SYMBOLS
+    )]
+    csa_region_symbols: Vec<backtrace::region::RegionSymbols>,
+
+    /// Symbols of the lowest and past the highest address of a user stack in
+    /// the elf file, given as `BEGIN:END`. May be given multiple times.
+    ///
+    /// The stack pointer of each core is checked against the stacks when the
+    /// device halts.
+    #[arg(
+        long = "user-stack-symbols",
+        default_values = backtrace::stack::DEFAULT_USER_STACK_SYMBOLS
+    )]
+    user_stack_symbols: Vec<backtrace::region::RegionSymbols>,
+
+    /// Symbols of the lowest and past the highest address of an interrupt
+    /// stack in the elf file, given as `BEGIN:END`. May be given multiple times.
+    #[arg(
+        long = "interrupt-stack-symbols",
+        default_values = backtrace::stack::DEFAULT_INTERRUPT_STACK_SYMBOLS
+    )]
+    interrupt_stack_symbols: Vec<backtrace::region::RegionSymbols>,
+
+    /// The word the application fills its stacks with at startup, e.g.
+    /// `0xDEADBEEF`. If set, the stacks are scanned for the high-water mark
+    /// when the device halts.
+    #[arg(long, value_parser = backtrace::stack::parse_fill_pattern)]
+    stack_fill_pattern: Option<u32>,
+
     /// Configures the log level.
     #[arg(short, long, value_enum, required = false, default_value_t = LogLevel::Warn)]
     log_level: LogLevel,
+
+    /// Filters the decoded defmt frames by module path and level, e.g.
+    /// `my_app=info,driver::can=trace`.
+    ///
+    /// Uses the syntax of `RUST_LOG`, frames of modules without a matching
+    /// directive are printed. The filter is applied on the host, the firmware
+    /// does not need to be rebuilt and captures still contain all frames.
+    #[arg(long, global = true)]
+    defmt_filter: Option<LogFilter>,
+
+    /// Layout of decoded defmt frames: `verbose`, `compact` or a template.
+    ///
+    /// `verbose` prints the module and location below each frame, `compact`
+    /// omits them. A template prints each frame on a single line with the
+    /// placeholders `{t}` (timestamp), `{L}` (level), `{s}` (message), `{f}`
+    /// (source file), `{l}` (line) and `{m}` (module), e.g. `{t} {L} {s} {f}:{l}`.
+    #[arg(long, global = true, default_value_t = FrameFormat::Verbose)]
+    defmt_format: FrameFormat,
+
+    /// Set to omit the locations of defmt frames logged by dependencies, i.e.
+    /// crates from the cargo registry or git checkouts.
+    #[arg(long, global = true, default_value_t = false)]
+    hide_dependency_locations: bool,
+
+    /// Replaces a prefix of the source file paths in defmt locations, in the
+    /// form `<from>=<to>`.
+    ///
+    /// Maps the paths of the machine that built the elf file to the local
+    /// checkout. May be given multiple times, later mappings take precedence.
+    #[arg(long, global = true)]
+    remap_path_prefix: Vec<PathRemap>,
+
+    /// Format of the output. With json, every log frame, halt, backtrace and
+    /// trace is printed as a JSON object on a single line and diagnostics are
+    /// written to stderr.
+    #[arg(long, value_enum, global = true, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SubCommand {
+    /// Decodes an RTT capture recorded with `--save-rtt`, no device is required.
+    Decode {
+        /// Elf file the application was built from, required to decode defmt data.
+        #[arg(long, value_parser = existing_path)]
+        elf: Option<PathBuf>,
+
+        /// Path to the capture.
+        #[arg(value_parser = existing_path)]
+        capture: PathBuf,
+    },
+    /// Stops the cores of the device and writes their registers and the
+    /// memory their backtraces are built from to a core dump.
+    Dump {
+        /// Path of the dump file.
+        #[arg(long)]
+        out: PathBuf,
+
+        /// Memory range recorded in addition, in the form `<start>..<end>` or
+        /// `<start>+<size>`, e.g. the DSPR of the cores or the LMU.
+        ///
+        /// Ranges in the core local segments 0xC and 0xD are recorded for
+        /// every core. May be given multiple times.
+        #[arg(long = "region")]
+        regions: Vec<MemoryRange>,
+
+        /// Elf file the application was built from. If given, the memory read
+        /// for the stack usage, the panic message and the variables of each
+        /// frame is recorded as well.
+        #[arg(long, value_parser = existing_path)]
+        elf: Option<PathBuf>,
+    },
+    /// Prints the backtraces of a core dump recorded with `dump`, no device is
+    /// required.
+    Analyze {
+        /// Elf file the application was built from.
+        #[arg(long, value_parser = existing_path)]
+        elf: PathBuf,
+
+        /// Memory range printed from the dump, in the form `<start>..<end>` or
+        /// `<start>+<size>`. May be given multiple times.
+        #[arg(long)]
+        memory: Vec<MemoryRange>,
+
+        /// Path to the dump.
+        #[arg(value_parser = existing_path)]
+        dump: PathBuf,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -57,11 +346,32 @@ fn main() -> anyhow::Result<()> {
         LogLevel::Trace => LevelFilter::Trace,
     };
 
+    // Stdout is reserved for events in json mode
+    let log_target = match args.output {
+        OutputFormat::Text => Target::Stdout,
+        OutputFormat::Json => Target::Stderr,
+    };
     Builder::from_default_env()
         .filter_level(log_filter)
-        .target(Target::Stdout)
+        .target(log_target)
         .init();
 
+    let defmt_options = DefmtOptions {
+        filter: args.defmt_filter.clone().unwrap_or_default(),
+        format: args.defmt_format.clone(),
+        hide_dependency_locations: args.hide_dependency_locations,
+        remap_path_prefixes: args.remap_path_prefix.clone(),
+        untrusted: false,
+    };
+
+    if let Some(SubCommand::Decode { elf, capture }) = &args.command {
+        return rtt::capture::decode_capture(capture, elf.as_deref(), args.output, &defmt_options);
+    }
+
+    if let Some(SubCommand::Analyze { elf, memory, dump }) = &args.command {
+        return analyze_dump(dump, elf, memory, &args);
+    }
+
     #[cfg(target_os = "linux")]
     {
         use crate::elf::elf_to_hex;
@@ -79,12 +389,32 @@ fn main() -> anyhow::Result<()> {
             .args(["run", "--init", "--rm", "-t", "-i"]);
 
         let mut tricore_args = Vec::new();
+        if args.ignore_elf_mismatch {
+            tricore_args.push("--ignore-elf-mismatch".to_owned());
+        }
+
         if args.no_flash {
             tricore_args.push("--no-flash".to_owned());
         }
         if args.list_devices {
             tricore_args.push("--list-devices".to_owned());
         }
+        if args.list_reset_classes {
+            tricore_args.push("--list-reset-classes".to_owned());
+        }
+
+        if args.attach {
+            tricore_args.push("--attach".to_owned());
+        }
+
+        if let Some(reset) = &args.reset {
+            tricore_args.push("--reset".to_owned());
+            tricore_args.push(match reset {
+                ResetStrategy::Named(name) => name.clone(),
+                ResetStrategy::Skip => "none".to_owned(),
+                ResetStrategy::FirstAvailable => unreachable!("Cannot be parsed from arguments"),
+            });
+        }
 
         if let Some(device) = &args.device {
             tricore_args.push("--device".to_owned());
@@ -96,6 +426,124 @@ fn main() -> anyhow::Result<()> {
             tricore_args.push(cores.to_string());
         }
 
+        for route in args.rtt_channels.iter() {
+            use crate::rtt::sink::SinkKind;
+
+            let sink = match &route.sink {
+                SinkKind::Defmt => "defmt".to_owned(),
+                SinkKind::Terminal => "terminal".to_owned(),
+                SinkKind::Text => "text".to_owned(),
+                SinkKind::File(path) => format!("file:{}", path.display()),
+                SinkKind::Tcp(address) => {
+                    // Make the port reachable from outside the container
+                    command
+                        .arg("-p")
+                        .arg(format!("{}:{}", address.port(), address.port()));
+                    format!("tcp:{address}")
+                }
+            };
+            tricore_args.push(format!("--rtt-channel={}={sink}", route.channel));
+        }
+
+        for route in args.rtt_inputs.iter() {
+            use crate::rtt::input::InputSource;
+
+            let source = match &route.source {
+                InputSource::Stdin => "stdin".to_owned(),
+                InputSource::Tcp(address) => {
+                    // Make the port reachable from outside the container
+                    command
+                        .arg("-p")
+                        .arg(format!("{}:{}", address.port(), address.port()));
+                    format!("tcp:{address}")
+                }
+            };
+            tricore_args.push(format!("--rtt-input={}={source}", route.channel));
+        }
+
+        match args.rtt_format {
+            RttFormat::Raw => tricore_args.push("--rtt-format=raw".to_owned()),
+            RttFormat::Text => tricore_args.push("--rtt-format=text".to_owned()),
+            RttFormat::Defmt => {}
+        }
+
+        if let Some(address) = args.rtt_address {
+            tricore_args.push(format!("--rtt-address={address:#X}"));
+        }
+
+        if args.rtt_symbol != locate::DEFAULT_SYMBOL {
+            tricore_args.push(format!("--rtt-symbol={}", args.rtt_symbol));
+        }
+
+        for range in args.rtt_scan.iter() {
+            tricore_args.push(format!("--rtt-scan={range}"));
+        }
+
+        for mapping in args.rtt_cores.iter() {
+            use crate::rtt::locate::CoreBlockTarget;
+
+            tricore_args.push(match &mapping.target {
+                CoreBlockTarget::Address(address) => {
+                    format!("--rtt-core={}={address:#X}", mapping.core)
+                }
+                CoreBlockTarget::Symbol(symbol) => {
+                    format!("--rtt-core={}={symbol}", mapping.core)
+                }
+            });
+        }
+
+        if let Some(path) = &args.save_rtt {
+            // The capture is mounted into the container, so it must exist beforehand
+            fs::File::create(path)
+                .with_context(|| format!("Cannot create capture file {}", path.display()))?;
+            let absolute_path = path.canonicalize()?;
+            command.arg("-v").arg(format!(
+                "{}:/root/.wine/drive_c/rtt-capture.bin",
+                absolute_path.display()
+            ));
+            tricore_args.push("--save-rtt=C:\\rtt-capture.bin".to_owned());
+        }
+
+        tricore_args.push(format!("--rtt-poll-min={}", args.rtt_poll_min));
+        tricore_args.push(format!("--rtt-poll-max={}", args.rtt_poll_max));
+        tricore_args.push(format!("--rtt-drain={}", args.rtt_drain));
+
+        if args.backtrace_verbose {
+            tricore_args.push("--backtrace-verbose".to_owned());
+        }
+        if args.locals {
+            tricore_args.push("--locals".to_owned());
+        }
+        tricore_args.push(format!(
+            "--backtrace-source-lines={}",
+            args.backtrace_source_lines
+        ));
+
+        for symbol in args.trap_table_symbols.iter() {
+            tricore_args.push(format!("--trap-table-symbol={symbol}"));
+        }
+        for symbol in args.interrupt_table_symbols.iter() {
+            tricore_args.push(format!("--interrupt-table-symbol={symbol}"));
+        }
+
+        for symbols in args.csa_region_symbols.iter() {
+            tricore_args.push(format!("--csa-region-symbols={symbols}"));
+        }
+        for symbols in args.user_stack_symbols.iter() {
+            tricore_args.push(format!("--user-stack-symbols={symbols}"));
+        }
+        for symbols in args.interrupt_stack_symbols.iter() {
+            tricore_args.push(format!("--interrupt-stack-symbols={symbols}"));
+        }
+        if let Some(pattern) = args.stack_fill_pattern {
+            tricore_args.push(format!("--stack-fill-pattern={pattern:#010X}"));
+        }
+
+        if args.trace {
+            tricore_args.push("--trace".to_owned());
+            tricore_args.push(format!("--trace-depth={}", args.trace_depth));
+        }
+
         match args.log_level {
             LogLevel::Warn => tricore_args.push("--log-level=warn".to_owned()),
             LogLevel::Info => tricore_args.push("--log-level=info".to_owned()),
@@ -103,6 +551,22 @@ fn main() -> anyhow::Result<()> {
             LogLevel::Trace => tricore_args.push("--log-level=trace".to_owned()),
         };
 
+        if let Some(filter) = &args.defmt_filter {
+            tricore_args.push(format!("--defmt-filter={filter}"));
+        }
+
+        tricore_args.push(format!("--defmt-format={}", args.defmt_format));
+        if args.hide_dependency_locations {
+            tricore_args.push("--hide-dependency-locations".to_owned());
+        }
+        for remap in args.remap_path_prefix.iter() {
+            tricore_args.push(format!("--remap-path-prefix={remap}"));
+        }
+
+        if args.output == OutputFormat::Json {
+            tricore_args.push("--output=json".to_owned());
+        }
+
         let temp_dir = match args.elf {
             Some(path) => {
                 if let Ok(absolute_path) = path.canonicalize() {
@@ -124,7 +588,7 @@ fn main() -> anyhow::Result<()> {
                         ihex_path.as_path().to_str().unwrap(),
                         "/root/.wine/drive_c/output.hex"
                     );
-                    println!("-v {}", elf_path_mount);
+                    log::debug!("Mounting {elf_path_mount}");
                     command.arg("-v").arg(elf_path_mount);
 
                     let filename = absolute_path.file_name().unwrap().to_str().unwrap();
@@ -134,7 +598,7 @@ fn main() -> anyhow::Result<()> {
                         absolute_path.as_path().to_str().unwrap(),
                         file_path_in_docker_in_wine
                     );
-                    println!("-v {}", elf_path_mount);
+                    log::debug!("Mounting {elf_path_mount}");
                     command.arg("-v").arg(elf_path_mount);
                     tricore_args.push(format!("C:\\{}", filename));
                     Some(temporary_directory)
@@ -145,12 +609,36 @@ fn main() -> anyhow::Result<()> {
             None => None,
         };
 
-        let mut daemon_command = "RUST_LOG=trace xvfb-run wine64 tricore-probe.exe".to_owned();
-        for arg in tricore_args {
-            daemon_command.push(' ');
-            daemon_command.push_str(&arg);
+        // The subcommand follows the arguments of the main command
+        if let Some(SubCommand::Dump { out, regions, elf }) = &args.command {
+            // The dump is mounted into the container, so it must exist beforehand
+            fs::File::create(out)
+                .with_context(|| format!("Cannot create dump file {}", out.display()))?;
+            let absolute_path = out.canonicalize()?;
+            command.arg("-v").arg(format!(
+                "{}:/root/.wine/drive_c/core-dump.tcd",
+                absolute_path.display()
+            ));
+            tricore_args.push("dump".to_owned());
+            tricore_args.push("--out=C:\\core-dump.tcd".to_owned());
+
+            for region in regions.iter() {
+                tricore_args.push(format!("--region={region}"));
+            }
+            if let Some(elf) = elf {
+                let absolute_path = elf.canonicalize()?;
+                command.arg("-v").arg(format!(
+                    "{}:/root/.wine/drive_c/dump.elf",
+                    absolute_path.display()
+                ));
+                tricore_args.push("--elf=C:\\dump.elf".to_owned());
+            }
         }
-        log::debug!("Running command in docker: {}", daemon_command);
+
+        // The arguments are passed to the shell as positional parameters, so they
+        // reach tricore-probe unchanged whatever quotes or spaces they contain
+        let daemon_command = "RUST_LOG=trace xvfb-run wine64 tricore-probe.exe \"$@\"";
+        log::debug!("Running command in docker: {daemon_command} {tricore_args:?}");
 
         let mut enumerator = udev::Enumerator::new().unwrap();
         enumerator.match_subsystem("usb").unwrap();
@@ -170,7 +658,7 @@ fn main() -> anyhow::Result<()> {
 
         let mut dev_path_param = Vec::new();
         for dev_path in devices {
-            println!("--device {}:{}", dev_path, dev_path);
+            log::debug!("Passing device {dev_path} to the container");
             dev_path_param.push("--device".to_string());
             dev_path_param.push(format!("{}:{}", dev_path, dev_path));
         }
@@ -178,8 +666,8 @@ fn main() -> anyhow::Result<()> {
 
         command
             .arg("veecle/flash-tricore")
-            .args(["bash", "-c"])
-            .arg(daemon_command);
+            .args(["bash", "-c", daemon_command, "tricore-probe"])
+            .args(tricore_args);
         command.status().expect("Failed to run docker command");
         // keep temp dir alive
         drop(temp_dir);
@@ -187,8 +675,6 @@ fn main() -> anyhow::Result<()> {
     #[cfg(not(target_os = "linux"))]
     {
         use crate::chip_communication::ChipCommunication;
-        use colored::Colorize;
-        use defmt::DefmtDecoder;
 
         let mut command_server = ChipCommunication::new()?;
 
@@ -220,28 +706,210 @@ fn main() -> anyhow::Result<()> {
             command_server.connect(None)?;
         }
 
+        if args.list_reset_classes {
+            let reset_classes = command_server.list_reset_classes()?;
+            crate::pretty_print_reset_classes(&reset_classes);
+            return Ok(());
+        }
+
+        if let Some(SubCommand::Dump { out, regions, elf }) = &args.command {
+            // Without the elf file, the CSA chain is followed up to the first
+            // invalid link and the frames are not analyzed
+            let layout = match elf {
+                Some(elf) => backtrace::MemoryLayout::resolve(
+                    elf,
+                    &args.csa_region_symbols,
+                    &args.user_stack_symbols,
+                    &args.interrupt_stack_symbols,
+                    args.stack_fill_pattern,
+                ),
+                None => backtrace::MemoryLayout::default(),
+            };
+            // The variables are recorded in any case, whether they are shown
+            // is decided when analyzing the dump
+            let analysis = elf
+                .as_deref()
+                .and_then(|elf| backtrace::FrameAnalysis::load(elf, false))
+                .map(|analysis| backtrace::FrameAnalysis {
+                    locals: true,
+                    ..analysis
+                });
+
+            let dump = command_server.dump(args.cores, &layout, analysis.as_ref(), regions)?;
+            dump.save(out)?;
+            println!(
+                "Wrote registers and memory of {} cores to {}",
+                dump.cores.len(),
+                out.display()
+            );
+            return Ok(());
+        }
+
         if let Some(elf) = args.elf {
             log::debug!("Elf file is {}", elf.display());
-            if args.no_flash {
-                log::warn!("Flashing skipped - this might lead to malformed defmt data!")
+            let flash = !args.attach && !args.no_flash;
+            if args.attach {
+                log::info!("Attaching to running application, flashing skipped")
+            } else if args.no_flash {
+                log::info!("Flashing skipped")
             } else {
                 command_server
                     .flash_elf(elf.as_path())
                     .context("Cannot flash elf file")?;
             }
 
-            let mut defmt_decoder = DefmtDecoder::spawn(elf.as_path())?;
+            let mut defmt_options = defmt_options;
+            if !flash {
+                let mismatches = command_server
+                    .verify_elf(elf.as_path())
+                    .context("Cannot compare the firmware on the device with the elf file")?;
+                if !mismatches.is_empty() {
+                    let differences: Vec<_> = mismatches
+                        .iter()
+                        .map(|region| format!("{} at {:#X}", region.description, region.address))
+                        .collect();
+                    if !args.ignore_elf_mismatch {
+                        bail!(
+                            "The firmware on the device does not match {} ({} differ), flash it \
+                            or pass --ignore-elf-mismatch",
+                            elf.display(),
+                            differences.join(", ")
+                        );
+                    }
+
+                    log::error!(
+                        "The firmware on the device does not match {} ({} differ), decoded \
+                        frames are tagged as untrusted",
+                        elf.display(),
+                        differences.join(", ")
+                    );
+                    defmt_options.untrusted = true;
+                } else {
+                    log::info!("The firmware on the device matches the elf file");
+                }
+            }
+
+            if args.rtt_poll_min > args.rtt_poll_max {
+                bail!("The minimum RTT poll interval exceeds the maximum");
+            }
+            let polling = rtt::poll::PollConfig {
+                min_interval: std::time::Duration::from_millis(args.rtt_poll_min),
+                max_interval: std::time::Duration::from_millis(args.rtt_poll_max),
+                drain_time: std::time::Duration::from_millis(args.rtt_drain),
+            };
+
+            let locator = rtt::RttLocator {
+                address: args.rtt_address,
+                symbol: args.rtt_symbol,
+                scan_ranges: args.rtt_scan,
+                core_blocks: args.rtt_cores,
+            };
+            let location = locator.resolve(elf.as_path())?;
+            let core_block_addresses = locator.resolve_core_blocks(elf.as_path())?;
+
+            // Output is only tagged by core if there is more than one control block
+            let main_core = (!core_block_addresses.is_empty()).then_some(0);
 
-            let backtrace = command_server.read_rtt(
-                defmt_decoder.rtt_control_block_address(),
-                &mut defmt_decoder,
+            let routes = if args.rtt_channels.is_empty() {
+                ChannelRoute::default_routes(args.rtt_format)
+            } else {
+                args.rtt_channels
+            };
+            // The printed lines are kept to tell if the application logged a panic
+            let recent_logs = output::RecentLogs::default();
+            let outputs = routes
+                .iter()
+                .map(|route| {
+                    route.open(
+                        elf.as_path(),
+                        main_core,
+                        args.output,
+                        &defmt_options,
+                        &recent_logs,
+                    )
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            let core_blocks = core_block_addresses
+                .into_iter()
+                .map(|(core, address)| {
+                    Ok(rtt::CoreRttBlock {
+                        core,
+                        address,
+                        outputs: routes
+                            .iter()
+                            .map(|route| {
+                                route.open(
+                                    elf.as_path(),
+                                    Some(core),
+                                    args.output,
+                                    &defmt_options,
+                                    &recent_logs,
+                                )
+                            })
+                            .collect::<anyhow::Result<_>>()?,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?;
+
+            let inputs = args
+                .rtt_inputs
+                .iter()
+                .map(InputRoute::open)
+                .collect::<anyhow::Result<_>>()?;
+
+            let config = rtt::RttConfig {
+                location,
+                reset: args.reset.unwrap_or_default(),
+                attach: args.attach,
+                outputs,
+                inputs,
+                core_blocks,
+                polling,
+                capture: args
+                    .save_rtt
+                    .as_deref()
+                    .map(rtt::capture::CaptureFile::create)
+                    .transpose()?,
+                output: args.output,
+            };
+
+            let analysis = backtrace::FrameAnalysis::load(elf.as_path(), args.locals);
+            let layout = backtrace::MemoryLayout::resolve(
+                elf.as_path(),
+                &args.csa_region_symbols,
+                &args.user_stack_symbols,
+                &args.interrupt_stack_symbols,
+                args.stack_fill_pattern,
+            );
+
+            let halt_info = command_server.read_rtt(
+                config,
                 args.cores,
+                args.trace.then_some(args.trace_depth),
+                &layout,
+                analysis.as_ref(),
+                &recent_logs,
             )?;
 
-            let backtrace_info = backtrace.addr2line(elf.as_path())?;
+            let vector_symbols = backtrace::vector::VectorTableSymbols {
+                trap: args.trap_table_symbols,
+                interrupt: args.interrupt_table_symbols,
+            };
+
+            let backtrace_options = backtrace::BacktraceOptions {
+                verbose: args.backtrace_verbose,
+                source_lines: args.backtrace_source_lines,
+                remap_path_prefixes: args.remap_path_prefix.clone(),
+            };
 
-            println!("{}", "Device halted, backtrace as follows".red());
-            backtrace_info.log_stdout();
+            print_halt_info(
+                &halt_info,
+                elf.as_path(),
+                args.output,
+                &vector_symbols,
+                &backtrace_options,
+            )?;
         } else {
             log::warn!("Nothing to do here without elf")
         }
@@ -257,6 +925,164 @@ enum LogLevel {
     Trace,
 }
 
+/// Prints the backtrace of every core after the device halted, starting with
+/// the core that halted first.
+fn print_halt_info(
+    halt_info: &chip_communication::HaltInfo,
+    elf: &Path,
+    output: OutputFormat,
+    vector_symbols: &backtrace::vector::VectorTableSymbols,
+    backtrace_options: &backtrace::BacktraceOptions,
+) -> anyhow::Result<()> {
+    use colored::Colorize;
+
+    // The core that halted first is printed first, the others by index
+    let mut cores: Vec<_> = halt_info.cores.iter().collect();
+    cores.sort_by_key(|core| core.core != halt_info.core);
+
+    if output == OutputFormat::Json {
+        // The stop reason of the debugger if it reported one, the state otherwise
+        let reason = match halt_info
+            .cores
+            .iter()
+            .find(|core| core.core == halt_info.core)
+        {
+            Some(core) if !core.stop_reason.is_empty() => core.stop_reason.clone(),
+            Some(core) => format!("{:?}", core.state),
+            None => "Unknown".to_owned(),
+        };
+        output::Event::Halt {
+            core: halt_info.core,
+            reason: &reason,
+        }
+        .emit();
+
+        for core in cores {
+            let state = format!("{:?}", core.state);
+            let stop_reason = Some(core.stop_reason.as_str()).filter(|r| !r.is_empty());
+            let backtrace_info = match &core.stacktrace {
+                Ok(stacktrace) => Some(stacktrace.addr2line(elf, vector_symbols)?),
+                Err(_) => None,
+            };
+            output::Event::Backtrace {
+                core: core.core,
+                state: &state,
+                stop_reason,
+                frames: backtrace_info
+                    .as_ref()
+                    .map(|info| info.frame_events(backtrace_options.verbose))
+                    .unwrap_or_default(),
+                chain_error: backtrace_info.as_ref().and_then(|info| info.chain_error()),
+                free_list: backtrace_info
+                    .as_ref()
+                    .and_then(|info| info.free_list_event()),
+                stacks: backtrace_info
+                    .as_ref()
+                    .map(|info| info.stack_events())
+                    .unwrap_or_default(),
+                panic: core
+                    .stacktrace
+                    .as_ref()
+                    .ok()
+                    .and_then(|stacktrace| stacktrace.panic.as_ref())
+                    .map(|panic| panic.event()),
+                error: core.stacktrace.as_ref().err().map(|e| format!("{e:#}")),
+            }
+            .emit();
+        }
+
+        for (core_index, history) in halt_info.traces.iter() {
+            history.addr2line(elf)?.emit_json(*core_index);
+        }
+        return Ok(());
+    }
+
+    for core in cores {
+        let event = if core.core == halt_info.core {
+            "halted first"
+        } else {
+            "was stopped"
+        };
+        let state = if core.stop_reason.is_empty() {
+            format!("{:?}", core.state)
+        } else {
+            format!("{:?}: {}", core.state, core.stop_reason)
+        };
+        if let Some(panic) = core
+            .stacktrace
+            .as_ref()
+            .ok()
+            .and_then(|stacktrace| stacktrace.panic.as_ref())
+        {
+            panic.log_stdout(core.core);
+        }
+        println!(
+            "{}",
+            format!("Core {} {event} ({state}), backtrace as follows", core.core).red()
+        );
+
+        match &core.stacktrace {
+            Ok(stacktrace) => stacktrace
+                .addr2line(elf, vector_symbols)?
+                .log_stdout(backtrace_options),
+            Err(error) => println!("{}", format!("{error:#}").yellow()),
+        }
+    }
+
+    for (core_index, history) in halt_info.traces.iter() {
+        println!(
+            "{}",
+            format!("Executed code on core {core_index} (oldest first)").red()
+        );
+        history.addr2line(elf)?.log_stdout();
+    }
+    Ok(())
+}
+
+/// Prints the backtraces and the requested memory of a core dump, the
+/// backtrace options are the same as for the device.
+fn analyze_dump(
+    dump_file: &Path,
+    elf: &Path,
+    memory: &[MemoryRange],
+    args: &Args,
+) -> anyhow::Result<()> {
+    let dump = dump::CoreDump::load(dump_file)?;
+    log::debug!("Dump was taken at unix time {}s", dump.time.as_secs());
+
+    let analysis = backtrace::FrameAnalysis::load(elf, args.locals);
+    let layout = backtrace::MemoryLayout::resolve(
+        elf,
+        &args.csa_region_symbols,
+        &args.user_stack_symbols,
+        &args.interrupt_stack_symbols,
+        args.stack_fill_pattern,
+    );
+    let halt_info = dump.halt_info(&layout, analysis.as_ref());
+
+    let vector_symbols = backtrace::vector::VectorTableSymbols {
+        trap: args.trap_table_symbols.clone(),
+        interrupt: args.interrupt_table_symbols.clone(),
+    };
+    let backtrace_options = backtrace::BacktraceOptions {
+        verbose: args.backtrace_verbose,
+        source_lines: args.backtrace_source_lines,
+        remap_path_prefixes: args.remap_path_prefix.clone(),
+    };
+    print_halt_info(
+        &halt_info,
+        elf,
+        args.output,
+        &vector_symbols,
+        &backtrace_options,
+    )?;
+
+    for range in memory {
+        dump.print_memory(range, args.output);
+    }
+    Ok(())
+}
+
 fn existing_path(input_path: &str) -> anyhow::Result<PathBuf> {
     PathBuf::from_str(input_path).with_context(|| "Value is not a correct path")
 }
@@ -271,3 +1097,14 @@ fn pretty_print_devices(devices: &[DeviceSelection]) {
         println!("Device {index}: {:?}", scanned_device.info.acc_hw())
     }
 }
+
+fn pretty_print_reset_classes(reset_classes: &[(u8, String)]) {
+    if reset_classes.is_empty() {
+        println!("No reset classes available");
+        return;
+    }
+    println!("Found {} reset classes:", reset_classes.len());
+    for (class, description) in reset_classes.iter() {
+        println!("Reset class {class}: {description:?}")
+    }
+}
@@ -0,0 +1,538 @@
+//! Machine readable output, see [Event].
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+
+/// Format of the output written to stdout.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum OutputFormat {
+    /// Colored text for humans.
+    #[default]
+    Text,
+    /// One JSON object per line and event.
+    Json,
+}
+
+/// Identifies the RTT channel a log event originates from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LogSource {
+    /// Set if the output is tagged by core.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub core: Option<usize>,
+    pub channel: usize,
+}
+
+/// Number of lines kept per source by [RecentLogs].
+const RECENT_LOG_LINES: usize = 32;
+
+/// The most recent lines the application logged, per core.
+///
+/// Sinks record the decoded frames and text lines they print, so the state of
+/// the device can be compared with what the application logged before it
+/// halted. The handle is shared between the sinks of all channels.
+#[derive(Debug, Clone, Default)]
+pub struct RecentLogs {
+    lines: Arc<Mutex<HashMap<Option<usize>, VecDeque<String>>>>,
+}
+
+impl RecentLogs {
+    /// Records a line that was printed for the given source.
+    pub fn record(&self, source: LogSource, line: &str) {
+        let Ok(mut lines) = self.lines.lock() else {
+            return;
+        };
+        let lines = lines.entry(source.core).or_default();
+        if lines.len() == RECENT_LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.to_owned());
+    }
+
+    /// Returns the lines logged by the given core, oldest first.
+    ///
+    /// Output that is not tagged by core may originate from any core, so its
+    /// lines are included as well.
+    pub fn lines(&self, core: usize) -> Vec<String> {
+        let Ok(lines) = self.lines.lock() else {
+            return Vec::new();
+        };
+        [None, Some(core)]
+            .iter()
+            .filter_map(|core| lines.get(core))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+}
+
+/// An event that is written to stdout as a single line of JSON with
+/// [OutputFormat::Json].
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// A decoded defmt frame or a line of text.
+    Log {
+        #[serde(flatten)]
+        source: LogSource,
+        /// Timestamp of the device for defmt frames.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<&'a str>,
+        /// Seconds since streaming started for text lines.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        host_time: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        level: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        line: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        module: Option<&'a str>,
+        message: &'a str,
+        /// Set if the elf file does not match the firmware on the device, so
+        /// the frame may have been decoded incorrectly.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        untrusted: bool,
+    },
+    /// Data of a channel that is forwarded without decoding.
+    Data {
+        #[serde(flatten)]
+        source: LogSource,
+        /// The data with invalid UTF-8 sequences replaced.
+        data: &'a str,
+    },
+    /// A core halted and streaming stopped.
+    Halt { core: usize, reason: &'a str },
+    /// The backtrace of a core after the device halted, innermost frame first.
+    Backtrace {
+        core: usize,
+        /// State of the core, e.g. `Halted` or `Debug`.
+        state: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stop_reason: Option<&'a str>,
+        frames: Vec<FrameEvent<'a>>,
+        /// Set if the CSA link chain ended at a corrupted link.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        chain_error: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        free_list: Option<FreeListEvent>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        stacks: Vec<StackEvent>,
+        /// Set if the core halted in the panic handler.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        panic: Option<PanicEvent>,
+        /// Set if the backtrace of the core cannot be read.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// The most recently executed code of a core, oldest first.
+    Trace {
+        core: usize,
+        truncated: bool,
+        entries: Vec<TraceEntryEvent<'a>>,
+    },
+    /// Memory of a core read from a core dump.
+    Memory {
+        core: usize,
+        address: u64,
+        /// The bytes as hexadecimal digits, not set if the memory is not part
+        /// of the dump.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Transfer statistics of an RTT session.
+    RttSummary {
+        bytes: u64,
+        seconds: f64,
+        polls: u64,
+        average_round_trip_ms: f64,
+        max_round_trip_ms: f64,
+        channels: Vec<ChannelSummaryEvent>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct FrameEvent<'a> {
+    pub address: u32,
+    pub function: &'a str,
+    pub location: &'a str,
+    /// Set if the frame is in the interrupt vector of the given priority.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interrupt_priority: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap: Option<TrapEvent>,
+    /// The context of the frame, only set for verbose backtraces.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<ContextEvent>,
+    /// Arguments and local variables, only set if requested.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<VariableEvent<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariableEvent<'a> {
+    pub name: &'a str,
+    #[serde(rename = "type")]
+    pub type_name: &'a str,
+    pub argument: bool,
+    /// The formatted value, or the reason it is not available in angle
+    /// brackets.
+    pub value: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StackEvent {
+    /// Either `user` or `interrupt`.
+    pub kind: &'static str,
+    /// The value of A10 while the stack was in use.
+    pub pointer: u32,
+    /// The stack region, not set if the pointer is not near any region.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u32>,
+    /// Bytes in use if the pointer is within the region.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub used: Option<u32>,
+    /// Bytes the pointer is below the region.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overflow: Option<u32>,
+    /// Bytes the pointer is above the region.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underflow: Option<u32>,
+    /// Bytes ever used according to the fill pattern.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub high_water: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PanicEvent {
+    /// The static parts of the message, arguments are shown as `{..}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<u32>,
+    /// Set if the application already logged the panic message.
+    pub logged: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FreeListEvent {
+    /// Address of the first free CSA, not set if the list is empty.
+    pub fcx: Option<u32>,
+    pub lcx: Option<u32>,
+    pub free: usize,
+    /// Number of free CSAs before FCX reaches LCX.
+    pub until_depletion: Option<usize>,
+    pub depleted: bool,
+    /// Set if the list ended at a corrupted link.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextEvent {
+    /// Either `upper` or `lower`.
+    pub kind: &'static str,
+    /// Not set if the registers were read from the core.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub csa_address: Option<u32>,
+    pub pcxi: u32,
+    /// Address of the context linked by the PCXI, not set at the end of the chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_csa_address: Option<u32>,
+    pub registers: BTreeMap<&'static str, u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub psw: Option<PswEvent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PswEvent {
+    pub io_privilege: &'static str,
+    pub protection_register_set: u8,
+    /// Not set if call depth counting is disabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_depth: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_depth_limit: Option<u8>,
+    pub interrupt_stack: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrapEvent {
+    pub class: u8,
+    pub class_name: &'static str,
+    pub tin: u8,
+    /// Abbreviation of the TIN as defined by the architecture, e.g. `DSE`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tin_name: Option<&'static str>,
+    /// Status registers describing the cause, e.g. `DEADD` for bus errors.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub registers: BTreeMap<&'static str, u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraceEntryEvent<'a> {
+    /// Start address of an executed block, not set for gaps in the trace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChannelSummaryEvent {
+    #[serde(flatten)]
+    pub source: LogSource,
+    /// Name of the channel as configured by the application.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub bytes: u64,
+    /// Number of times the application potentially lost data, [None] if the
+    /// channel skips messages, which cannot be detected.
+    pub overflows: Option<u64>,
+    /// Number of times the application waited for the host.
+    pub stalls: u64,
+}
+
+impl Event<'_> {
+    /// Writes the event as a single line to stdout.
+    pub fn emit(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => println!("{line}"),
+            Err(error) => log::error!("Cannot serialize {self:?}: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn to_json(event: &Event) -> serde_json::Value {
+        serde_json::to_value(event).unwrap()
+    }
+
+    #[test]
+    fn serializes_defmt_log() {
+        let mut event = Event::Log {
+            source: LogSource {
+                core: None,
+                channel: 0,
+            },
+            timestamp: Some("0.000123"),
+            host_time: None,
+            level: Some("INFO"),
+            file: Some("src/main.rs"),
+            line: Some(42),
+            module: Some("app::driver"),
+            message: "Started",
+            untrusted: false,
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({
+                "type": "log",
+                "channel": 0,
+                "timestamp": "0.000123",
+                "level": "INFO",
+                "file": "src/main.rs",
+                "line": 42,
+                "module": "app::driver",
+                "message": "Started",
+            })
+        );
+
+        if let Event::Log { untrusted, .. } = &mut event {
+            *untrusted = true;
+        }
+        assert_eq!(to_json(&event)["untrusted"], json!(true));
+    }
+
+    #[test]
+    fn serializes_text_log_of_core() {
+        let event = Event::Log {
+            source: LogSource {
+                core: Some(1),
+                channel: 2,
+            },
+            timestamp: None,
+            host_time: Some(1.5),
+            level: None,
+            file: None,
+            line: None,
+            module: None,
+            message: "hello",
+            untrusted: false,
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({
+                "type": "log",
+                "core": 1,
+                "channel": 2,
+                "host_time": 1.5,
+                "message": "hello",
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_data_and_halt() {
+        let event = Event::Data {
+            source: LogSource {
+                core: None,
+                channel: 1,
+            },
+            data: "\u{fffd}ab",
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({ "type": "data", "channel": 1, "data": "\u{fffd}ab" })
+        );
+
+        let event = Event::Halt {
+            core: 0,
+            reason: "Debug",
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({ "type": "halt", "core": 0, "reason": "Debug" })
+        );
+    }
+
+    #[test]
+    fn serializes_memory() {
+        let event = Event::Memory {
+            core: 1,
+            address: 0x7000_0000,
+            data: Some("DEADBEEF".to_string()),
+            error: None,
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({ "type": "memory", "core": 1, "address": 0x7000_0000u32, "data": "DEADBEEF" })
+        );
+
+        let event = Event::Memory {
+            core: 0,
+            address: 0x9000_0000,
+            data: None,
+            error: Some("Memory at 0x90000000 is not part of the dump".to_string()),
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({
+                "type": "memory",
+                "core": 0,
+                "address": 0x9000_0000u32,
+                "error": "Memory at 0x90000000 is not part of the dump",
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_backtrace() {
+        let event = Event::Backtrace {
+            core: 0,
+            state: "Debug",
+            stop_reason: None,
+            frames: vec![FrameEvent {
+                address: 0x8000_0100,
+                function: "main",
+                location: "src/main.rs:10",
+                interrupt_priority: None,
+                trap: None,
+                context: None,
+                variables: Vec::new(),
+            }],
+            chain_error: None,
+            free_list: None,
+            stacks: Vec::new(),
+            panic: None,
+            error: None,
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({
+                "type": "backtrace",
+                "core": 0,
+                "state": "Debug",
+                "frames": [{
+                    "address": 0x8000_0100u32,
+                    "function": "main",
+                    "location": "src/main.rs:10",
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn serializes_rtt_summary() {
+        let event = Event::RttSummary {
+            bytes: 10,
+            seconds: 2.0,
+            polls: 4,
+            average_round_trip_ms: 0.5,
+            max_round_trip_ms: 1.0,
+            channels: vec![ChannelSummaryEvent {
+                source: LogSource {
+                    core: None,
+                    channel: 0,
+                },
+                name: Some("defmt".to_owned()),
+                bytes: 10,
+                overflows: None,
+                stalls: 0,
+            }],
+        };
+        assert_eq!(
+            to_json(&event),
+            json!({
+                "type": "rtt_summary",
+                "bytes": 10,
+                "seconds": 2.0,
+                "polls": 4,
+                "average_round_trip_ms": 0.5,
+                "max_round_trip_ms": 1.0,
+                "channels": [{
+                    "channel": 0,
+                    "name": "defmt",
+                    "bytes": 10,
+                    "overflows": null,
+                    "stalls": 0,
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_recent_lines_per_core() {
+        let recent_logs = RecentLogs::default();
+        let source = |core| LogSource { core, channel: 0 };
+
+        recent_logs.record(source(Some(1)), "core 1");
+        recent_logs.record(source(None), "untagged");
+        for line in 0..RECENT_LOG_LINES + 1 {
+            recent_logs.record(source(Some(0)), &line.to_string());
+        }
+
+        assert_eq!(recent_logs.lines(1), ["untagged", "core 1"]);
+        let lines = recent_logs.lines(0);
+        assert_eq!(lines.len(), RECENT_LOG_LINES + 1);
+        assert_eq!(lines[..2], ["untagged", "1"]);
+    }
+}
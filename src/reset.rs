@@ -0,0 +1,99 @@
+//! Selects the reset that is issued before RTT data is streamed, see [ResetStrategy].
+use std::str::FromStr;
+
+use anyhow::Context;
+use rust_mcd::core::Core;
+use rust_mcd::reset::ResetClass;
+
+/// Determines how the device is reset before RTT data is streamed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ResetStrategy {
+    /// Uses the first reset class that is reported by the device.
+    #[default]
+    FirstAvailable,
+    /// Uses the reset class whose description contains the given name, ignoring
+    /// case.
+    Named(String),
+    /// Does not reset the device, the application is not disturbed.
+    Skip,
+}
+
+impl FromStr for ResetStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => anyhow::bail!("Reset class name must not be empty"),
+            name if name.eq_ignore_ascii_case("none") => Ok(Self::Skip),
+            name => Ok(Self::Named(name.to_owned())),
+        }
+    }
+}
+
+impl ResetStrategy {
+    /// Resolves the reset class of this strategy for the given core.
+    ///
+    /// Returns [None] if the device should not be reset.
+    pub fn select<'a>(&self, core: &'a Core<'a>) -> anyhow::Result<Option<ResetClass<'a>>> {
+        let name = match self {
+            Self::Skip => return Ok(None),
+            Self::FirstAvailable => {
+                let class = core
+                    .get_reset_classes()?
+                    .next()
+                    .context("Device does not report any reset class")?;
+                return Ok(Some(class));
+            }
+            Self::Named(name) => name.to_lowercase(),
+        };
+
+        let mut available = Vec::new();
+        for class in core.get_reset_classes()? {
+            let description = class.get_info()?.description().to_owned();
+            if description.to_lowercase().contains(&name) {
+                return Ok(Some(class));
+            }
+            available.push(description);
+        }
+
+        anyhow::bail!("No reset class matches \"{name}\", available classes are {available:?}")
+    }
+}
+
+/// Returns the index and description of all reset classes of the given core.
+pub fn list_reset_classes(core: &Core) -> anyhow::Result<Vec<(u8, String)>> {
+    core.get_reset_classes()?
+        .map(|class| {
+            let info = class
+                .get_info()
+                .with_context(|| format!("Cannot query info of reset class {}", class.class()))?;
+            Ok((class.class(), info.description().to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(
+            "none".parse::<ResetStrategy>().unwrap(),
+            ResetStrategy::Skip
+        );
+        assert_eq!(
+            "None".parse::<ResetStrategy>().unwrap(),
+            ResetStrategy::Skip
+        );
+        assert_eq!(
+            " NONE ".parse::<ResetStrategy>().unwrap(),
+            ResetStrategy::Skip
+        );
+        assert_eq!(
+            "System Reset".parse::<ResetStrategy>().unwrap(),
+            ResetStrategy::Named("System Reset".to_owned())
+        );
+        assert!("  ".parse::<ResetStrategy>().is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! Records the raw RTT data to a file and replays it later, see [CaptureFile]
+//! and [decode_capture].
+//!
+//! A capture starts with a header followed by records:
+//!
+//! ```text
+//! header  "TPRTTCAP" u32 version, u64 start of the capture in microseconds since the unix epoch
+//! stream  u8 0, u8 core, u8 tagged, u8 channel, u8 format, u16 name length, name
+//! data    u8 1, u64 microseconds since the start, u8 core, u8 channel, u32 length, data
+//! ```
+//!
+//! A stream record describes an up channel before its first data record, its
+//! output is tagged by core if `tagged` is 1. The format is 0 for raw data, 1
+//! for text and 2 for defmt. All numbers are little endian.
+use std::{
+    cell::RefCell,
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::defmt::{DefmtDecoder, DefmtOptions};
+use crate::output::{LogSource, OutputFormat};
+
+use super::sink::{JsonDataSink, RttFormat, SinkKind, TextSink};
+
+const MAGIC: &[u8; 8] = b"TPRTTCAP";
+const VERSION: u32 = 1;
+
+const STREAM_RECORD: u8 = 0;
+const DATA_RECORD: u8 = 1;
+
+/// A capture file that is written while streaming.
+///
+/// The handle can be cloned to share the file between multiple channels.
+#[derive(Clone)]
+pub struct CaptureFile {
+    file: Rc<RefCell<BufWriter<File>>>,
+    start: Instant,
+}
+
+impl CaptureFile {
+    /// Creates the capture file, an existing file is truncated.
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Cannot create capture file {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+
+        let unix_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        file.write_all(MAGIC)?;
+        file.write_u32::<LE>(VERSION)?;
+        file.write_u64::<LE>(unix_time.as_micros() as u64)?;
+
+        Ok(CaptureFile {
+            file: Rc::new(RefCell::new(file)),
+            start: Instant::now(),
+        })
+    }
+
+    /// Returns a sink that records the data of a channel before forwarding it
+    /// to the given sink.
+    ///
+    /// Data of sinks other than defmt and text is replayed as raw data.
+    pub fn record(
+        &self,
+        core: Option<usize>,
+        channel: usize,
+        name: Option<&str>,
+        kind: &SinkKind,
+        inner: Box<dyn Write>,
+    ) -> anyhow::Result<CaptureSink> {
+        let format = match kind {
+            SinkKind::Defmt => 2,
+            SinkKind::Text => 1,
+            _ => 0,
+        };
+        let name = name.unwrap_or_default().as_bytes();
+        let core = core.map(|core| core as u8);
+
+        let mut file = self.file.borrow_mut();
+        file.write_u8(STREAM_RECORD)?;
+        file.write_u8(core.unwrap_or_default())?;
+        file.write_u8(core.is_some() as u8)?;
+        file.write_u8(channel as u8)?;
+        file.write_u8(format)?;
+        file.write_u16::<LE>(name.len() as u16)?;
+        file.write_all(name)?;
+
+        Ok(CaptureSink {
+            capture: self.clone(),
+            core: core.unwrap_or_default(),
+            channel: channel as u8,
+            inner,
+        })
+    }
+}
+
+/// Records all data written to it before forwarding it, see [CaptureFile::record].
+pub struct CaptureSink {
+    capture: CaptureFile,
+    core: u8,
+    channel: u8,
+    inner: Box<dyn Write>,
+}
+
+impl Write for CaptureSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        {
+            let mut file = self.capture.file.borrow_mut();
+            file.write_u8(DATA_RECORD)?;
+            file.write_u64::<LE>(self.capture.start.elapsed().as_micros() as u64)?;
+            file.write_u8(self.core)?;
+            file.write_u8(self.channel)?;
+            file.write_u32::<LE>(buf.len() as u32)?;
+            file.write_all(buf)?;
+        }
+
+        self.inner.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.capture.file.borrow_mut().flush()?;
+        self.inner.flush()
+    }
+}
+
+/// A record of a capture, see the [module documentation](self).
+#[derive(Debug, PartialEq)]
+enum CaptureRecord {
+    Stream {
+        core: u8,
+        tagged: bool,
+        channel: u8,
+        format: RttFormat,
+        name: String,
+    },
+    Data {
+        /// Time since the start of the capture.
+        timestamp: Duration,
+        core: u8,
+        channel: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// Reads the records of a capture.
+struct CaptureReader<R> {
+    reader: R,
+    /// Start of the capture since the unix epoch.
+    start: Duration,
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads and validates the header of the capture.
+    fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("Cannot read capture header")?;
+        if &magic != MAGIC {
+            bail!("Not an rtt capture");
+        }
+        let version = reader.read_u32::<LE>()?;
+        if version != VERSION {
+            bail!("Unsupported capture version {version}, expected {VERSION}");
+        }
+        let start = Duration::from_micros(reader.read_u64::<LE>()?);
+
+        Ok(CaptureReader { reader, start })
+    }
+
+    /// Returns the next record, [None] at the end of the capture.
+    fn next_record(&mut self) -> anyhow::Result<Option<CaptureRecord>> {
+        let reader = &mut self.reader;
+        let record = match reader.read_u8() {
+            Ok(record) => record,
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(error) => return Err(error).context("Cannot read capture"),
+        };
+
+        Ok(Some(match record {
+            STREAM_RECORD => {
+                let core = reader.read_u8()?;
+                let tagged = reader.read_u8()? != 0;
+                let channel = reader.read_u8()?;
+                let format = reader.read_u8()?;
+                let mut name = vec![0; reader.read_u16::<LE>()? as usize];
+                reader.read_exact(&mut name)?;
+                let name = String::from_utf8_lossy(&name).into_owned();
+
+                let format = match format {
+                    0 => RttFormat::Raw,
+                    1 => RttFormat::Text,
+                    2 => RttFormat::Defmt,
+                    _ => bail!("Unknown format {format} of channel {channel}"),
+                };
+                CaptureRecord::Stream {
+                    core,
+                    tagged,
+                    channel,
+                    format,
+                    name,
+                }
+            }
+            DATA_RECORD => {
+                let timestamp = Duration::from_micros(reader.read_u64::<LE>()?);
+                let core = reader.read_u8()?;
+                let channel = reader.read_u8()?;
+                let mut data = vec![0; reader.read_u32::<LE>()? as usize];
+                reader
+                    .read_exact(&mut data)
+                    .context("The capture ends within a data record")?;
+                CaptureRecord::Data {
+                    timestamp,
+                    core,
+                    channel,
+                    data,
+                }
+            }
+            _ => bail!("Unknown record {record}, the capture is corrupted"),
+        }))
+    }
+}
+
+/// Output of a replayed stream.
+enum ReplaySink {
+    /// Text is printed with the timestamps of the capture.
+    Text(TextSink),
+    Other(Box<dyn Write>),
+}
+
+/// Replays a capture file through the decoders of the recorded streams.
+///
+/// The elf file must be the one the application was built from, it is only
+/// required if the capture contains defmt data.
+///
+/// With [OutputFormat::Json], the replayed data is emitted as events. Defmt
+/// frames are printed as configured by the options, so a capture can be
+/// filtered differently than while it was recorded.
+pub fn decode_capture(
+    capture_file: &Path,
+    elf_file: Option<&Path>,
+    output: OutputFormat,
+    defmt: &DefmtOptions,
+) -> anyhow::Result<()> {
+    let file = File::open(capture_file)
+        .with_context(|| format!("Cannot open capture file {}", capture_file.display()))?;
+    let mut reader = CaptureReader::new(BufReader::new(file))
+        .with_context(|| format!("Cannot read capture file {}", capture_file.display()))?;
+    log::debug!("Capture started at unix time {}s", reader.start.as_secs());
+
+    let mut streams = HashMap::new();
+    let mut sinks: HashMap<(u8, u8), ReplaySink> = HashMap::new();
+    while let Some(record) = reader.next_record()? {
+        match record {
+            CaptureRecord::Stream {
+                core,
+                tagged,
+                channel,
+                format,
+                name,
+            } => {
+                streams.insert((core, channel), (format, name, tagged));
+            }
+            CaptureRecord::Data {
+                timestamp,
+                core,
+                channel,
+                data,
+            } => {
+                let sink = match sinks.entry((core, channel)) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        let Some((format, name, tagged)) = streams.get(&(core, channel)) else {
+                            bail!("Data of core {core} channel {channel} was not described");
+                        };
+                        entry.insert(open_replay_sink(
+                            *format,
+                            name,
+                            tagged.then_some(core),
+                            channel,
+                            elf_file,
+                            output,
+                            defmt,
+                        )?)
+                    }
+                };
+
+                match sink {
+                    ReplaySink::Text(sink) => {
+                        sink.set_elapsed(timestamp);
+                        sink.write_all(&data)?;
+                    }
+                    ReplaySink::Other(sink) => sink.write_all(&data)?,
+                }
+            }
+        }
+    }
+
+    for sink in sinks.values_mut() {
+        match sink {
+            ReplaySink::Text(sink) => sink.flush()?,
+            ReplaySink::Other(sink) => sink.flush()?,
+        }
+    }
+
+    Ok(())
+}
+
+fn open_replay_sink(
+    format: RttFormat,
+    name: &str,
+    core: Option<u8>,
+    channel: u8,
+    elf_file: Option<&Path>,
+    output: OutputFormat,
+    defmt: &DefmtOptions,
+) -> anyhow::Result<ReplaySink> {
+    let source = LogSource {
+        core: core.map(usize::from),
+        channel: channel as usize,
+    };
+    Ok(match format {
+        RttFormat::Defmt => {
+            let elf_file =
+                elf_file.context("The capture contains defmt data, an elf file is required")?;
+            ReplaySink::Other(Box::new(DefmtDecoder::spawn(
+                elf_file, source, output, defmt, None,
+            )?))
+        }
+        RttFormat::Text if output == OutputFormat::Json => ReplaySink::Text(TextSink::json(source)),
+        RttFormat::Text => {
+            let name = if name.is_empty() {
+                format!("channel {channel}")
+            } else {
+                name.to_owned()
+            };
+            let label = match core {
+                Some(core) => format!("core {core} {name}"),
+                None => name,
+            };
+            ReplaySink::Text(TextSink::new(label, Box::new(std::io::stdout())))
+        }
+        RttFormat::Raw if output == OutputFormat::Json => {
+            ReplaySink::Other(Box::new(JsonDataSink::new(source)))
+        }
+        RttFormat::Raw => ReplaySink::Other(Box::new(std::io::stdout())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(path: &Path) -> anyhow::Result<Vec<CaptureRecord>> {
+        let mut reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+        let mut records = Vec::new();
+        while let Some(record) = reader.next_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Drops the timestamps, they depend on the time the test took.
+    fn without_time(records: Vec<CaptureRecord>) -> Vec<CaptureRecord> {
+        records
+            .into_iter()
+            .map(|record| match record {
+                CaptureRecord::Data {
+                    core,
+                    channel,
+                    data,
+                    ..
+                } => CaptureRecord::Data {
+                    timestamp: Duration::ZERO,
+                    core,
+                    channel,
+                    data,
+                },
+                stream => stream,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("capture.bin");
+
+        let capture = CaptureFile::create(&path).unwrap();
+        let mut defmt = capture
+            .record(None, 0, None, &SinkKind::Defmt, Box::new(std::io::sink()))
+            .unwrap();
+        let mut text = capture
+            .record(
+                Some(1),
+                2,
+                Some("Terminal"),
+                &SinkKind::Text,
+                Box::new(std::io::sink()),
+            )
+            .unwrap();
+        defmt.write_all(&[1, 2, 3]).unwrap();
+        text.write_all(b"hello\n").unwrap();
+        text.flush().unwrap();
+
+        assert_eq!(
+            without_time(records(&path).unwrap()),
+            [
+                CaptureRecord::Stream {
+                    core: 0,
+                    tagged: false,
+                    channel: 0,
+                    format: RttFormat::Defmt,
+                    name: String::new(),
+                },
+                CaptureRecord::Stream {
+                    core: 1,
+                    tagged: true,
+                    channel: 2,
+                    format: RttFormat::Text,
+                    name: "Terminal".to_owned(),
+                },
+                CaptureRecord::Data {
+                    timestamp: Duration::ZERO,
+                    core: 0,
+                    channel: 0,
+                    data: vec![1, 2, 3],
+                },
+                CaptureRecord::Data {
+                    timestamp: Duration::ZERO,
+                    core: 1,
+                    channel: 2,
+                    data: b"hello\n".to_vec(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn forwards_recorded_data() {
+        let directory = tempfile::tempdir().unwrap();
+        let capture = CaptureFile::create(&directory.path().join("capture.bin")).unwrap();
+
+        let forwarded = tempfile::NamedTempFile::new().unwrap();
+        let mut sink = capture
+            .record(
+                None,
+                1,
+                None,
+                &SinkKind::Terminal,
+                Box::new(forwarded.reopen().unwrap()),
+            )
+            .unwrap();
+        sink.write_all(b"data").unwrap();
+        sink.flush().unwrap();
+
+        assert_eq!(std::fs::read(forwarded.path()).unwrap(), b"data");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = CaptureReader::new(&b"TPCOREDM\x01\x00\x00\x00"[..])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "Not an rtt capture");
+
+        let mut header = MAGIC.to_vec();
+        header.extend(2u32.to_le_bytes());
+        header.extend(0u64.to_le_bytes());
+        assert!(CaptureReader::new(header.as_slice()).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_records() {
+        let mut capture = MAGIC.to_vec();
+        capture.extend(VERSION.to_le_bytes());
+        capture.extend(0u64.to_le_bytes());
+        capture.push(DATA_RECORD);
+        capture.extend(5u64.to_le_bytes());
+        capture.extend([0, 0]);
+        capture.extend(10u32.to_le_bytes());
+        capture.extend([1, 2, 3]);
+
+        let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+        assert!(reader.next_record().is_err());
+
+        let mut capture = capture[..20].to_vec();
+        capture.push(7);
+        let mut reader = CaptureReader::new(capture.as_slice()).unwrap();
+        assert!(reader.next_record().is_err());
+    }
+}
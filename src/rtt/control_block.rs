@@ -0,0 +1,259 @@
+//! Models the SEGGER RTT control block and its channels.
+//!
+//! The layout is based on https://github.com/knurling-rs/defmt/blob/59c14b924815a7185fd0079a74b936dba90c867c/firmware/defmt-rtt/src/lib.rs#L124
+//! and the SEGGER RTT sources:
+//!
+//! ```text
+//! +0   char acID[16]            "SEGGER RTT"
+//! +16  i32  MaxNumUpBuffers
+//! +20  i32  MaxNumDownBuffers
+//! +24  aUp[MaxNumUpBuffers]     channel descriptors, 24 bytes each
+//!      aDown[MaxNumDownBuffers] channel descriptors, 24 bytes each
+//! ```
+use anyhow::{bail, Context};
+use byteorder::ReadBytesExt;
+use rust_mcd::core::Core;
+
+/// The ID at the start of every control block.
+pub const RTT_ID: &[u8; 16] = b"SEGGER RTT\0\0\0\0\0\0";
+
+/// Size of the header before the channel descriptors.
+const HEADER_SIZE: u64 = 24;
+
+/// Size of a single channel descriptor.
+const DESCRIPTOR_SIZE: u64 = 24;
+
+/// Upper bound for the number of channels, protects against reading garbage
+/// when the control block is not initialized properly.
+const MAX_CHANNELS: u32 = 32;
+
+/// Maximum length of a channel name that is read from the device.
+const MAX_NAME_LENGTH: usize = 32;
+
+/// Returns the address of the write offset of the given up channel.
+///
+/// The address of up channels does not depend on the content of the control
+/// block, so this is available before the application initialized it.
+pub fn up_channel_write_offset_addr(base_address: u64, channel: usize) -> u64 {
+    base_address + HEADER_SIZE + channel as u64 * DESCRIPTOR_SIZE + 12
+}
+
+/// Direction of an RTT channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelDirection {
+    /// Target to host.
+    Up,
+    /// Host to target.
+    Down,
+}
+
+/// Operating mode of a channel, stored in the lowest two bits of its flags.
+///
+/// The mode defines how the writing side behaves if the buffer is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelMode {
+    /// Data that does not fit into the buffer is dropped entirely.
+    NoBlockSkip,
+    /// Data is written as far as it fits, the rest is dropped.
+    NoBlockTrim,
+    /// The writer waits until the reader made enough space.
+    BlockIfFull,
+}
+
+impl ChannelMode {
+    pub fn from_flags(flags: u32) -> Self {
+        match flags & 0b11 {
+            0 => Self::NoBlockSkip,
+            1 => Self::NoBlockTrim,
+            _ => Self::BlockIfFull,
+        }
+    }
+}
+
+/// A single channel within the control block.
+///
+/// A channel descriptor has the following layout:
+///
+/// ```text
+/// +0  const char* sName
+/// +4  char*       pBuffer
+/// +8  u32         SizeOfBuffer
+/// +12 u32         WrOff
+/// +16 u32         RdOff
+/// +20 u32         Flags
+/// ```
+#[derive(Debug, Clone)]
+pub struct RttChannel {
+    pub direction: ChannelDirection,
+    pub index: usize,
+    /// Name of the channel, if the application assigned one.
+    pub name: Option<String>,
+    descriptor_address: u64,
+    /// Defines where the ring buffer starts.
+    pub buffer_address: u32,
+    /// Defines the size of the ring buffer.
+    pub size: u32,
+    /// The flags at the time the control block was read.
+    pub flags: u32,
+}
+
+impl RttChannel {
+    pub fn write_offset_addr(&self) -> u64 {
+        self.descriptor_address + 12
+    }
+
+    pub fn read_offset_addr(&self) -> u64 {
+        self.descriptor_address + 16
+    }
+
+    pub fn flags_addr(&self) -> u64 {
+        self.descriptor_address + 20
+    }
+
+    /// Returns true if the application configured a buffer for this channel.
+    pub fn is_configured(&self) -> bool {
+        self.buffer_address != 0 && self.size != 0
+    }
+
+    /// Returns a name for this channel suitable for log output.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{name:?}"),
+            None => format!("{:?} channel {}", self.direction, self.index),
+        }
+    }
+}
+
+#[cfg(test)]
+impl RttChannel {
+    /// Creates an up channel with its descriptor at the given address.
+    pub fn up(descriptor_address: u64, buffer_address: u32, size: u32) -> Self {
+        RttChannel {
+            direction: ChannelDirection::Up,
+            index: 0,
+            name: None,
+            descriptor_address,
+            buffer_address,
+            size,
+            flags: 0,
+        }
+    }
+}
+
+/// The control block as read from the device.
+#[derive(Debug)]
+pub struct RttControlBlock {
+    pub base_address: u64,
+    pub up_channels: Vec<RttChannel>,
+    pub down_channels: Vec<RttChannel>,
+}
+
+impl RttControlBlock {
+    /// Reads and validates the control block at the given address.
+    ///
+    /// The application must have initialized the control block before.
+    pub fn read(core: &Core, base_address: u64) -> anyhow::Result<Self> {
+        let header = core
+            .read_bytes(base_address, HEADER_SIZE as usize)
+            .context("Cannot read rtt control block header")?;
+
+        if &header[..16] != RTT_ID {
+            bail!("No valid rtt control block found at {base_address:#X}");
+        }
+
+        let mut counts = &header[16..];
+        let max_up = counts.read_u32::<byteorder::LE>()?;
+        let max_down = counts.read_u32::<byteorder::LE>()?;
+
+        if max_up > MAX_CHANNELS || max_down > MAX_CHANNELS {
+            bail!(
+                "The rtt control block at {base_address:#X} reports {max_up} up and {max_down} \
+                down channels, it is likely corrupted"
+            );
+        }
+
+        let read_channels = |direction, first_descriptor: u64, count: u32| {
+            (0..count as usize)
+                .map(|index| {
+                    let descriptor_address = first_descriptor + index as u64 * DESCRIPTOR_SIZE;
+                    read_channel(core, direction, index, descriptor_address)
+                })
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+
+        let up_start = base_address + HEADER_SIZE;
+        let down_start = up_start + max_up as u64 * DESCRIPTOR_SIZE;
+
+        Ok(RttControlBlock {
+            base_address,
+            up_channels: read_channels(ChannelDirection::Up, up_start, max_up)?,
+            down_channels: read_channels(ChannelDirection::Down, down_start, max_down)?,
+        })
+    }
+
+    /// Returns the up channel with the given index.
+    pub fn up_channel(&self, index: usize) -> anyhow::Result<&RttChannel> {
+        self.up_channels.get(index).with_context(|| {
+            format!(
+                "Up channel {index} does not exist, the application provides {} up channels",
+                self.up_channels.len()
+            )
+        })
+    }
+
+    /// Returns the down channel with the given index.
+    pub fn down_channel(&self, index: usize) -> anyhow::Result<&RttChannel> {
+        self.down_channels.get(index).with_context(|| {
+            format!(
+                "Down channel {index} does not exist, the application provides {} down channels",
+                self.down_channels.len()
+            )
+        })
+    }
+}
+
+fn read_channel(
+    core: &Core,
+    direction: ChannelDirection,
+    index: usize,
+    descriptor_address: u64,
+) -> anyhow::Result<RttChannel> {
+    let data = core
+        .read_bytes(descriptor_address, DESCRIPTOR_SIZE as usize)
+        .with_context(|| format!("Cannot read descriptor of {direction:?} channel {index}"))?;
+
+    let mut data = data.as_slice();
+    let name_address = data.read_u32::<byteorder::LE>()?;
+    let buffer_address = data.read_u32::<byteorder::LE>()?;
+    let size = data.read_u32::<byteorder::LE>()?;
+    let _write_offset = data.read_u32::<byteorder::LE>()?;
+    let _read_offset = data.read_u32::<byteorder::LE>()?;
+    let flags = data.read_u32::<byteorder::LE>()?;
+
+    let name = if name_address == 0 {
+        None
+    } else {
+        // The name is informational only, so we do not fail if it cannot be read
+        core.read_bytes(name_address as u64, MAX_NAME_LENGTH)
+            .ok()
+            .map(|bytes| {
+                let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+                String::from_utf8_lossy(&bytes[..end]).into_owned()
+            })
+    };
+
+    log::trace!(
+        "Found {direction:?} channel {index} {name:?} with buffer at {buffer_address:#X}, \
+        size {size}, flags {flags:#X}"
+    );
+
+    Ok(RttChannel {
+        direction,
+        index,
+        name,
+        descriptor_address,
+        buffer_address,
+        size,
+        flags,
+    })
+}
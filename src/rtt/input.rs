@@ -0,0 +1,130 @@
+//! Sources for the data of RTT down channels, see [InputRoute].
+use std::{
+    io::Read,
+    net::{SocketAddr, TcpListener},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, Sender},
+};
+
+use anyhow::{bail, Context};
+
+/// Source of the data written to an RTT down channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputSource {
+    /// Forwards the standard input of this process.
+    Stdin,
+    /// Forwards the data received from clients connecting to the given address,
+    /// one client at a time.
+    Tcp(SocketAddr),
+}
+
+impl FromStr for InputSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "stdin" => Ok(Self::Stdin),
+            Some(("tcp", address)) => {
+                Ok(Self::Tcp(address.parse().with_context(|| {
+                    format!("Invalid socket address {address:?}")
+                })?))
+            }
+            _ => bail!("Unknown input {s:?}, expected \"stdin\" or \"tcp:<address>:<port>\""),
+        }
+    }
+}
+
+/// Routes an input source to an RTT down channel.
+///
+/// Parsed from `<channel>=<source>`, e.g. `0=stdin`, see [InputSource] for the
+/// available sources.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputRoute {
+    pub channel: usize,
+    pub source: InputSource,
+}
+
+impl FromStr for InputRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, source) = s
+            .split_once('=')
+            .context("Expected an input route in the form <channel>=<source>")?;
+
+        Ok(InputRoute {
+            channel: channel
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid channel index {channel:?}"))?,
+            source: source.trim().parse()?,
+        })
+    }
+}
+
+impl InputRoute {
+    /// Starts reading from the source of this route in a background thread.
+    pub fn open(&self) -> anyhow::Result<ChannelInput> {
+        let (sender, receiver) = mpsc::channel();
+
+        match &self.source {
+            InputSource::Stdin => {
+                std::thread::spawn(move || forward(std::io::stdin().lock(), &sender));
+            }
+            InputSource::Tcp(address) => {
+                let listener = TcpListener::bind(address)
+                    .with_context(|| format!("Cannot listen on {address}"))?;
+                log::info!("Accepting rtt input on {address}");
+
+                std::thread::spawn(move || {
+                    for client in listener.incoming() {
+                        match client {
+                            Ok(client) => {
+                                log::info!("Input client connected");
+                                if !forward(client, &sender) {
+                                    return;
+                                }
+                                log::info!("Input client disconnected");
+                            }
+                            Err(error) => log::warn!("Cannot accept tcp client: {error}"),
+                        }
+                    }
+                });
+            }
+        }
+
+        Ok(ChannelInput {
+            channel: self.channel,
+            receiver,
+        })
+    }
+}
+
+/// Forwards everything read from the reader, returns false if the receiving
+/// side hung up.
+fn forward(mut reader: impl Read, sender: &Sender<Vec<u8>>) -> bool {
+    let mut buffer = [0u8; 256];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return true,
+            Ok(length) => {
+                if sender.send(buffer[..length].to_vec()).is_err() {
+                    return false;
+                }
+            }
+            Err(error) => {
+                log::warn!("Cannot read rtt input: {error}");
+                return true;
+            }
+        }
+    }
+}
+
+/// An opened [InputRoute].
+///
+/// Every received chunk is treated as a single message when it is written to
+/// the down channel, which matters if the channel does not block when full.
+pub struct ChannelInput {
+    pub channel: usize,
+    pub receiver: Receiver<Vec<u8>>,
+}
@@ -0,0 +1,268 @@
+//! Determines where the RTT control block resides in the device, see [RttLocator].
+use std::{fs, path::Path, str::FromStr};
+
+use anyhow::{bail, Context};
+use elf::{endian::AnyEndian, ElfBytes};
+use rust_mcd::core::Core;
+
+use super::control_block::RTT_ID;
+
+/// Name of the control block symbol used by SEGGER RTT and defmt-rtt.
+pub const DEFAULT_SYMBOL: &str = "_SEGGER_RTT";
+
+/// Upper bound for the size of a single read while scanning.
+const SCAN_CHUNK_SIZE: usize = 0x1000;
+
+/// Parses an address, either decimal or hexadecimal when prefixed with `0x`.
+pub fn parse_address(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let address = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(&hex.replace('_', ""), 16),
+        None => s.replace('_', "").parse(),
+    };
+
+    address.with_context(|| format!("Invalid address {s:?}"))
+}
+
+/// A range of device memory that is searched for the control block.
+///
+/// Parsed from `<start>..<end>` or `<start>+<size>`, e.g. `0x70000000+0x3C000`
+/// for the DSPR of core 0 on a TC37x.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MemoryRange {
+    pub start: u64,
+    /// Exclusive end of the range.
+    pub end: u64,
+}
+
+impl FromStr for MemoryRange {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = if let Some((start, end)) = s.split_once("..") {
+            (parse_address(start)?, parse_address(end)?)
+        } else if let Some((start, size)) = s.split_once('+') {
+            let start = parse_address(start)?;
+            (start, start + parse_address(size)?)
+        } else {
+            bail!("Expected a memory range in the form <start>..<end> or <start>+<size>")
+        };
+
+        if end <= start {
+            bail!("Memory range {start:#X}..{end:#X} is empty");
+        }
+
+        Ok(MemoryRange { start, end })
+    }
+}
+
+impl std::fmt::Display for MemoryRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#X}..{:#X}", self.start, self.end)
+    }
+}
+
+/// Where the control block is expected, as obtained by [RttLocator::resolve].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RttLocation {
+    /// The control block is at a known address.
+    Address(u64),
+    /// The control block must be searched in the device memory, see [scan].
+    Scan(Vec<MemoryRange>),
+}
+
+/// Where the control block of a specific core is found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CoreBlockTarget {
+    Address(u64),
+    Symbol(String),
+}
+
+/// Assigns a control block to a secondary core.
+///
+/// Parsed from `<core>=<address>` or `<core>=<symbol>`, e.g. `1=0x60000100` or
+/// `2=RTT_CONTROL_CPU2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreBlockMapping {
+    pub core: usize,
+    pub target: CoreBlockTarget,
+}
+
+impl FromStr for CoreBlockMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (core, target) = s
+            .split_once('=')
+            .context("Expected a control block in the form <core>=<address|symbol>")?;
+
+        let core = core
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid core index {core:?}"))?;
+        if core == 0 {
+            bail!("The control block of core 0 is located with --rtt-address or --rtt-symbol");
+        }
+
+        let target = target.trim();
+        let target = match parse_address(target) {
+            Ok(address) => CoreBlockTarget::Address(address),
+            Err(_) if !target.is_empty() => CoreBlockTarget::Symbol(target.to_owned()),
+            Err(error) => return Err(error),
+        };
+
+        Ok(CoreBlockMapping { core, target })
+    }
+}
+
+/// Collects the ways to locate the control block given by the user.
+#[derive(Debug, Clone)]
+pub struct RttLocator {
+    /// Explicit address of the control block, takes precedence over everything else.
+    pub address: Option<u64>,
+    /// Name of the control block symbol in the elf file.
+    pub symbol: String,
+    /// Memory searched for the control block if the symbol cannot be found.
+    pub scan_ranges: Vec<MemoryRange>,
+    /// Control blocks of secondary cores given by the user.
+    pub core_blocks: Vec<CoreBlockMapping>,
+}
+
+impl RttLocator {
+    /// Determines the location of the control block for the given elf file.
+    ///
+    /// Scanning is only used if no address is given and the symbol cannot be
+    /// found, e.g. because the elf file is stripped.
+    pub fn resolve(&self, elf_file: &Path) -> anyhow::Result<RttLocation> {
+        if let Some(address) = self.address {
+            log::debug!("Using rtt control block address {address:#X}");
+            return Ok(RttLocation::Address(address));
+        }
+
+        match find_control_block_symbol(elf_file, &self.symbol) {
+            Ok(address) => {
+                log::debug!("Found symbol {} at {address:#X}", self.symbol);
+                Ok(RttLocation::Address(address))
+            }
+            Err(error) if !self.scan_ranges.is_empty() => {
+                log::info!("{error:#}, scanning device memory for the rtt control block");
+                Ok(RttLocation::Scan(self.scan_ranges.clone()))
+            }
+            Err(error) => Err(error).context(
+                "Cannot locate the rtt control block, specify its address or memory ranges to scan",
+            ),
+        }
+    }
+
+    /// Returns the addresses of the control blocks of secondary cores, sorted
+    /// by core index.
+    ///
+    /// By convention, the control block of core `n` is named like the one of
+    /// core 0 with the suffix `_CPU<n>`, e.g. `_SEGGER_RTT_CPU1`. Blocks given
+    /// by the user take precedence over the convention.
+    pub fn resolve_core_blocks(&self, elf_file: &Path) -> anyhow::Result<Vec<(usize, u64)>> {
+        let prefix = format!("{}_CPU", self.symbol);
+        let mut blocks = match find_symbols(elf_file, |name| {
+            name.strip_prefix(&prefix)?.parse::<usize>().ok()
+        }) {
+            Ok(blocks) => blocks,
+            Err(error) => {
+                log::debug!("Cannot look up control blocks of secondary cores: {error:#}");
+                Vec::new()
+            }
+        };
+        blocks.retain(|(core, _)| *core != 0);
+
+        for mapping in self.core_blocks.iter() {
+            let address = match &mapping.target {
+                CoreBlockTarget::Address(address) => *address,
+                CoreBlockTarget::Symbol(symbol) => find_control_block_symbol(elf_file, symbol)?,
+            };
+
+            blocks.retain(|(core, _)| *core != mapping.core);
+            blocks.push((mapping.core, address));
+        }
+
+        blocks.sort_by_key(|(core, _)| *core);
+        blocks.dedup_by_key(|(core, _)| *core);
+        for (core, address) in blocks.iter() {
+            log::debug!("Control block of core {core} is at {address:#X}");
+        }
+
+        Ok(blocks)
+    }
+}
+
+/// Returns the address of the symbol with the given name in the elf file.
+pub fn find_control_block_symbol(elf_file: &Path, symbol_name: &str) -> anyhow::Result<u64> {
+    find_symbols(elf_file, |name| (name == symbol_name).then_some(()))?
+        .first()
+        .map(|(_, address)| *address)
+        .with_context(|| format!("Elf file does not have {symbol_name} symbol"))
+}
+
+/// Returns the address of every symbol in the elf file the filter maps to a
+/// value, along with that value.
+fn find_symbols<T>(
+    elf_file: &Path,
+    filter: impl Fn(&str) -> Option<T>,
+) -> anyhow::Result<Vec<(T, u64)>> {
+    let elf_data = fs::read(elf_file).context("Cannot read elf file")?;
+    let elf =
+        ElfBytes::<'_, AnyEndian>::minimal_parse(&elf_data).context("Cannot parse elf file")?;
+
+    let (symbols, strings) = elf
+        .symbol_table()
+        .with_context(|| "Could not parse symbol table from elf file")?
+        .with_context(|| "Elf file does not have symbol table")?;
+
+    Ok(symbols
+        .iter()
+        .filter_map(|symbol| {
+            let name = strings.get(symbol.st_name as usize).ok()?;
+            Some((filter(name)?, symbol.st_value))
+        })
+        .collect())
+}
+
+/// Searches the given memory ranges for control blocks and returns the address
+/// of every ID found.
+///
+/// Control blocks are word aligned, so only aligned addresses are considered.
+/// The memory is read in chunks that overlap by the size of the ID, so an ID
+/// crossing a chunk boundary is found as well.
+pub fn scan(core: &Core, ranges: &[MemoryRange]) -> anyhow::Result<Vec<u64>> {
+    let chunk_size = std::cmp::min(SCAN_CHUNK_SIZE, core.query_payload_size() as usize);
+    if chunk_size <= RTT_ID.len() {
+        bail!("The maximum payload of {chunk_size} bytes is too small to scan memory");
+    }
+
+    let mut found = Vec::new();
+    for range in ranges {
+        let mut chunk_start = range.start;
+        while chunk_start + RTT_ID.len() as u64 <= range.end {
+            let length = std::cmp::min(chunk_size as u64, range.end - chunk_start) as usize;
+            let chunk = core
+                .read_bytes(chunk_start, length)
+                .with_context(|| format!("Cannot read memory range {range}"))?;
+
+            found.extend(
+                chunk
+                    .windows(RTT_ID.len())
+                    .enumerate()
+                    .filter(|(offset, window)| {
+                        (chunk_start + *offset as u64) % 4 == 0 && window == RTT_ID
+                    })
+                    .map(|(offset, _)| chunk_start + offset as u64),
+            );
+
+            chunk_start += (length - (RTT_ID.len() - 1)) as u64;
+        }
+    }
+
+    // The overlap never yields the same aligned address twice, but adjacent
+    // ranges given by the user might
+    found.dedup();
+
+    Ok(found)
+}
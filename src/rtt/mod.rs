@@ -0,0 +1,836 @@
+//! Implements the host side of SEGGER RTT.
+//!
+//! The data of each up channel in the [RttControlBlock] is forwarded to a sink
+//! as configured by a [ChannelRoute], see [decode_rtt].
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc::TryRecvError;
+
+use anyhow::{bail, Context};
+use byteorder::ReadBytesExt;
+use rust_mcd::breakpoint::TriggerType;
+use rust_mcd::core::{Core, CoreState};
+use rust_mcd::error::EventError;
+
+use crate::output::{ChannelSummaryEvent, Event, LogSource, OutputFormat};
+use crate::reset::ResetStrategy;
+
+use self::capture::CaptureFile;
+use self::control_block::{up_channel_write_offset_addr, ChannelMode, RttChannel, RttControlBlock};
+use self::input::ChannelInput;
+use self::locate::{MemoryRange, RttLocation};
+use self::poll::{AdaptivePoller, PollConfig, PollStatistics};
+use self::reader::UpChannelReader;
+use self::sink::{ChannelOutput, SinkKind, TextSink};
+
+pub mod capture;
+pub mod control_block;
+pub mod input;
+pub mod locate;
+pub mod poll;
+pub mod reader;
+pub mod sink;
+
+pub use self::input::InputRoute;
+pub use self::locate::RttLocator;
+pub use self::sink::ChannelRoute;
+
+/// Configures how [decode_rtt] connects to the application and where the data
+/// of the RTT channels flows.
+pub struct RttConfig {
+    /// Location of the RTT control block in the device.
+    pub location: RttLocation,
+    /// Reset that is issued before streaming, ignored when attaching.
+    pub reset: ResetStrategy,
+    /// Set to attach to the running application without reset or halt.
+    pub attach: bool,
+    /// Sinks for the data of up channels.
+    pub outputs: Vec<ChannelOutput>,
+    /// Sources for the data of down channels.
+    pub inputs: Vec<ChannelInput>,
+    /// Control blocks of secondary cores, streamed along the one above.
+    pub core_blocks: Vec<CoreRttBlock>,
+    /// Timing of the polling loop.
+    pub polling: PollConfig,
+    /// Records the data of all up channels if set.
+    pub capture: Option<CaptureFile>,
+    /// Format of the transfer summary printed when the device halted.
+    pub output: OutputFormat,
+}
+
+/// The control block of a secondary core, see [RttConfig].
+pub struct CoreRttBlock {
+    /// Index of the core, the control block is read through this core.
+    pub core: usize,
+    /// Address of the control block in the device.
+    pub address: u64,
+    /// Sinks for the data of the up channels of this control block.
+    pub outputs: Vec<ChannelOutput>,
+}
+
+/// Decode the rtt data from the up channels of the configured rtt block and
+/// write it to the sinks of the configured outputs, while data of the configured
+/// inputs is written to the down channels.
+///
+/// A main core must be provided through which the RTT data is read from the chip.
+/// Before reading, all cores are reset as determined by the configured
+/// [ResetStrategy] and the function waits for the application to write to the
+/// RTT buffer. When attaching, the device is neither reset nor halted and
+/// streaming starts at the current write index of the already initialized RTT block.
+///
+/// The control blocks of secondary cores are read through their respective core.
+/// Streaming them starts as soon as the application initialized them.
+///
+/// The function will return when the device halts, e.g. when any core (including the
+/// secondary ones) hits a breakpoint. The remaining RTT data is collected before
+/// all active cores are stopped, the returned reason names the core that halted.
+pub fn decode_rtt(
+    core: &mut Core<'_>,
+    secondary_cores: &mut [Core<'_>],
+    active_cores: usize,
+    config: RttConfig,
+) -> anyhow::Result<HaltReason> {
+    let RttConfig {
+        location,
+        reset,
+        attach,
+        outputs,
+        inputs,
+        core_blocks,
+        polling,
+        capture,
+        output,
+    } = config;
+
+    let Some(first_channel) = outputs.iter().map(|output| output.channel).min() else {
+        bail!("No rtt channel is routed to a sink");
+    };
+
+    for block in core_blocks.iter() {
+        if block.core == 0 || block.core > secondary_cores.len() {
+            bail!(
+                "Cannot stream the rtt control block of core {}, the device has {} cores",
+                block.core,
+                secondary_cores.len() + 1
+            );
+        }
+    }
+
+    let rtt_block_address = if attach {
+        for secondary_core in secondary_cores.iter_mut() {
+            secondary_core.download_triggers();
+        }
+
+        let rtt_block_address = match location {
+            RttLocation::Address(address) => address,
+            RttLocation::Scan(ranges) => scan_for_control_block(core, &ranges)?
+                .context("No rtt control block found in the scanned memory")?,
+        };
+
+        log::info!(
+            "Attaching to the running application, expecting rtt block at {:#X}",
+            rtt_block_address
+        );
+        rtt_block_address
+    } else {
+        let reset_class = reset.select(core)?;
+        match reset_class {
+            Some(reset_class) => {
+                log::debug!("Resetting device with {}", reset_class.get_info()?);
+                core.reset(reset_class, true)?;
+
+                for secondary_core in secondary_cores.iter_mut() {
+                    secondary_core.reset(reset_class, true)?;
+                    secondary_core.download_triggers();
+                }
+
+                // The control blocks of the previous run are still in memory, they
+                // must not be mistaken for initialized ones
+                for block in core_blocks.iter() {
+                    log::debug!(
+                        "Invalidating rtt control block of core {} at {:#X}",
+                        block.core,
+                        block.address
+                    );
+                    secondary_cores[block.core - 1].write(block.address, vec![0])?;
+                }
+            }
+            None => {
+                log::info!("Skipping reset, the application keeps running");
+
+                for secondary_core in secondary_cores.iter_mut() {
+                    secondary_core.download_triggers();
+                }
+            }
+        }
+
+        match location {
+            RttLocation::Address(rtt_block_address) => {
+                log::info!(
+                    "Trying to detect segger rtt block at {:#X}",
+                    rtt_block_address
+                );
+
+                wait_for_first_write(
+                    core,
+                    up_channel_write_offset_addr(rtt_block_address, first_channel),
+                )?;
+                rtt_block_address
+            }
+            RttLocation::Scan(ranges) => {
+                wait_for_initialization(core, &ranges, reset_class.is_some())?
+            }
+        }
+    };
+
+    // Best effort to make sure that the address is correct: Reading the control
+    // block validates its ID
+    let rtt_block = RttControlBlock::read(core, rtt_block_address).with_context(|| {
+        if attach {
+            "The rtt control block is malformatted or not yet initialized by the application"
+        } else {
+            "The application wrote to the rtt buffer, but the rtt control block is malformatted"
+        }
+    })?;
+    log::info!("Detected RTT control block");
+
+    for channel in rtt_block
+        .up_channels
+        .iter()
+        .chain(rtt_block.down_channels.iter())
+    {
+        log::debug!(
+            "Found {:?} channel {}: {} with {} bytes",
+            channel.direction,
+            channel.index,
+            channel.display_name(),
+            channel.size
+        );
+    }
+
+    let mut streams = connect_up_channels(core, &rtt_block, outputs, attach, capture.as_ref())?;
+    log::debug!("Host connected");
+
+    let mut writers = Vec::new();
+    for input in inputs {
+        let channel = rtt_block.down_channel(input.channel)?.clone();
+        if !channel.is_configured() {
+            bail!(
+                "Down channel {} is routed to an input, but the application did not configure it",
+                channel.index
+            );
+        }
+
+        writers.push(DownChannelWriter {
+            channel,
+            input: Some(input),
+            pending: VecDeque::new(),
+        });
+    }
+
+    let mut core_blocks: Vec<_> = core_blocks
+        .into_iter()
+        .map(|block| CoreBlockReader {
+            core: block.core,
+            address: block.address,
+            outputs: Some(block.outputs),
+            streams: Vec::new(),
+            capture: capture.clone(),
+        })
+        .collect();
+
+    if !attach && core.query_state()?.state != CoreState::Running {
+        core.run()?;
+    }
+
+    let mut poller = AdaptivePoller::new(polling);
+    loop {
+        let started = poller.start();
+        let mut transferred = read_channels(core, &mut streams)?;
+        transferred += write_channels(core, &mut writers)?;
+
+        /// Checks if the core is still running, if it is not we assume a
+        /// breakpoint was hit.
+        ///
+        /// This function is a bit of a hack to work around lifetime issues
+        /// when borrowing the cores in multiple iterations of the loop.
+        fn should_exit_for_core(
+            core: &mut Core,
+            core_index: usize,
+            accept_reset_event: bool,
+        ) -> Option<anyhow::Result<HaltReason>> {
+            let core_state = core.query_state_gracefully(|e| {
+                accept_reset_event && e.event_error_code() == EventError::Reset
+            });
+
+            let core_state = match core_state {
+                Ok(core_state) => core_state,
+                Err(error) => return Some(Err(error).context("Failed to query core state")),
+            };
+
+            if core_state.state != CoreState::Running {
+                log::trace!("Core {core_index} halted: {core_state:?}");
+                return Some(Ok(HaltReason::DebugHit { core: core_index }));
+            }
+
+            None
+        }
+
+        let secondary_active_cores = active_cores - 1;
+
+        // Only check for exit on active cores
+        let halted = match should_exit_for_core(core, 0, false) {
+            Some(exit_reason) => Some((0, exit_reason)),
+            None => secondary_cores
+                .iter_mut()
+                .take(secondary_active_cores)
+                .enumerate()
+                .find_map(|(secondary_index, core)| {
+                    // FIXME: The core index we give here might be misleading, we can probably
+                    // obtain that information from the core itself
+                    let core_index = secondary_index + 1;
+                    should_exit_for_core(core, core_index, true)
+                        .map(|exit_reason| (core_index, exit_reason))
+                }),
+        };
+
+        if let Some((core_index, exit_reason)) = halted {
+            if exit_reason.is_ok() {
+                log::info!(
+                    "Core {core_index} halted, collecting RTT data for {}ms",
+                    poller.drain_time().as_millis()
+                );
+                std::thread::sleep(poller.drain_time());
+
+                // The other cores are stopped as well, so their state at the time
+                // of the halt can be inspected
+                stop_active_cores(core, secondary_cores, active_cores)?;
+
+                // A single read is limited by the payload size, so the data is
+                // drained until the buffers are empty
+                while read_channels(core, &mut streams)? > 0 {}
+                for block in core_blocks.iter_mut() {
+                    let block_core = &mut secondary_cores[block.core - 1];
+                    while read_core_block(block_core, block, attach)? > 0 {}
+                }
+
+                print_summary(
+                    poller.statistics(),
+                    streams
+                        .iter()
+                        .chain(core_blocks.iter().flat_map(|block| block.streams.iter())),
+                    output,
+                );
+            }
+            return exit_reason.with_context(|| format!("Cannot query state of core {core_index}"));
+        }
+
+        for block in core_blocks.iter_mut() {
+            transferred += read_core_block(&mut secondary_cores[block.core - 1], block, attach)?;
+        }
+
+        poller.finish(started, transferred);
+    }
+}
+
+/// Stops all active cores that are still running.
+fn stop_active_cores(
+    core: &Core,
+    secondary_cores: &[Core],
+    active_cores: usize,
+) -> anyhow::Result<()> {
+    for (core_index, core) in [core]
+        .into_iter()
+        .chain(secondary_cores.iter())
+        .enumerate()
+        .take(active_cores)
+    {
+        if core.query_state()?.state == CoreState::Running {
+            log::debug!("Stopping core {core_index}");
+            core.stop()
+                .with_context(|| format!("Cannot stop core {core_index}"))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Connects the routed up channels of the given control block to their sinks.
+///
+/// When attaching, data written before is skipped, otherwise streaming continues
+/// at the read index of the host.
+fn connect_up_channels(
+    core: &Core,
+    rtt_block: &RttControlBlock,
+    outputs: Vec<ChannelOutput>,
+    attach: bool,
+    capture: Option<&CaptureFile>,
+) -> anyhow::Result<Vec<UpChannelStream>> {
+    let mut streams = Vec::new();
+    for output in outputs {
+        let channel = rtt_block.up_channel(output.channel)?.clone();
+        if !channel.is_configured() {
+            log::warn!(
+                "Up channel {} is routed to a sink, but the application did not configure it",
+                channel.index
+            );
+        }
+
+        if output.kind == SinkKind::Defmt {
+            if attach {
+                // Attaching must not change the behaviour of the running
+                // application, the firmware keeps the mode it chose for a full buffer
+                log::debug!(
+                    "Leaving the flags of {} untouched while attached",
+                    channel.display_name()
+                );
+            } else {
+                // Set the flag that the host is connected
+                core.write(channel.flags_addr(), u32::to_le_bytes(2).to_vec())?;
+            }
+        }
+
+        let read_index = if attach {
+            // Data written before we attached is skipped, we only stream what the
+            // application writes from now on
+            let device_write_index = read_u32(core, channel.write_offset_addr())
+                .with_context(|| "Error while obtaining the device write index")?;
+            log::debug!(
+                "Attached to {} at write index {device_write_index:#X}",
+                channel.display_name()
+            );
+            core.write(
+                channel.read_offset_addr(),
+                u32::to_le_bytes(device_write_index).into(),
+            )?;
+            device_write_index
+        } else {
+            // Continue where the host left off, this is only non-zero if the device was not
+            // reset before
+            read_u32(core, channel.read_offset_addr())
+                .with_context(|| "Cannot obtain the host read index")?
+        };
+
+        let source = LogSource {
+            core: output.core,
+            channel: channel.index,
+        };
+        let mut sink = if output.kind == SinkKind::Text && output.format == OutputFormat::Json {
+            Box::new(TextSink::json(source).record_to(output.recent_logs, source))
+        } else if output.kind == SinkKind::Text {
+            let name = match &channel.name {
+                Some(name) => name.clone(),
+                None => format!("channel {}", channel.index),
+            };
+            let label = match output.core {
+                Some(core) => format!("core {core} {name}"),
+                None => name,
+            };
+            Box::new(TextSink::new(label, output.sink).record_to(output.recent_logs, source))
+        } else {
+            output.sink
+        };
+
+        if let Some(capture) = capture {
+            sink = Box::new(capture.record(
+                output.core,
+                channel.index,
+                channel.name.as_deref(),
+                &output.kind,
+                sink,
+            )?);
+        }
+
+        streams.push(UpChannelStream {
+            reader: UpChannelReader::new(channel, read_index),
+            core: output.core,
+            sink,
+        });
+    }
+
+    Ok(streams)
+}
+
+/// Streams the control block of a secondary core.
+struct CoreBlockReader {
+    core: usize,
+    address: u64,
+    /// Set until the application initialized the control block.
+    outputs: Option<Vec<ChannelOutput>>,
+    streams: Vec<UpChannelStream>,
+    capture: Option<CaptureFile>,
+}
+
+fn read_core_block(
+    core: &mut Core,
+    block: &mut CoreBlockReader,
+    attach: bool,
+) -> anyhow::Result<usize> {
+    if let Some(outputs) = block.outputs.take() {
+        match RttControlBlock::read(core, block.address) {
+            Ok(rtt_block) => {
+                log::info!("Detected RTT control block of core {}", block.core);
+                block.streams =
+                    connect_up_channels(core, &rtt_block, outputs, attach, block.capture.as_ref())
+                        .with_context(|| {
+                            format!("Cannot connect to rtt control block of core {}", block.core)
+                        })?;
+            }
+            Err(error) => {
+                log::trace!("Control block of core {} not ready: {error:#}", block.core);
+                block.outputs = Some(outputs);
+                return Ok(0);
+            }
+        }
+    }
+
+    read_channels(core, &mut block.streams)
+}
+
+/// Scans the given memory for a control block, returns its address if one is found.
+fn scan_for_control_block(core: &Core, ranges: &[MemoryRange]) -> anyhow::Result<Option<u64>> {
+    let found = locate::scan(core, ranges)?;
+    if found.len() > 1 {
+        log::warn!(
+            "Found multiple rtt control blocks at {:X?}, using the first one",
+            found
+        );
+    }
+
+    Ok(found.first().copied())
+}
+
+/// Waits until the application initialized a control block in the given memory
+/// and returns its address.
+///
+/// The memory is not cleared by a reset, so after a reset the IDs left behind
+/// by the previous run are invalidated before the application is started.
+/// Without that, a stale control block of a different application could be
+/// picked up. The core keeps running when this function returns.
+fn wait_for_initialization(
+    core: &Core,
+    ranges: &[MemoryRange],
+    invalidate_stale: bool,
+) -> anyhow::Result<u64> {
+    let ranges_description = ranges
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    log::info!("Scanning {ranges_description} for the segger rtt block");
+
+    if invalidate_stale {
+        for stale_address in locate::scan(core, ranges)? {
+            log::debug!("Invalidating stale rtt control block at {stale_address:#X}");
+            core.write(stale_address, vec![0])?;
+        }
+    }
+
+    if core.query_state()?.state != CoreState::Running {
+        core.run()?;
+    }
+
+    loop {
+        if let Some(address) = scan_for_control_block(core, ranges)? {
+            log::debug!("Found rtt control block at {address:#X}");
+            return Ok(address);
+        }
+
+        if core.query_state()?.state != CoreState::Running {
+            bail!("The device halted before the application initialized the rtt control block");
+        }
+    }
+}
+
+/// Waits until the application writes to the RTT buffer for the first time.
+///
+/// We create a breakpoint that puts the chip into debug mode when the write index
+/// at the given address is changed and then wait for the chip to hit the
+/// breakpoint. The core is halted when this function returns.
+fn wait_for_first_write(core: &Core, write_index_address: u64) -> anyhow::Result<()> {
+    let breakpoint_on_write_change =
+        core.create_breakpoint(TriggerType::RW, write_index_address, 4)?;
+    core.download_triggers();
+    if core.query_state()?.state != CoreState::Running {
+        core.run()?;
+    }
+
+    loop {
+        let state = core.query_state()?;
+        if state.state != CoreState::Running {
+            log::trace!("Breakpoint hit, checking validity of structure");
+            break;
+        }
+    }
+
+    // Remove the breakpoint, we do busy looping to acquire the rtt data
+    breakpoint_on_write_change.remove()
+}
+
+/// Represents the reason why decoding rtt data failed.
+#[derive(Debug)]
+pub enum HaltReason {
+    /// The core with the given index halted, e.g. at a breakpoint. All other
+    /// active cores are stopped afterwards.
+    DebugHit { core: usize },
+}
+
+fn read_u32(core: &Core, address: u64) -> anyhow::Result<u32> {
+    Ok(core
+        .read_bytes(address, 4)?
+        .as_slice()
+        .read_u32::<byteorder::LE>()?)
+}
+
+/// Forwards the data of an up channel to its sink.
+struct UpChannelStream {
+    reader: UpChannelReader,
+    /// Set if the output is tagged by core.
+    core: Option<usize>,
+    sink: Box<dyn Write>,
+}
+
+/// Forwards the available data of all streams, returns the number of bytes read.
+fn read_channels(core: &mut Core, streams: &mut [UpChannelStream]) -> anyhow::Result<usize> {
+    let mut total = 0;
+    for stream in streams.iter_mut() {
+        let mut data = Vec::new();
+        stream
+            .reader
+            .poll(core, &mut data)
+            .with_context(|| format!("Cannot read {}", stream.reader.channel().display_name()))?;
+        if data.is_empty() {
+            continue;
+        }
+
+        log::trace!("Read {} bytes from the device", data.len());
+        stream.sink.write_all(&data)?;
+        stream.sink.flush()?;
+        total += data.len();
+    }
+
+    Ok(total)
+}
+
+/// Prints the transfer statistics of the session.
+///
+/// A buffer that was full points to the host not keeping up with the
+/// application, while data missing without that was lost by the application.
+/// Channels that skip messages drop them without the buffer being full, so
+/// their loss is reported as unknown.
+fn print_summary<'a>(
+    statistics: &PollStatistics,
+    streams: impl Iterator<Item = &'a UpChannelStream>,
+    output: OutputFormat,
+) {
+    if output == OutputFormat::Json {
+        Event::RttSummary {
+            bytes: statistics.bytes,
+            seconds: statistics.elapsed().as_secs_f64(),
+            polls: statistics.polls,
+            average_round_trip_ms: statistics.average_round_trip().as_secs_f64() * 1000.0,
+            max_round_trip_ms: statistics.max_round_trip.as_secs_f64() * 1000.0,
+            channels: streams
+                .map(|stream| ChannelSummaryEvent {
+                    source: LogSource {
+                        core: stream.core,
+                        channel: stream.reader.channel().index,
+                    },
+                    name: stream.reader.channel().name.clone(),
+                    bytes: stream.reader.statistics().bytes,
+                    overflows: stream.reader.statistics().data_loss(),
+                    stalls: stream.reader.statistics().stalls,
+                })
+                .collect(),
+        }
+        .emit();
+        return;
+    }
+
+    println!(
+        "RTT transferred {} bytes in {:.1}s ({}/s) with {} polls, round trip {:.2}ms on average \
+        and {:.2}ms at most",
+        statistics.bytes,
+        statistics.elapsed().as_secs_f64(),
+        format_bytes(statistics.throughput()),
+        statistics.polls,
+        statistics.average_round_trip().as_secs_f64() * 1000.0,
+        statistics.max_round_trip.as_secs_f64() * 1000.0
+    );
+
+    for stream in streams {
+        let channel = stream.reader.channel();
+        let channel_statistics = stream.reader.statistics();
+        let core = match stream.core {
+            Some(core) => format!("core {core} "),
+            None => String::new(),
+        };
+
+        let mut notes = Vec::new();
+        match channel_statistics.data_loss() {
+            None => notes.push(format!(
+                "dropped messages cannot be detected in skip mode, the buffer was filled up to \
+                {} of {} bytes",
+                channel_statistics.max_fill, channel.size
+            )),
+            Some(0) => {}
+            Some(overflows) => notes.push(format!(
+                "the buffer was full {overflows} times, the host did not keep up"
+            )),
+        }
+        if channel_statistics.stalls > 0 {
+            notes.push(format!(
+                "the application waited {} times for the host",
+                channel_statistics.stalls
+            ));
+        }
+
+        let mut line = format!(
+            "  {core}{}: {} bytes",
+            channel.display_name(),
+            channel_statistics.bytes
+        );
+        for note in notes {
+            line.push_str(", ");
+            line.push_str(&note);
+        }
+        println!("{line}");
+    }
+}
+
+/// Formats a number of bytes with a binary unit.
+fn format_bytes(bytes: f64) -> String {
+    if bytes < 1024.0 {
+        format!("{bytes:.0} B")
+    } else if bytes < 1024.0 * 1024.0 {
+        format!("{:.1} KiB", bytes / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes / (1024.0 * 1024.0))
+    }
+}
+
+/// Forwards the data of an input to a down channel.
+struct DownChannelWriter {
+    channel: RttChannel,
+    /// Set to [None] once the input was closed.
+    input: Option<ChannelInput>,
+    /// Messages that are not yet written to the device.
+    pending: VecDeque<Vec<u8>>,
+}
+
+/// Writes the pending input of all writers, returns the number of bytes written.
+fn write_channels(core: &mut Core, writers: &mut [DownChannelWriter]) -> anyhow::Result<usize> {
+    let mut total = 0;
+    for writer in writers.iter_mut() {
+        total += write_to_core(core, writer)
+            .with_context(|| format!("Cannot write {}", writer.channel.display_name()))?;
+    }
+
+    Ok(total)
+}
+
+fn write_to_core(core: &mut Core, writer: &mut DownChannelWriter) -> anyhow::Result<usize> {
+    if let Some(input) = writer.input.as_ref() {
+        loop {
+            match input.receiver.try_recv() {
+                Ok(message) => writer.pending.push_back(message),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    log::debug!("Input of {} closed", writer.channel.display_name());
+                    writer.input = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    if writer.pending.is_empty() {
+        return Ok(0);
+    }
+
+    let ring_buffer = &writer.channel;
+    let device_read_index = read_u32(core, ring_buffer.read_offset_addr())
+        .with_context(|| "Error while obtaining the device read index")?;
+    let host_write_index = read_u32(core, ring_buffer.write_offset_addr())
+        .with_context(|| "Error while obtaining the host write index")?;
+    if device_read_index >= ring_buffer.size || host_write_index >= ring_buffer.size {
+        bail!(
+            "The RTT indices on the device (read {:#X}, write {:#X}) exceed the given size of {:#X}",
+            device_read_index,
+            host_write_index,
+            ring_buffer.size
+        );
+    }
+
+    // One byte always stays free to distinguish a full from an empty buffer
+    let mut free = if device_read_index > host_write_index {
+        device_read_index - host_write_index - 1
+    } else {
+        ring_buffer.size - (host_write_index - device_read_index) - 1
+    } as usize;
+
+    // The application may change the mode at runtime
+    let mode = ChannelMode::from_flags(read_u32(core, ring_buffer.flags_addr())?);
+
+    let mut data = Vec::new();
+    while let Some(message) = writer.pending.front_mut() {
+        if message.len() <= free {
+            free -= message.len();
+            data.append(message);
+            writer.pending.pop_front();
+            continue;
+        }
+
+        match mode {
+            ChannelMode::NoBlockSkip => {
+                log::warn!(
+                    "Dropped {} bytes of input, {} is full",
+                    message.len(),
+                    ring_buffer.display_name()
+                );
+                writer.pending.pop_front();
+            }
+            ChannelMode::NoBlockTrim => {
+                // Only the message that is written now is trimmed, the following
+                // ones are written once the application made space
+                let trimmed = message.split_off(free);
+                log::warn!(
+                    "Trimmed {} bytes of input {:?}, {} is full",
+                    trimmed.len(),
+                    String::from_utf8_lossy(&trimmed),
+                    ring_buffer.display_name()
+                );
+                data.append(message);
+                writer.pending.pop_front();
+                break;
+            }
+            ChannelMode::BlockIfFull => {
+                // The rest is written once the application made space
+                data.extend(message.drain(..free));
+                break;
+            }
+        }
+    }
+
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    // The write may wrap, so we might need to do two writes
+    let chunk_at_end = std::cmp::min(data.len(), (ring_buffer.size - host_write_index) as usize);
+    let chunk_at_start = data.split_off(chunk_at_end);
+    let length = data.len() + chunk_at_start.len();
+    core.write((ring_buffer.buffer_address + host_write_index) as u64, data)?;
+    if !chunk_at_start.is_empty() {
+        core.write(ring_buffer.buffer_address as u64, chunk_at_start)?;
+    }
+
+    let host_write_index = (host_write_index + length as u32) % ring_buffer.size;
+    core.write(
+        ring_buffer.write_offset_addr(),
+        u32::to_le_bytes(host_write_index).into(),
+    )?;
+    log::trace!("Wrote {length} bytes to the device");
+
+    Ok(length)
+}
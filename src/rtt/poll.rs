@@ -0,0 +1,173 @@
+//! Controls how often the RTT buffers are polled, see [AdaptivePoller].
+use std::time::{Duration, Instant};
+
+/// Timing of the RTT polling loop.
+#[derive(Debug, Clone, Copy)]
+pub struct PollConfig {
+    /// Interval between polls while the application writes data.
+    pub min_interval: Duration,
+    /// Upper bound the interval grows to while the application is idle.
+    pub max_interval: Duration,
+    /// Time to wait for remaining data after the device halted.
+    pub drain_time: Duration,
+}
+
+/// Smallest interval used once the application became idle, so the interval
+/// can grow even if the minimum is zero.
+const FIRST_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Adapts the polling interval to the amount of data the application writes.
+///
+/// The interval is reset to the minimum whenever data was read and doubles
+/// with every poll that did not return any data, up to the maximum.
+pub struct AdaptivePoller {
+    config: PollConfig,
+    interval: Duration,
+    statistics: PollStatistics,
+}
+
+impl AdaptivePoller {
+    pub fn new(config: PollConfig) -> Self {
+        AdaptivePoller {
+            config,
+            interval: config.min_interval,
+            statistics: PollStatistics::new(),
+        }
+    }
+
+    pub fn drain_time(&self) -> Duration {
+        self.config.drain_time
+    }
+
+    /// Returns the start time of a poll, pass it to [AdaptivePoller::finish]
+    /// once the poll is done.
+    pub fn start(&self) -> Instant {
+        Instant::now()
+    }
+
+    /// Records a finished poll and waits for the next one.
+    pub fn finish(&mut self, started: Instant, bytes: usize) {
+        self.statistics.record(started.elapsed(), bytes);
+
+        self.interval = if bytes > 0 {
+            self.config.min_interval
+        } else {
+            std::cmp::max(self.interval * 2, FIRST_BACKOFF)
+                .clamp(self.config.min_interval, self.config.max_interval)
+        };
+
+        if !self.interval.is_zero() {
+            std::thread::sleep(self.interval);
+        }
+    }
+
+    pub fn statistics(&self) -> &PollStatistics {
+        &self.statistics
+    }
+}
+
+/// Timing of all polls of a session.
+#[derive(Debug, Clone)]
+pub struct PollStatistics {
+    started: Instant,
+    /// Number of polls.
+    pub polls: u64,
+    /// Number of bytes transferred on all channels.
+    pub bytes: u64,
+    /// Sum of the round trip time of all polls.
+    pub total_round_trip: Duration,
+    /// Longest round trip time of a single poll.
+    pub max_round_trip: Duration,
+}
+
+impl PollStatistics {
+    fn new() -> Self {
+        PollStatistics {
+            started: Instant::now(),
+            polls: 0,
+            bytes: 0,
+            total_round_trip: Duration::ZERO,
+            max_round_trip: Duration::ZERO,
+        }
+    }
+
+    fn record(&mut self, round_trip: Duration, bytes: usize) {
+        self.polls += 1;
+        self.bytes += bytes as u64;
+        self.total_round_trip += round_trip;
+        self.max_round_trip = std::cmp::max(self.max_round_trip, round_trip);
+    }
+
+    /// Returns the time since the session started.
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn average_round_trip(&self) -> Duration {
+        if self.polls == 0 {
+            return Duration::ZERO;
+        }
+
+        Duration::from_secs_f64(self.total_round_trip.as_secs_f64() / self.polls as f64)
+    }
+
+    /// Returns the average throughput in bytes per second.
+    pub fn throughput(&self) -> f64 {
+        let elapsed = self.elapsed().as_secs_f64();
+        if elapsed == 0.0 {
+            return 0.0;
+        }
+
+        self.bytes as f64 / elapsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poller(min_interval: u64, max_interval: u64) -> AdaptivePoller {
+        AdaptivePoller::new(PollConfig {
+            min_interval: Duration::from_millis(min_interval),
+            max_interval: Duration::from_millis(max_interval),
+            drain_time: Duration::ZERO,
+        })
+    }
+
+    fn intervals(poller: &mut AdaptivePoller, bytes: &[usize]) -> Vec<u64> {
+        bytes
+            .iter()
+            .map(|bytes| {
+                poller.finish(poller.start(), *bytes);
+                poller.interval.as_millis() as u64
+            })
+            .collect()
+    }
+
+    #[test]
+    fn backs_off_while_idle() {
+        let mut poller = poller(0, 4);
+        assert_eq!(intervals(&mut poller, &[0, 0, 0, 0, 0]), [1, 2, 4, 4, 4]);
+    }
+
+    #[test]
+    fn resets_interval_on_data() {
+        let mut poller = poller(1, 8);
+        assert_eq!(intervals(&mut poller, &[0, 0, 10, 0]), [2, 4, 1, 2]);
+    }
+
+    #[test]
+    fn records_polls() {
+        let mut statistics = PollStatistics::new();
+        assert_eq!(statistics.average_round_trip(), Duration::ZERO);
+
+        statistics.record(Duration::from_millis(2), 100);
+        statistics.record(Duration::from_millis(4), 0);
+        statistics.record(Duration::from_millis(6), 28);
+
+        assert_eq!(statistics.polls, 3);
+        assert_eq!(statistics.bytes, 128);
+        assert_eq!(statistics.max_round_trip, Duration::from_millis(6));
+        assert_eq!(statistics.average_round_trip(), Duration::from_millis(4));
+    }
+}
@@ -0,0 +1,449 @@
+//! Reads the data of RTT up channels, see [UpChannelReader].
+//!
+//! The reader only accesses the device through [RttMemory], so it does not
+//! depend on a debugger connection.
+use anyhow::Context;
+use byteorder::ReadBytesExt;
+use rust_mcd::core::Core;
+
+use super::control_block::{ChannelMode, RttChannel};
+
+/// Access to the device memory as required by [UpChannelReader].
+pub trait RttMemory {
+    /// Returns the maximum number of bytes transferred by a single region.
+    fn max_transfer_size(&self) -> usize;
+
+    /// Reads the given regions, given by address and length, ideally within a
+    /// single transaction.
+    fn read_regions(&mut self, regions: &[(u64, usize)]) -> anyhow::Result<Vec<Vec<u8>>>;
+
+    /// Writes a little endian word.
+    fn write_u32(&mut self, address: u64, value: u32) -> anyhow::Result<()>;
+}
+
+impl RttMemory for Core<'_> {
+    fn max_transfer_size(&self) -> usize {
+        self.query_payload_size() as usize
+    }
+
+    fn read_regions(&mut self, regions: &[(u64, usize)]) -> anyhow::Result<Vec<Vec<u8>>> {
+        Core::read_regions(self, regions)
+    }
+
+    fn write_u32(&mut self, address: u64, value: u32) -> anyhow::Result<()> {
+        self.write(address, u32::to_le_bytes(value).into())
+    }
+}
+
+/// Counters describing the transfer of a single up channel.
+///
+/// What a full buffer means depends on the [ChannelMode] of the channel, so
+/// it is counted per mode.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReaderStatistics {
+    /// Number of bytes read from the device.
+    pub bytes: u64,
+    /// Number of times the buffer was observed full while the channel truncates
+    /// messages, data might have been dropped by the application each time.
+    pub overflows: u64,
+    /// Number of times the buffer was observed full while the channel blocks,
+    /// the application waited for the host each time but did not lose data.
+    pub stalls: u64,
+    /// Set if the channel skipped messages at some point. The application then
+    /// drops a message that does not fit even if the buffer is not full, and
+    /// the offsets do not tell a dropped message from one that was never
+    /// written, so data loss cannot be detected.
+    pub skip_mode: bool,
+    /// Highest number of bytes that were waiting in the buffer.
+    pub max_fill: u32,
+    /// Number of times the read offset on the device did not match the one of
+    /// the host.
+    pub resyncs: u64,
+}
+
+impl ReaderStatistics {
+    /// Returns the number of times the application potentially lost data, or
+    /// [None] if the loss cannot be detected, see [ReaderStatistics::skip_mode].
+    pub fn data_loss(&self) -> Option<u64> {
+        if self.skip_mode {
+            None
+        } else {
+            Some(self.overflows)
+        }
+    }
+}
+
+/// The offsets of a channel on the device are outside of its buffer, e.g.
+/// because the application overwrote the control block.
+#[derive(Debug, PartialEq)]
+pub struct InvalidOffsets {
+    pub write: u32,
+    pub read: u32,
+    pub size: u32,
+}
+
+impl std::fmt::Display for InvalidOffsets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The RTT indices on the device (write {:#X}, read {:#X}) exceed the buffer size of {:#X}",
+            self.write, self.read, self.size
+        )
+    }
+}
+
+impl std::error::Error for InvalidOffsets {}
+
+/// Reads the data of an up channel.
+///
+/// Each [UpChannelReader::poll] reads the offsets of the channel and validates
+/// them, reads the available data and then releases the read data on the
+/// device by advancing the read offset.
+///
+/// The application never overwrites data that was not read yet. Depending on
+/// the [ChannelMode] it drops or truncates messages or blocks instead if the
+/// buffer is full. The reader cannot observe dropped messages directly, so a
+/// full buffer is reported as potential data loss, see [ReaderStatistics].
+pub struct UpChannelReader {
+    channel: RttChannel,
+    read_index: u32,
+    statistics: ReaderStatistics,
+}
+
+impl UpChannelReader {
+    /// Creates a reader that continues at the given read index.
+    pub fn new(channel: RttChannel, read_index: u32) -> Self {
+        UpChannelReader {
+            channel,
+            read_index,
+            statistics: ReaderStatistics::default(),
+        }
+    }
+
+    pub fn channel(&self) -> &RttChannel {
+        &self.channel
+    }
+
+    pub fn statistics(&self) -> ReaderStatistics {
+        self.statistics
+    }
+
+    /// Appends the data available on the device to the buffer and returns the
+    /// number of bytes read.
+    ///
+    /// At most [RttMemory::max_transfer_size] bytes are read per call, the rest
+    /// is left for the next call.
+    pub fn poll(
+        &mut self,
+        memory: &mut impl RttMemory,
+        buffer: &mut Vec<u8>,
+    ) -> anyhow::Result<usize> {
+        if !self.channel.is_configured() {
+            return Ok(0);
+        }
+
+        let size = self.channel.size;
+
+        // WrOff, RdOff and Flags are adjacent in the descriptor
+        let offsets = memory.read_regions(&[(self.channel.write_offset_addr(), 12)])?;
+        let mut offsets = offsets
+            .first()
+            .context("No data returned for the channel offsets")?
+            .as_slice();
+        let device_write_index = offsets.read_u32::<byteorder::LE>()?;
+        let device_read_index = offsets.read_u32::<byteorder::LE>()?;
+        let flags = offsets.read_u32::<byteorder::LE>()?;
+
+        if device_write_index >= size || device_read_index >= size {
+            return Err(InvalidOffsets {
+                write: device_write_index,
+                read: device_read_index,
+                size,
+            }
+            .into());
+        }
+
+        if device_read_index != self.read_index {
+            // Only the host writes the read offset, so either the application
+            // initialized the channel again or another host reads it as well
+            log::warn!(
+                "Read offset of {} changed on the device from {:#X} to {:#X}, continuing there",
+                self.channel.display_name(),
+                self.read_index,
+                device_read_index
+            );
+            self.read_index = device_read_index;
+            self.statistics.resyncs += 1;
+        }
+
+        let available = if device_write_index >= self.read_index {
+            device_write_index - self.read_index
+        } else {
+            size - self.read_index + device_write_index
+        };
+
+        self.statistics.max_fill = std::cmp::max(self.statistics.max_fill, available);
+
+        // One byte always stays free to distinguish a full from an empty buffer
+        let full = available == size - 1;
+        let mode = ChannelMode::from_flags(flags);
+        if mode == ChannelMode::NoBlockSkip {
+            self.statistics.skip_mode = true;
+        }
+        // Every poll frees space, so a full buffer found by the next poll was
+        // filled up again by the application
+        if full {
+            match mode {
+                ChannelMode::NoBlockSkip => {
+                    self.statistics.overflows += 1;
+                    log::warn!(
+                        "{} is full, the application drops messages until the host catches up",
+                        self.channel.display_name()
+                    )
+                }
+                ChannelMode::NoBlockTrim => {
+                    self.statistics.overflows += 1;
+                    log::warn!(
+                        "{} is full, the application truncates messages until the host catches up",
+                        self.channel.display_name()
+                    )
+                }
+                ChannelMode::BlockIfFull => {
+                    self.statistics.stalls += 1;
+                    log::debug!(
+                        "{} is full, the application blocks until the host catches up",
+                        self.channel.display_name()
+                    )
+                }
+            }
+        }
+
+        if available == 0 {
+            return Ok(0);
+        }
+
+        let length = std::cmp::min(available as usize, memory.max_transfer_size());
+        let chunk_at_end = std::cmp::min(length, (size - self.read_index) as usize);
+        let start_address = (self.channel.buffer_address + self.read_index) as u64;
+
+        // The data may wrap, the two parts are read at once
+        let chunks = if chunk_at_end < length {
+            memory.read_regions(&[
+                (start_address, chunk_at_end),
+                (self.channel.buffer_address as u64, length - chunk_at_end),
+            ])?
+        } else {
+            memory.read_regions(&[(start_address, length)])?
+        };
+        for chunk in chunks {
+            buffer.extend_from_slice(&chunk);
+        }
+
+        self.read_index = (self.read_index + length as u32) % size;
+        memory.write_u32(self.channel.read_offset_addr(), self.read_index)?;
+        self.statistics.bytes += length as u64;
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use super::*;
+
+    const DESCRIPTOR: u64 = 0x7000_0000;
+    const BUFFER: u32 = 0x7000_0100;
+    const SIZE: u32 = 16;
+
+    /// Device memory holding a single channel descriptor and its buffer.
+    struct MockMemory {
+        memory: Vec<u8>,
+        max_transfer_size: usize,
+        /// The regions of every [RttMemory::read_regions] call.
+        reads: Vec<Vec<(u64, usize)>>,
+    }
+
+    impl MockMemory {
+        fn new(write: u32, read: u32, flags: u32) -> Self {
+            let mut memory = MockMemory {
+                memory: vec![0; (BUFFER as u64 - DESCRIPTOR) as usize + SIZE as usize],
+                max_transfer_size: 1024,
+                reads: Vec::new(),
+            };
+            // The buffer holds its own offset, so the order of the data is visible
+            for index in 0..SIZE {
+                memory.memory[(BUFFER as u64 - DESCRIPTOR) as usize + index as usize] = index as u8;
+            }
+            memory.set_offsets(write, read);
+            memory.write_u32(DESCRIPTOR + 20, flags).unwrap();
+            memory
+        }
+
+        fn set_offsets(&mut self, write: u32, read: u32) {
+            self.write_u32(DESCRIPTOR + 12, write).unwrap();
+            self.write_u32(DESCRIPTOR + 16, read).unwrap();
+        }
+
+        fn read_offset(&self) -> u32 {
+            let offset = 16;
+            u32::from_le_bytes(self.memory[offset..offset + 4].try_into().unwrap())
+        }
+
+        fn range(&self, address: u64, length: usize) -> anyhow::Result<std::ops::Range<usize>> {
+            let start = address
+                .checked_sub(DESCRIPTOR)
+                .context("Address below the mocked memory")? as usize;
+            if start + length > self.memory.len() {
+                bail!("Address above the mocked memory");
+            }
+            Ok(start..start + length)
+        }
+    }
+
+    impl RttMemory for MockMemory {
+        fn max_transfer_size(&self) -> usize {
+            self.max_transfer_size
+        }
+
+        fn read_regions(&mut self, regions: &[(u64, usize)]) -> anyhow::Result<Vec<Vec<u8>>> {
+            self.reads.push(regions.to_vec());
+            regions
+                .iter()
+                .map(|(address, length)| Ok(self.memory[self.range(*address, *length)?].to_vec()))
+                .collect()
+        }
+
+        fn write_u32(&mut self, address: u64, value: u32) -> anyhow::Result<()> {
+            let range = self.range(address, 4)?;
+            self.memory[range].copy_from_slice(&value.to_le_bytes());
+            Ok(())
+        }
+    }
+
+    fn reader(read: u32) -> UpChannelReader {
+        UpChannelReader::new(RttChannel::up(DESCRIPTOR, BUFFER, SIZE), read)
+    }
+
+    #[test]
+    fn reads_wrapped_data_in_one_transaction() {
+        let mut memory = MockMemory::new(4, 12, 0b10);
+        let mut reader = reader(12);
+
+        let mut buffer = Vec::new();
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 8);
+        assert_eq!(buffer, [12, 13, 14, 15, 0, 1, 2, 3]);
+        assert_eq!(memory.read_offset(), 4);
+
+        // One read for the offsets, one for both parts of the data
+        assert_eq!(memory.reads.len(), 2);
+        assert_eq!(
+            memory.reads[1],
+            [(BUFFER as u64 + 12, 4), (BUFFER as u64, 4)]
+        );
+    }
+
+    #[test]
+    fn limits_reads_to_transfer_size() {
+        let mut memory = MockMemory::new(10, 0, 0b10);
+        memory.max_transfer_size = 4;
+        let mut reader = reader(0);
+
+        let mut buffer = Vec::new();
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 4);
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 4);
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 2);
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 0);
+        assert_eq!(buffer, (0..10).collect::<Vec<u8>>());
+        assert_eq!(reader.statistics().bytes, 10);
+    }
+
+    #[test]
+    fn rejects_out_of_range_offsets() {
+        for (write, read) in [(SIZE, 0), (0, SIZE), (u32::MAX, 3)] {
+            let mut memory = MockMemory::new(write, read, 0b10);
+            let mut reader = reader(read);
+
+            let error = reader.poll(&mut memory, &mut Vec::new()).unwrap_err();
+            assert_eq!(
+                error.downcast_ref::<InvalidOffsets>(),
+                Some(&InvalidOffsets {
+                    write,
+                    read,
+                    size: SIZE
+                })
+            );
+            // Nothing was released on the device
+            assert_eq!(memory.read_offset(), read);
+        }
+    }
+
+    #[test]
+    fn follows_read_offset_of_device() {
+        let mut memory = MockMemory::new(8, 6, 0b10);
+        let mut reader = reader(2);
+
+        let mut buffer = Vec::new();
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 2);
+        assert_eq!(buffer, [6, 7]);
+        assert_eq!(reader.statistics().resyncs, 1);
+    }
+
+    /// Polls a buffer that the application filled up twice in the given mode.
+    fn poll_full_buffer(flags: u32) -> ReaderStatistics {
+        let mut memory = MockMemory::new(SIZE - 1, 0, flags);
+        let mut reader = reader(0);
+
+        let mut buffer = Vec::new();
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 15);
+        // Not full, nothing is reported
+        memory.set_offsets(4, SIZE - 1);
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 5);
+        // Full again
+        memory.set_offsets(3, 4);
+        assert_eq!(reader.poll(&mut memory, &mut buffer).unwrap(), 15);
+
+        reader.statistics()
+    }
+
+    #[test]
+    fn reports_data_loss_when_trimming() {
+        let statistics = poll_full_buffer(0b01);
+        assert_eq!(statistics.overflows, 2);
+        assert_eq!(statistics.stalls, 0);
+        assert_eq!(statistics.data_loss(), Some(2));
+        assert_eq!(statistics.max_fill, SIZE - 1);
+    }
+
+    #[test]
+    fn reports_no_data_loss_when_blocking() {
+        let statistics = poll_full_buffer(0b10);
+        assert_eq!(statistics.overflows, 0);
+        assert_eq!(statistics.stalls, 2);
+        assert_eq!(statistics.data_loss(), Some(0));
+    }
+
+    #[test]
+    fn reports_unknown_data_loss_when_skipping() {
+        let statistics = poll_full_buffer(0b00);
+        assert!(statistics.skip_mode);
+        assert_eq!(statistics.data_loss(), None);
+
+        // Messages may be dropped while the buffer is not full
+        let mut memory = MockMemory::new(4, 0, 0b00);
+        let mut reader = reader(0);
+        reader.poll(&mut memory, &mut Vec::new()).unwrap();
+        assert_eq!(reader.statistics().overflows, 0);
+        assert_eq!(reader.statistics().data_loss(), None);
+        assert_eq!(reader.statistics().max_fill, 4);
+    }
+
+    #[test]
+    fn reports_no_data_loss_without_full_buffer() {
+        let mut memory = MockMemory::new(4, 0, 0b01);
+        let mut reader = reader(0);
+        reader.poll(&mut memory, &mut Vec::new()).unwrap();
+        assert_eq!(reader.statistics().data_loss(), Some(0));
+    }
+}
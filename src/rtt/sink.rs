@@ -0,0 +1,484 @@
+//! Destinations for the data of RTT up channels, see [ChannelRoute].
+use std::{
+    fs::File,
+    io::{ErrorKind, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+use crate::defmt::{DefmtDecoder, DefmtOptions};
+use crate::output::{Event, LogSource, OutputFormat, RecentLogs};
+
+/// Destination for the data of an RTT up channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkKind {
+    /// Decodes the data as defmt frames, see [DefmtDecoder].
+    Defmt,
+    /// Writes the data to the terminal as is.
+    Terminal,
+    /// Decodes the data as UTF-8 text and prints it line by line to the
+    /// terminal, see [TextSink].
+    Text,
+    /// Writes the data to a file, the file is truncated first.
+    File(PathBuf),
+    /// Serves the data to a client connecting to the given address, see [TcpSink].
+    Tcp(SocketAddr),
+}
+
+impl FromStr for SinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "defmt" => Ok(Self::Defmt),
+            _ if s == "terminal" || s == "raw" => Ok(Self::Terminal),
+            _ if s == "text" => Ok(Self::Text),
+            Some(("file", path)) if !path.is_empty() => Ok(Self::File(PathBuf::from(path))),
+            Some(("tcp", address)) => {
+                Ok(Self::Tcp(address.parse().with_context(|| {
+                    format!("Invalid socket address {address:?}")
+                })?))
+            }
+            _ => bail!(
+                "Unknown sink {s:?}, expected one of \"defmt\", \"terminal\", \"text\", \
+                \"file:<path>\" or \"tcp:<address>:<port>\""
+            ),
+        }
+    }
+}
+
+/// Format of the data on the default RTT channel.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum RttFormat {
+    /// Print the data to the terminal as is.
+    Raw,
+    /// Print the data as lines of text with timestamps.
+    Text,
+    /// Decode the data as defmt frames.
+    #[default]
+    Defmt,
+}
+
+impl RttFormat {
+    /// Returns the sink that handles data in this format.
+    pub fn sink(self) -> SinkKind {
+        match self {
+            RttFormat::Raw => SinkKind::Terminal,
+            RttFormat::Text => SinkKind::Text,
+            RttFormat::Defmt => SinkKind::Defmt,
+        }
+    }
+}
+
+/// Routes an RTT up channel to a sink.
+///
+/// Parsed from `<channel>=<sink>`, e.g. `1=file:log.txt`, see [SinkKind] for
+/// the available sinks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelRoute {
+    pub channel: usize,
+    pub sink: SinkKind,
+}
+
+impl FromStr for ChannelRoute {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, sink) = s
+            .split_once('=')
+            .context("Expected a channel route in the form <channel>=<sink>")?;
+
+        Ok(ChannelRoute {
+            channel: channel
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid channel index {channel:?}"))?,
+            sink: sink.trim().parse()?,
+        })
+    }
+}
+
+impl ChannelRoute {
+    /// Returns the route used when the user did not configure any, it handles
+    /// up channel 0 in the given format.
+    pub fn default_routes(format: RttFormat) -> Vec<ChannelRoute> {
+        vec![ChannelRoute {
+            channel: 0,
+            sink: format.sink(),
+        }]
+    }
+
+    /// Opens the sink of this route.
+    ///
+    /// The elf file is required to decode defmt data, decoded frames are printed
+    /// as configured by the options. Text sinks open the
+    /// terminal, the data is formatted by a [TextSink] once the name of the
+    /// channel is known.
+    ///
+    /// If a core index is given, the route serves the control block of that
+    /// core. Decoded defmt and text output is then tagged with the core index,
+    /// files get the suffix `.cpu<n>` and TCP sinks listen on the given port
+    /// plus the core index, so the control blocks of multiple cores can share
+    /// the same routes.
+    ///
+    /// With [OutputFormat::Json], decoded defmt frames, text lines and data
+    /// written to the terminal are emitted as events instead.
+    pub fn open(
+        &self,
+        elf_file: &Path,
+        core: Option<usize>,
+        format: OutputFormat,
+        defmt: &DefmtOptions,
+        recent_logs: &RecentLogs,
+    ) -> anyhow::Result<ChannelOutput> {
+        let source = LogSource {
+            core,
+            channel: self.channel,
+        };
+        let sink: Box<dyn Write> = match &self.sink {
+            SinkKind::Defmt => Box::new(DefmtDecoder::spawn(
+                elf_file,
+                source,
+                format,
+                defmt,
+                Some(recent_logs.clone()),
+            )?),
+            SinkKind::Terminal if format == OutputFormat::Json => {
+                Box::new(JsonDataSink::new(source))
+            }
+            SinkKind::Terminal | SinkKind::Text => Box::new(std::io::stdout()),
+            SinkKind::File(path) => {
+                let path = match core {
+                    Some(core) => core_file_path(path, core),
+                    None => path.clone(),
+                };
+                Box::new(
+                    File::create(&path)
+                        .with_context(|| format!("Cannot create file {}", path.display()))?,
+                )
+            }
+            SinkKind::Tcp(address) => {
+                let mut address = *address;
+                if let Some(core) = core {
+                    let port = u16::try_from(core)
+                        .ok()
+                        .and_then(|core| address.port().checked_add(core))
+                        .with_context(|| {
+                            format!(
+                                "The tcp port {} plus core index {core} exceeds the port range",
+                                address.port()
+                            )
+                        })?;
+                    address.set_port(port);
+                }
+                Box::new(TcpSink::bind(address)?)
+            }
+        };
+
+        Ok(ChannelOutput {
+            channel: self.channel,
+            core,
+            kind: self.sink.clone(),
+            format,
+            sink,
+            recent_logs: recent_logs.clone(),
+        })
+    }
+}
+
+/// Inserts the core index before the extension of the path, e.g. `log.txt`
+/// becomes `log.cpu1.txt`.
+fn core_file_path(path: &Path, core: usize) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_os_string();
+    file_name.push(format!(".cpu{core}"));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+
+    path.with_file_name(file_name)
+}
+
+/// An opened [ChannelRoute].
+pub struct ChannelOutput {
+    pub channel: usize,
+    /// The core whose control block is served, if output is tagged by core.
+    pub core: Option<usize>,
+    pub kind: SinkKind,
+    pub format: OutputFormat,
+    pub sink: Box<dyn Write>,
+    /// Records the lines printed by text sinks.
+    pub recent_logs: RecentLogs,
+}
+
+/// Emits the data written to it as data events.
+///
+/// Invalid UTF-8 sequences are replaced.
+pub struct JsonDataSink {
+    source: LogSource,
+}
+
+impl JsonDataSink {
+    pub fn new(source: LogSource) -> Self {
+        JsonDataSink { source }
+    }
+}
+
+impl Write for JsonDataSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Event::Data {
+            source: self.source,
+            data: &String::from_utf8_lossy(buf),
+        }
+        .emit();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
+
+/// Serves data to a single TCP client at a time.
+///
+/// Data that is written while no client is connected is dropped, so the
+/// application is never stalled by the host.
+///
+/// The client socket is non-blocking as well. Data the client does not accept
+/// immediately is buffered up to [TcpSink::PENDING_LIMIT] bytes, beyond that it
+/// is dropped and the number of dropped bytes is reported.
+pub struct TcpSink {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    /// Data accepted from the channel but not yet sent to the client.
+    pending: Vec<u8>,
+    /// Bytes dropped since the client last kept up.
+    dropped: u64,
+}
+
+impl TcpSink {
+    /// Maximum number of bytes buffered for a slow client.
+    pub const PENDING_LIMIT: usize = 64 * 1024;
+
+    /// Listens for clients on the given address.
+    pub fn bind(address: SocketAddr) -> anyhow::Result<Self> {
+        let listener =
+            TcpListener::bind(address).with_context(|| format!("Cannot listen on {address}"))?;
+        listener
+            .set_nonblocking(true)
+            .context("Cannot configure tcp listener")?;
+        log::info!("Serving rtt data on {address}");
+
+        Ok(TcpSink {
+            listener,
+            client: None,
+            pending: Vec::new(),
+            dropped: 0,
+        })
+    }
+
+    /// Returns the connected client, accepting a new one if none is connected.
+    fn client(&mut self) -> Option<&mut TcpStream> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((client, address)) => {
+                    log::info!("Client {address} connected");
+                    // Accepted sockets may not inherit the non-blocking mode of the listener
+                    if let Err(error) = client.set_nonblocking(true) {
+                        log::warn!("Cannot configure tcp client: {error}");
+                    } else {
+                        self.client = Some(client);
+                    }
+                }
+                Err(error) if error.kind() == ErrorKind::WouldBlock => {}
+                Err(error) => log::warn!("Cannot accept tcp client: {error}"),
+            }
+        }
+
+        self.client.as_mut()
+    }
+
+    /// Sends as much of the pending data as the client accepts without blocking.
+    fn send_pending(&mut self) {
+        let Some(client) = self.client.as_mut() else {
+            return;
+        };
+
+        let mut sent = 0;
+        while sent < self.pending.len() {
+            match client.write(&self.pending[sent..]) {
+                Ok(0) => {
+                    self.disconnect("connection closed".to_owned());
+                    return;
+                }
+                Ok(count) => sent += count,
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => {
+                    self.disconnect(error.to_string());
+                    return;
+                }
+            }
+        }
+        self.pending.drain(..sent);
+
+        if self.pending.is_empty() && self.dropped > 0 {
+            log::warn!(
+                "Dropped {} bytes because the tcp client did not keep up",
+                self.dropped
+            );
+            self.dropped = 0;
+        }
+    }
+
+    fn disconnect(&mut self, reason: String) {
+        log::info!("Client disconnected: {reason}");
+        if !self.pending.is_empty() || self.dropped > 0 {
+            log::warn!(
+                "Dropped {} bytes that were not sent to the tcp client",
+                self.pending.len() as u64 + self.dropped
+            );
+        }
+        self.client = None;
+        self.pending.clear();
+        self.dropped = 0;
+    }
+}
+
+impl Write for TcpSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.client().is_none() {
+            return Ok(buf.len());
+        }
+
+        // Make room for the new data before deciding what has to be dropped
+        self.send_pending();
+
+        let accepted = std::cmp::min(buf.len(), Self::PENDING_LIMIT - self.pending.len());
+        self.pending.extend_from_slice(&buf[..accepted]);
+        self.dropped += (buf.len() - accepted) as u64;
+        self.send_pending();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_pending();
+        Ok(())
+    }
+}
+
+/// Splits the data into lines of UTF-8 text and prefixes each line with the
+/// time elapsed on the host and a label.
+///
+/// Invalid UTF-8 sequences are replaced, a trailing incomplete line is only
+/// written when the sink is dropped. Lines are emitted as log events instead
+/// if the sink was created with [TextSink::json].
+pub struct TextSink {
+    label: String,
+    /// Set if lines are emitted as log events.
+    json: Option<LogSource>,
+    inner: Box<dyn Write>,
+    line: Vec<u8>,
+    start: Instant,
+    /// Replaces the elapsed time if set, e.g. when replaying a capture.
+    elapsed: Option<Duration>,
+    /// Records the written lines if set.
+    recent_logs: Option<(RecentLogs, LogSource)>,
+}
+
+impl TextSink {
+    pub fn new(label: String, inner: Box<dyn Write>) -> Self {
+        TextSink {
+            label,
+            json: None,
+            inner,
+            line: Vec::new(),
+            start: Instant::now(),
+            elapsed: None,
+            recent_logs: None,
+        }
+    }
+
+    /// Records every written line as a line of the given source.
+    pub fn record_to(mut self, recent_logs: RecentLogs, source: LogSource) -> Self {
+        self.recent_logs = Some((recent_logs, source));
+        self
+    }
+
+    /// Creates a sink that emits every line as a log event of the given source.
+    pub fn json(source: LogSource) -> Self {
+        let mut sink = TextSink::new(String::new(), Box::new(std::io::stdout()));
+        sink.json = Some(source);
+        sink
+    }
+
+    /// Sets the time used for the lines written from now on.
+    pub fn set_elapsed(&mut self, elapsed: Duration) {
+        self.elapsed = Some(elapsed);
+    }
+
+    fn write_line(&mut self) -> std::io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        let line = String::from_utf8_lossy(&line);
+        if let Some((recent_logs, source)) = &self.recent_logs {
+            recent_logs.record(*source, line.trim_end_matches(['\r', '\n']));
+        }
+        let elapsed = self.elapsed.unwrap_or_else(|| self.start.elapsed());
+        if let Some(source) = self.json {
+            Event::Log {
+                source,
+                timestamp: None,
+                host_time: Some(elapsed.as_secs_f64()),
+                level: None,
+                file: None,
+                line: None,
+                module: None,
+                message: line.trim_end_matches(['\r', '\n']),
+                untrusted: false,
+            }
+            .emit();
+            return Ok(());
+        }
+
+        writeln!(
+            self.inner,
+            "[{:>5}.{:06}] {}: {}",
+            elapsed.as_secs(),
+            elapsed.subsec_micros(),
+            self.label,
+            line.trim_end_matches(['\r', '\n'])
+        )
+    }
+}
+
+impl Write for TextSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            self.line.push(byte);
+            if byte == b'\n' {
+                self.write_line()?;
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Drop for TextSink {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            let _ = self.write_line();
+        }
+        let _ = self.inner.flush();
+    }
+}
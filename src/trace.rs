@@ -0,0 +1,415 @@
+//! Captures the program flow of a core with the on-chip trace unit (MCDS or
+//! miniMCDS) and decodes it into an [InstructionHistory].
+use std::path::Path;
+
+use colored::{Color, Colorize};
+use rust_mcd::core::Core;
+use rust_mcd::trace::{TraceCycle, TraceFormat, TraceFrame, TraceSource};
+
+use crate::backtrace::{Addr2LineInfo, Addr2LineRegistry};
+use crate::output::{Event, TraceEntryEvent};
+
+/// Selects the trace source of the core that records its program flow.
+fn program_trace<'a>(core: &'a Core<'a>) -> anyhow::Result<Option<TraceSource<'a>>> {
+    let sources = core.traces()?;
+    log::trace!("Available trace sources: {sources:#?}");
+    Ok(sources.into_iter().find(|source| source.is_program_trace()))
+}
+
+/// Clears and arms the program flow trace of the given core.
+///
+/// Returns false if the core does not provide a suitable trace source.
+pub fn start_program_trace(core: &Core) -> anyhow::Result<bool> {
+    let Some(source) = program_trace(core)? else {
+        return Ok(false);
+    };
+
+    let state = source.start()?;
+    log::debug!("Started trace {:?}: {state:?}", source.name());
+    Ok(true)
+}
+
+/// Reads the `depth` most recent frames of the program flow trace of the given core
+/// and decodes them.
+///
+/// Returns [None] if the core does not provide a suitable trace source.
+pub fn read_program_history(core: &Core, depth: u32) -> anyhow::Result<Option<InstructionHistory>> {
+    let Some(source) = program_trace(core)? else {
+        return Ok(None);
+    };
+
+    let state = source.stop()?;
+    log::debug!("Stopped trace {:?}: {state:?}", source.name());
+
+    let frames = source.read_latest_frames(depth)?;
+    log::trace!("Read {} trace frames", frames.len());
+
+    Ok(Some(InstructionHistory::decode(
+        source.format(),
+        &frames,
+        state.wraparound,
+    )))
+}
+
+/// The length of a block of sequentially executed code.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BlockLength {
+    Instructions(u64),
+    Bytes(u64),
+}
+
+impl BlockLength {
+    fn is_empty(&self) -> bool {
+        matches!(self, Self::Instructions(0) | Self::Bytes(0))
+    }
+}
+
+/// An entry in the [InstructionHistory].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HistoryEntry {
+    /// A block of code that was executed sequentially, starting at `start`.
+    Executed {
+        start: u32,
+        length: BlockLength,
+        /// Set if the block was left through a taken branch.
+        branch_taken: bool,
+    },
+    /// The trace unit lost frames at this point, e.g. due to bandwidth limitations.
+    Gap,
+}
+
+/// The history of executed code of a core, ordered from oldest to newest.
+#[derive(Debug, Default, PartialEq)]
+pub struct InstructionHistory {
+    pub entries: Vec<HistoryEntry>,
+    /// Set if older parts of the history were overwritten in the trace buffer.
+    pub truncated: bool,
+}
+
+impl InstructionHistory {
+    /// Decodes raw trace frames of the given format into an instruction history.
+    ///
+    /// Frames that do not describe executed code (data accesses, ownership changes
+    /// or control information) are skipped. Frames that cannot describe executed
+    /// code (empty blocks or addresses beyond 32 bit) are reported as a gap.
+    /// `wraparound` is the state of the trace buffer, i.e. older frames were
+    /// overwritten.
+    pub fn decode(format: TraceFormat, frames: &[TraceFrame], wraparound: bool) -> Self {
+        let mut entries = Vec::new();
+
+        for frame in frames {
+            if (frame.markers.gap || frame.markers.error)
+                && entries.last() != Some(&HistoryEntry::Gap)
+            {
+                entries.push(HistoryEntry::Gap);
+            }
+
+            let branch_taken = match (format, frame.cycle) {
+                (_, TraceCycle::Execute) => true,
+                (_, TraceCycle::NotExecute) => false,
+                (TraceFormat::CoreFetch, TraceCycle::Fetch) => false,
+                _ => continue,
+            };
+
+            let length = match format {
+                TraceFormat::CoreFlowByteCount => BlockLength::Bytes(frame.data),
+                TraceFormat::CoreFlowInstructionCount
+                | TraceFormat::CoreFlowInstructionPredicate => {
+                    BlockLength::Instructions(frame.data)
+                }
+                // Every frame represents a single instruction
+                TraceFormat::CoreExecute | TraceFormat::CoreFetch => BlockLength::Instructions(1),
+                TraceFormat::Other(_) => continue,
+            };
+
+            let start = u32::try_from(frame.address).ok();
+            let (Some(start), false) = (start, length.is_empty()) else {
+                log::debug!("Skipping invalid trace frame {frame:?}");
+                if entries.last() != Some(&HistoryEntry::Gap) {
+                    entries.push(HistoryEntry::Gap);
+                }
+                continue;
+            };
+
+            entries.push(HistoryEntry::Executed {
+                start,
+                length,
+                branch_taken,
+            });
+        }
+
+        InstructionHistory {
+            entries,
+            truncated: wraparound,
+        }
+    }
+
+    /// Resolves the addresses in this history to functions and source locations.
+    pub(crate) fn addr2line(&self, elf_file: &Path) -> anyhow::Result<InstructionHistoryInfo> {
+        let mut registry = Addr2LineRegistry::new(elf_file);
+
+        registry.load(self.entries.iter().filter_map(|entry| match entry {
+            HistoryEntry::Executed { start, .. } => Some(*start),
+            HistoryEntry::Gap => None,
+        }))?;
+
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                let info = match entry {
+                    HistoryEntry::Executed { start, .. } => {
+                        Some(registry.get_address_info(*start)?)
+                    }
+                    HistoryEntry::Gap => None,
+                };
+                anyhow::Ok((*entry, info))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(InstructionHistoryInfo {
+            entries,
+            truncated: self.truncated,
+        })
+    }
+}
+
+/// An [InstructionHistory] with symbol information attached.
+pub struct InstructionHistoryInfo {
+    entries: Vec<(HistoryEntry, Option<Addr2LineInfo>)>,
+    truncated: bool,
+}
+
+impl InstructionHistoryInfo {
+    /// Emits the history of the given core as a single event, gaps are
+    /// entries without address.
+    pub fn emit_json(&self, core: usize) {
+        Event::Trace {
+            core,
+            truncated: self.truncated,
+            entries: self
+                .entries
+                .iter()
+                .map(|(entry, info)| match entry {
+                    HistoryEntry::Executed { start, .. } => TraceEntryEvent {
+                        address: Some(*start),
+                        function: info.as_ref().map(|info| info.function.as_str()),
+                        location: info.as_ref().map(|info| info.module.as_str()),
+                    },
+                    HistoryEntry::Gap => TraceEntryEvent {
+                        address: None,
+                        function: None,
+                        location: None,
+                    },
+                })
+                .collect(),
+        }
+        .emit();
+    }
+
+    pub fn log_stdout(&self) {
+        if self.truncated {
+            println!("{}", "... (older history was overwritten)".dimmed());
+        }
+
+        for (entry, info) in self.entries.iter() {
+            let (
+                HistoryEntry::Executed {
+                    start,
+                    length,
+                    branch_taken,
+                },
+                Some(info),
+            ) = (entry, info)
+            else {
+                println!("{}", "... (trace gap, frames were lost)".yellow());
+                continue;
+            };
+
+            let length = match length {
+                BlockLength::Instructions(1) => "".to_owned(),
+                BlockLength::Instructions(count) => format!(" +{count} instr"),
+                BlockLength::Bytes(count) => format!(" +{count} bytes"),
+            };
+            let branch = if *branch_taken { " ->" } else { "" };
+
+            println!(
+                "{}{}{} {} {}",
+                format!("{start:#8X}").white(),
+                length.dimmed(),
+                branch,
+                info.function.bold().blue(),
+                format!("@ {}", info.module).color(Color::TrueColor {
+                    r: 100,
+                    g: 100,
+                    b: 100
+                })
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_mcd::trace::{TraceCycle, TraceFormat, TraceFrame, TraceMarkers};
+
+    use super::{BlockLength, HistoryEntry, InstructionHistory};
+
+    fn frame(cycle: TraceCycle, address: u64, data: u64) -> TraceFrame {
+        TraceFrame {
+            timestamp: 0,
+            markers: TraceMarkers::default(),
+            cycle,
+            address,
+            data,
+        }
+    }
+
+    fn marker(markers: TraceMarkers) -> TraceFrame {
+        TraceFrame {
+            markers,
+            ..frame(TraceCycle::None, 0, 0)
+        }
+    }
+
+    fn executed(start: u32, length: BlockLength, branch_taken: bool) -> HistoryEntry {
+        HistoryEntry::Executed {
+            start,
+            length,
+            branch_taken,
+        }
+    }
+
+    #[test]
+    fn decodes_branch_frames() {
+        let frames = [
+            // Sync frame when the trace started, no code executed yet
+            marker(TraceMarkers {
+                start: true,
+                run: true,
+                ..Default::default()
+            }),
+            frame(TraceCycle::Execute, 0x8000_0100, 4),
+            frame(TraceCycle::NotExecute, 0x8000_0200, 2),
+            frame(TraceCycle::Read, 0x7000_0000, 0xDEAD_BEEF),
+            frame(TraceCycle::Ownership, 0, 1),
+            frame(TraceCycle::Execute, 0x8000_0100, 4),
+        ];
+
+        let history =
+            InstructionHistory::decode(TraceFormat::CoreFlowInstructionCount, &frames, false);
+        assert_eq!(
+            history,
+            InstructionHistory {
+                entries: vec![
+                    executed(0x8000_0100, BlockLength::Instructions(4), true),
+                    executed(0x8000_0200, BlockLength::Instructions(2), false),
+                    executed(0x8000_0100, BlockLength::Instructions(4), true),
+                ],
+                truncated: false,
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_length_per_format() {
+        let frames = [frame(TraceCycle::Execute, 0x8000_0100, 12)];
+
+        let history = InstructionHistory::decode(TraceFormat::CoreFlowByteCount, &frames, false);
+        assert_eq!(
+            history.entries,
+            [executed(0x8000_0100, BlockLength::Bytes(12), true)]
+        );
+
+        let history = InstructionHistory::decode(TraceFormat::CoreExecute, &frames, false);
+        assert_eq!(
+            history.entries,
+            [executed(0x8000_0100, BlockLength::Instructions(1), true)]
+        );
+
+        let frames = [
+            frame(TraceCycle::Fetch, 0x8000_0100, 0),
+            frame(TraceCycle::Fetch, 0x8000_0104, 0),
+        ];
+        let history = InstructionHistory::decode(TraceFormat::CoreFetch, &frames, false);
+        assert_eq!(
+            history.entries,
+            [
+                executed(0x8000_0100, BlockLength::Instructions(1), false),
+                executed(0x8000_0104, BlockLength::Instructions(1), false),
+            ]
+        );
+
+        let history = InstructionHistory::decode(TraceFormat::Other(0x100), &frames, false);
+        assert_eq!(history.entries, []);
+    }
+
+    #[test]
+    fn reports_gaps_once() {
+        let gap = TraceMarkers {
+            gap: true,
+            ..Default::default()
+        };
+        let error = TraceMarkers {
+            error: true,
+            ..Default::default()
+        };
+        let frames = [
+            frame(TraceCycle::Execute, 0x8000_0100, 4),
+            marker(gap),
+            marker(error),
+            TraceFrame {
+                markers: gap,
+                ..frame(TraceCycle::Execute, 0x8000_0300, 3)
+            },
+        ];
+
+        let history =
+            InstructionHistory::decode(TraceFormat::CoreFlowInstructionCount, &frames, false);
+        assert_eq!(
+            history.entries,
+            [
+                executed(0x8000_0100, BlockLength::Instructions(4), true),
+                HistoryEntry::Gap,
+                executed(0x8000_0300, BlockLength::Instructions(3), true),
+            ]
+        );
+    }
+
+    #[test]
+    fn marks_wrapped_buffer_as_truncated() {
+        let frames = [frame(TraceCycle::NotExecute, 0x8000_0100, 4)];
+
+        let history =
+            InstructionHistory::decode(TraceFormat::CoreFlowInstructionCount, &frames, true);
+        assert!(history.truncated);
+        assert_eq!(
+            history.entries,
+            [executed(0x8000_0100, BlockLength::Instructions(4), false)]
+        );
+    }
+
+    #[test]
+    fn skips_garbage_frames() {
+        let frames = [
+            frame(TraceCycle::Execute, 0x8000_0100, 4),
+            // Truncated frame, the block length is missing
+            frame(TraceCycle::Execute, 0x8000_0200, 0),
+            // Garbage, neither a valid address nor a known cycle
+            frame(TraceCycle::Execute, 0xFFFF_FFFF_8000_0000, 2),
+            frame(TraceCycle::Unknown(0x7F), 0x8000_0300, 2),
+            frame(TraceCycle::NotExecute, 0x8000_0400, 1),
+        ];
+
+        let history =
+            InstructionHistory::decode(TraceFormat::CoreFlowInstructionCount, &frames, false);
+        assert_eq!(
+            history.entries,
+            [
+                executed(0x8000_0100, BlockLength::Instructions(4), true),
+                HistoryEntry::Gap,
+                executed(0x8000_0400, BlockLength::Instructions(1), false),
+            ]
+        );
+    }
+}